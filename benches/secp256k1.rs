@@ -0,0 +1,202 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use num_bigint_dig::{BigInt, BigUint, ModInverse, ToBigInt};
+use num_integer::Integer;
+use num_traits::{One, Zero};
+
+use ritcoin::libs::{
+    ecc::{
+        EccPoint, SECP256K1GENS, SECP256K1GENS_ORDER, SECP256K1GENS_X, SECP256K1GENS_Y,
+        SECP256K1_PRIME,
+    },
+    field_element::FieldElement,
+    finite_field::FiniteField,
+    jacobian::JacobianPoint,
+    key::Key,
+    scalar::Scalar,
+    signature::RecoverableSignature,
+};
+
+fn field_benches(c: &mut Criterion) {
+    let prime: BigInt = SECP256K1_PRIME.clone();
+    let x_big = FiniteField::new(SECP256K1GENS_X.to_bigint().unwrap(), prime.clone());
+    let y_big = FiniteField::new(SECP256K1GENS_Y.to_bigint().unwrap(), prime.clone());
+    let one_big = FiniteField::new(BigInt::one(), prime);
+    let x = FieldElement::from_biguint(&SECP256K1GENS_X);
+    let y = FieldElement::from_biguint(&SECP256K1GENS_Y);
+
+    let mut group = c.benchmark_group("field add");
+    group.bench_function("FiniteField", |b| {
+        b.iter(|| black_box(&x_big) + black_box(&y_big))
+    });
+    group.bench_function("FieldElement", |b| b.iter(|| black_box(x) + black_box(y)));
+    group.finish();
+
+    let mut group = c.benchmark_group("field mul");
+    group.bench_function("FiniteField", |b| {
+        b.iter(|| black_box(&x_big) * black_box(&y_big))
+    });
+    group.bench_function("FieldElement", |b| b.iter(|| black_box(x) * black_box(y)));
+    group.finish();
+
+    let mut group = c.benchmark_group("field square");
+    group.bench_function("FiniteField", |b| {
+        b.iter(|| black_box(&x_big) * black_box(&x_big))
+    });
+    group.bench_function("FieldElement", |b| b.iter(|| black_box(x).square()));
+    group.finish();
+
+    let mut group = c.benchmark_group("field invert");
+    group.bench_function("FiniteField", |b| {
+        b.iter(|| black_box(&one_big) / black_box(&x_big))
+    });
+    group.bench_function("FieldElement", |b| b.iter(|| black_box(x).invert()));
+    group.finish();
+}
+
+fn scalar_benches(c: &mut Criterion) {
+    let order: &BigUint = &SECP256K1GENS_ORDER;
+    let k_big = order - 12345_u32;
+    let r_big = order >> 3;
+    let k = Scalar::from_biguint(&k_big);
+    let r = Scalar::from_biguint(&r_big);
+
+    let mut group = c.benchmark_group("scalar add");
+    group.bench_function("BigUint", |b| {
+        b.iter(|| (black_box(&k_big) + black_box(&r_big)) % order)
+    });
+    group.bench_function("Scalar", |b| b.iter(|| black_box(k) + black_box(r)));
+    group.finish();
+
+    let mut group = c.benchmark_group("scalar mul");
+    group.bench_function("BigUint", |b| {
+        b.iter(|| (black_box(&k_big) * black_box(&r_big)) % order)
+    });
+    group.bench_function("Scalar", |b| b.iter(|| black_box(k) * black_box(r)));
+    group.finish();
+
+    let mut group = c.benchmark_group("scalar invert");
+    group.bench_function("BigUint", |b| {
+        b.iter(|| black_box(&k_big).mod_inverse(order))
+    });
+    group.bench_function("Scalar", |b| b.iter(|| black_box(k).invert()));
+    group.finish();
+
+    // the secret-dependent part of ECDSA signing, s = (z + r * e) / k
+    let z_big = order >> 1;
+    let e_big = order - 0xdead_beef_u32;
+    let z = Scalar::from_biguint(&z_big);
+    let e = Scalar::from_biguint(&e_big);
+    let mut group = c.benchmark_group("signature s");
+    group.bench_function("BigUint", |b| {
+        b.iter(|| {
+            let k_inv = black_box(&k_big).mod_inverse(order).unwrap();
+            ((black_box(&z_big) + black_box(&r_big) * black_box(&e_big))
+                * k_inv.to_biguint().unwrap())
+                % order
+        })
+    });
+    group.bench_function("Scalar", |b| {
+        b.iter(|| (black_box(z) + black_box(r) * black_box(e)) * black_box(k).invert())
+    });
+    group.finish();
+}
+
+fn point_benches(c: &mut Criterion) {
+    let k = &(*SECP256K1GENS_ORDER) - 12345_u32;
+    let g: &EccPoint = &SECP256K1GENS;
+    let scalar = Scalar::from_biguint(&k);
+    let g_jacobian = JacobianPoint::from_affine(g).unwrap();
+
+    let mut group = c.benchmark_group("scalar * generator");
+    group.bench_function("BigUint * EccPoint", |b| {
+        b.iter(|| black_box(&k) * black_box(g))
+    });
+    group.bench_function("Jacobian vartime", |b| {
+        let bytes = k.to_bytes_be();
+        b.iter(|| black_box(g_jacobian).mul_vartime(black_box(&bytes)))
+    });
+    group.bench_function("Jacobian fixed window", |b| {
+        b.iter(|| black_box(g_jacobian).mul(black_box(&scalar)))
+    });
+    group.bench_function("Jacobian generator table", |b| {
+        b.iter(|| JacobianPoint::mul_generator(black_box(&scalar)))
+    });
+    group.finish();
+}
+
+// the affine FiniteField double-and-add that `BigUint * EccPoint` used before the
+// Jacobian rewrite, kept as the baseline for the point benches
+fn affine_mul(k: &BigUint, point: &EccPoint) -> EccPoint {
+    let mut k = k.clone();
+    let mut current = point.clone();
+    let mut res = EccPoint::PointAtInfinity;
+    while !k.is_zero() {
+        if k.is_odd() {
+            res = res + current.clone();
+        }
+        current = &current + &current;
+        k >>= 1;
+    }
+    res
+}
+
+fn double_mul_benches(c: &mut Criterion) {
+    let g: &EccPoint = &SECP256K1GENS;
+    let p = Key::new(BigUint::from(0xdead_beef_u32)).point;
+    let u = &(*SECP256K1GENS_ORDER) - 12345_u32;
+    let v = &(*SECP256K1GENS_ORDER) >> 7;
+
+    let mut group = c.benchmark_group("u * G + v * P");
+    group.bench_function("affine double-and-add", |b| {
+        b.iter(|| affine_mul(black_box(&u), g) + affine_mul(black_box(&v), &p))
+    });
+    group.bench_function("two scalar multiplications", |b| {
+        b.iter(|| black_box(&u) * g + black_box(&v) * &p)
+    });
+    group.bench_function("EccPoint::double_mul", |b| {
+        b.iter(|| EccPoint::double_mul(black_box(&u), g, black_box(&v), &p))
+    });
+    group.finish();
+}
+
+fn verify_benches(c: &mut Criterion) {
+    let keys: Vec<Key> = (1..=64_u32)
+        .map(|i| Key::new(BigUint::from(i) * 0x1234_5678_u32))
+        .collect();
+    let zs: Vec<BigUint> = (1..=64_u32).map(|i| BigUint::from(i) << 200).collect();
+    let sigs: Vec<RecoverableSignature> = keys
+        .iter()
+        .zip(&zs)
+        .map(|(key, z)| key.sign_recoverable(z.clone()))
+        .collect();
+    let items: Vec<_> = keys
+        .iter()
+        .zip(&zs)
+        .zip(&sigs)
+        .map(|((key, z), sig)| (&key.point, z, sig))
+        .collect();
+
+    let mut group = c.benchmark_group("verify 64 signatures");
+    group.sample_size(10);
+    group.bench_function("verify_secp256k1", |b| {
+        b.iter(|| {
+            for (point, z, sig) in &items {
+                point.verify_secp256k1(z, &sig.signature).unwrap();
+            }
+        })
+    });
+    group.bench_function("verify_secp256k1_batch", |b| {
+        b.iter(|| EccPoint::verify_secp256k1_batch(black_box(&items)).unwrap())
+    });
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    field_benches,
+    scalar_benches,
+    point_benches,
+    double_mul_benches,
+    verify_benches
+);
+criterion_main!(benches);
@@ -0,0 +1,3 @@
+extern crate num_bigint_dig as num_bigint;
+
+pub mod libs;
@@ -0,0 +1,547 @@
+use std::{fmt, str::FromStr};
+
+use anyhow::bail;
+use sha2::{Digest, Sha256};
+
+use super::{
+    bech32::{decode_segwit_address, encode_segwit_address},
+    ecc::{hash160, EccPoint},
+    network::{
+        BitcoinNetwork, BITCOIN_MAINNET_HRP, BITCOIN_MAINNET_P2SH_PREFIX, BITCOIN_MAINNET_PREFIX,
+        BITCOIN_REGTEST_HRP, BITCOIN_TESTNET_HRP, BITCOIN_TESTNET_P2SH_PREFIX,
+        BITCOIN_TESTNET_PREFIX,
+    },
+    script::Script,
+    signature::{base58_decode_with_checksum, base58_encode_with_checksum},
+};
+
+// P2PKH and P2SH are base58check encoded, native segwit outputs use bech32 for
+// witness v0 and bech32m for v1 (BIP173/BIP350).
+// Regtest shares the testnet base58 version bytes, so a parsed legacy regtest
+// address comes back tagged as `BitcoinNetwork::TestNet`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Address {
+    P2pkh {
+        network: BitcoinNetwork,
+        hash: [u8; 20],
+    },
+    P2sh {
+        network: BitcoinNetwork,
+        hash: [u8; 20],
+    },
+    P2wpkh {
+        network: BitcoinNetwork,
+        hash: [u8; 20],
+    },
+    P2wsh {
+        network: BitcoinNetwork,
+        hash: [u8; 32],
+    },
+    P2tr {
+        network: BitcoinNetwork,
+        output_key: [u8; 32],
+    },
+}
+
+impl Address {
+    pub fn p2pkh(
+        point: &EccPoint,
+        compressed: bool,
+        network: BitcoinNetwork,
+    ) -> anyhow::Result<Address> {
+        let sec = if compressed {
+            point.serialize_sec_compressed()?
+        } else {
+            point.serialize_sec()?
+        };
+        Ok(Address::P2pkh {
+            network,
+            hash: hash160_array(&sec),
+        })
+    }
+
+    pub fn p2sh(redeem_script: &Script, network: BitcoinNetwork) -> Address {
+        Address::P2sh {
+            network,
+            hash: hash160_array(&redeem_script.raw_serialize()),
+        }
+    }
+
+    // P2WPKH commits to the compressed SEC only
+    pub fn p2wpkh(point: &EccPoint, network: BitcoinNetwork) -> anyhow::Result<Address> {
+        Ok(Address::P2wpkh {
+            network,
+            hash: hash160_array(&point.serialize_sec_compressed()?),
+        })
+    }
+
+    pub fn p2wsh(witness_script: &Script, network: BitcoinNetwork) -> Address {
+        Address::P2wsh {
+            network,
+            hash: Sha256::digest(witness_script.raw_serialize()).into(),
+        }
+    }
+
+    // key path only output, tweaked as in BIP341/BIP86
+    pub fn p2tr(internal_key: &EccPoint, network: BitcoinNetwork) -> anyhow::Result<Address> {
+        Ok(Address::P2tr {
+            network,
+            output_key: internal_key.taproot_output_key()?.x_only()?,
+        })
+    }
+
+    pub fn from_script_pubkey(
+        script_pubkey: &Script,
+        network: BitcoinNetwork,
+    ) -> anyhow::Result<Address> {
+        if let Some(hash) = script_pubkey.p2pkh_hash() {
+            return Ok(Address::P2pkh {
+                network,
+                hash: hash.try_into()?,
+            });
+        }
+        if let Some(hash) = script_pubkey.p2sh_hash() {
+            return Ok(Address::P2sh {
+                network,
+                hash: hash.try_into()?,
+            });
+        }
+        match script_pubkey.witness_version_and_program() {
+            Some((version, program)) => Address::from_witness_program(version, program, network),
+            None => bail!("[Address::from_script_pubkey] unsupported scriptPubKey"),
+        }
+    }
+
+    fn from_witness_program(
+        version: u8,
+        program: &[u8],
+        network: BitcoinNetwork,
+    ) -> anyhow::Result<Address> {
+        Ok(match (version, program.len()) {
+            (0, 20) => Address::P2wpkh {
+                network,
+                hash: program.try_into()?,
+            },
+            (0, 32) => Address::P2wsh {
+                network,
+                hash: program.try_into()?,
+            },
+            (1, 32) => Address::P2tr {
+                network,
+                output_key: program.try_into()?,
+            },
+            _ => bail!(
+                "[Address::from_witness_program] unsupported witness v{} program of {} bytes",
+                version,
+                program.len()
+            ),
+        })
+    }
+
+    pub fn script_pubkey(&self) -> Script {
+        let (version, program): (u8, &[u8]) = match self {
+            Address::P2pkh { hash, .. } => return Script::p2pkh(hash),
+            Address::P2sh { hash, .. } => return Script::p2sh(hash),
+            Address::P2wpkh { hash, .. } => (0, hash),
+            Address::P2wsh { hash, .. } => (0, hash),
+            Address::P2tr { output_key, .. } => (1, output_key),
+        };
+        Script::witness_program(version, program)
+            .expect("address programs are valid witness programs")
+    }
+
+    pub fn network(&self) -> BitcoinNetwork {
+        match self {
+            Address::P2pkh { network, .. }
+            | Address::P2sh { network, .. }
+            | Address::P2wpkh { network, .. }
+            | Address::P2wsh { network, .. }
+            | Address::P2tr { network, .. } => *network,
+        }
+    }
+
+    // hash160, sha256 or x-only output key, depending on the kind
+    pub fn payload(&self) -> &[u8] {
+        match self {
+            Address::P2pkh { hash, .. }
+            | Address::P2sh { hash, .. }
+            | Address::P2wpkh { hash, .. } => hash,
+            Address::P2wsh { hash, .. } => hash,
+            Address::P2tr { output_key, .. } => output_key,
+        }
+    }
+
+    fn base58_version(&self) -> Option<u8> {
+        match self {
+            Address::P2pkh {
+                network: BitcoinNetwork::MainNet,
+                ..
+            } => Some(BITCOIN_MAINNET_PREFIX),
+            Address::P2pkh { .. } => Some(BITCOIN_TESTNET_PREFIX),
+            Address::P2sh {
+                network: BitcoinNetwork::MainNet,
+                ..
+            } => Some(BITCOIN_MAINNET_P2SH_PREFIX),
+            Address::P2sh { .. } => Some(BITCOIN_TESTNET_P2SH_PREFIX),
+            _ => None,
+        }
+    }
+
+    fn witness_version(&self) -> Option<u8> {
+        match self {
+            Address::P2wpkh { .. } | Address::P2wsh { .. } => Some(0),
+            Address::P2tr { .. } => Some(1),
+            _ => None,
+        }
+    }
+
+    fn from_base58(s: &str) -> anyhow::Result<Address> {
+        let raw = base58_decode_with_checksum(s)?;
+        if raw.len() != 21 {
+            bail!(
+                "[Address::from_str] expected 21 bytes of payload, got {}",
+                raw.len()
+            );
+        }
+        let hash: [u8; 20] = raw[1..].try_into()?;
+        Ok(match raw[0] {
+            BITCOIN_MAINNET_PREFIX => Address::P2pkh {
+                network: BitcoinNetwork::MainNet,
+                hash,
+            },
+            BITCOIN_TESTNET_PREFIX => Address::P2pkh {
+                network: BitcoinNetwork::TestNet,
+                hash,
+            },
+            BITCOIN_MAINNET_P2SH_PREFIX => Address::P2sh {
+                network: BitcoinNetwork::MainNet,
+                hash,
+            },
+            BITCOIN_TESTNET_P2SH_PREFIX => Address::P2sh {
+                network: BitcoinNetwork::TestNet,
+                hash,
+            },
+            version => bail!("[Address::from_str] unknown version byte {:#04x}", version),
+        })
+    }
+
+    fn from_bech32(s: &str) -> anyhow::Result<Address> {
+        let (hrp, version, program) = decode_segwit_address(s)?;
+        let network = match hrp.as_str() {
+            BITCOIN_MAINNET_HRP => BitcoinNetwork::MainNet,
+            BITCOIN_TESTNET_HRP => BitcoinNetwork::TestNet,
+            BITCOIN_REGTEST_HRP => BitcoinNetwork::RegTest,
+            _ => bail!("[Address::from_str] unknown hrp {:?}", hrp),
+        };
+        Address::from_witness_program(version, &program, network)
+    }
+}
+
+fn hash160_array(data: &[u8]) -> [u8; 20] {
+    let mut hash = [0_u8; 20];
+    hash.copy_from_slice(&hash160(data));
+    hash
+}
+
+fn hrp(network: BitcoinNetwork) -> &'static str {
+    match network {
+        BitcoinNetwork::MainNet => BITCOIN_MAINNET_HRP,
+        BitcoinNetwork::TestNet => BITCOIN_TESTNET_HRP,
+        BitcoinNetwork::RegTest => BITCOIN_REGTEST_HRP,
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let encoded = match (self.base58_version(), self.witness_version()) {
+            (Some(version), _) => {
+                let mut raw = Vec::with_capacity(21);
+                raw.push(version);
+                raw.extend(self.payload());
+                base58_encode_with_checksum(&raw).map_err(|_| fmt::Error)?
+            }
+            (None, Some(version)) => {
+                encode_segwit_address(hrp(self.network()), version, self.payload())
+                    .map_err(|_| fmt::Error)?
+            }
+            (None, None) => unreachable!("every address kind has an encoding"),
+        };
+        write!(f, "{}", encoded)
+    }
+}
+
+// bech32 if the string starts with a known hrp, base58check otherwise
+impl FromStr for Address {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let lower = s.to_lowercase();
+        let is_bech32 = [
+            BITCOIN_MAINNET_HRP,
+            BITCOIN_TESTNET_HRP,
+            BITCOIN_REGTEST_HRP,
+        ]
+        .iter()
+        .any(|hrp| {
+            lower
+                .strip_prefix(hrp)
+                .is_some_and(|rest| rest.starts_with('1'))
+        });
+        if is_bech32 {
+            Address::from_bech32(s)
+        } else {
+            Address::from_base58(s)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use num_bigint_dig::BigUint;
+    use num_traits::{Num, Pow};
+
+    use crate::libs::{
+        ecc::EccPoint,
+        key::Key,
+        network::BitcoinNetwork,
+        script::{Command, Opcode, Script},
+        transaction::TransactionOutput,
+    };
+
+    use super::Address;
+
+    #[test]
+    fn p2pkh_address_test() {
+        // Programming Bitcoin, chapter 4 exercise 5
+        let cases = [
+            (
+                BigUint::from(5002_u32),
+                false,
+                BitcoinNetwork::TestNet,
+                "mmTPbXQFxboEtNRkwfh6K51jvdtHLxGeMA",
+            ),
+            (
+                BigUint::from(2020_u32).pow(5_u32),
+                true,
+                BitcoinNetwork::TestNet,
+                "mopVkxp8UhXqRYbCYJsbeE1h1fiF64jcoH",
+            ),
+            (
+                BigUint::from_str_radix("12345deadbeef", 16).unwrap(),
+                true,
+                BitcoinNetwork::MainNet,
+                "1F1Pn2y6pDb68E5nYJJeba4TLg2U7B6KF1",
+            ),
+        ];
+        for (secret, compressed, network, expected) in cases {
+            let key = Key::new(secret);
+            let address = Address::p2pkh(&key.point, compressed, network).unwrap();
+            assert_eq!(address.to_string(), expected);
+            assert_eq!(expected.parse::<Address>().unwrap(), address);
+        }
+    }
+
+    #[test]
+    fn regtest_address_test() {
+        let key = Key::new(BigUint::from(5002_u32));
+        let address = Address::p2pkh(&key.point, false, BitcoinNetwork::RegTest).unwrap();
+        assert_eq!(address.to_string(), "mmTPbXQFxboEtNRkwfh6K51jvdtHLxGeMA");
+    }
+
+    #[test]
+    fn p2sh_address_test() {
+        // hash160 of an empty redeem script is b472a266d0bd89c13706a4132ccfb16f7c3b9fcb
+        let empty = Script::new(vec![]);
+        let address = Address::p2sh(&empty, BitcoinNetwork::MainNet);
+        assert_eq!(
+            hex::encode(address.payload()),
+            "b472a266d0bd89c13706a4132ccfb16f7c3b9fcb"
+        );
+        assert_eq!(address.to_string(), "3J98t1WpEZ73CNmQviecrnyiWrnqRhWNLy");
+        assert_eq!(
+            "3J98t1WpEZ73CNmQviecrnyiWrnqRhWNLy"
+                .parse::<Address>()
+                .unwrap(),
+            address
+        );
+
+        let redeem = Script::new(vec![Command::Op(Opcode::OP_1)]);
+        let testnet = Address::p2sh(&redeem, BitcoinNetwork::TestNet);
+        assert!(testnet.to_string().starts_with('2'));
+        assert_eq!(testnet.to_string().parse::<Address>().unwrap(), testnet);
+    }
+
+    #[test]
+    fn invalid_address_test() {
+        assert!("".parse::<Address>().is_err());
+        assert!("1F1Pn2y6pDb68E5nYJJeba4TLg2U7B6KF2"
+            .parse::<Address>()
+            .is_err());
+        assert!("0F1Pn2y6pDb68E5nYJJeba4TLg2U7B6KF1"
+            .parse::<Address>()
+            .is_err());
+    }
+
+    #[test]
+    fn p2wpkh_address_test() {
+        let key = Key::new(BigUint::from(1_u32));
+        let cases = [
+            (
+                BitcoinNetwork::MainNet,
+                "bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4",
+            ),
+            (
+                BitcoinNetwork::TestNet,
+                "tb1qw508d6qejxtdg4y5r3zarvary0c5xw7kxpjzsx",
+            ),
+            (
+                BitcoinNetwork::RegTest,
+                "bcrt1qw508d6qejxtdg4y5r3zarvary0c5xw7kygt080",
+            ),
+        ];
+        for (network, expected) in cases {
+            let address = Address::p2wpkh(&key.point, network).unwrap();
+            assert_eq!(address.to_string(), expected);
+            let parsed = expected.parse::<Address>().unwrap();
+            assert_eq!(parsed, address);
+            assert_eq!(parsed.network(), network);
+            assert_eq!(
+                hex::encode(parsed.payload()),
+                "751e76e8199196d454941c45d1b3a323f1433bd6"
+            );
+        }
+    }
+
+    #[test]
+    fn p2wsh_address_test() {
+        // BIP173: <G compressed> OP_CHECKSIG
+        let key = Key::new(BigUint::from(1_u32));
+        let witness_script = Script::new(vec![
+            Command::Data(key.point.serialize_sec_compressed().unwrap()),
+            Command::Op(Opcode::OP_CHECKSIG),
+        ]);
+        let address = Address::p2wsh(&witness_script, BitcoinNetwork::TestNet);
+        assert_eq!(
+            address.to_string(),
+            "tb1qrp33g0q5c5txsp9arysrx4k6zdkfs4nce4xj0gdcccefvpysxf3q0sl5k7"
+        );
+        let address = Address::p2wsh(&witness_script, BitcoinNetwork::MainNet);
+        assert_eq!(
+            address.to_string(),
+            "bc1qrp33g0q5c5txsp9arysrx4k6zdkfs4nce4xj0gdcccefvpysxf3qccfmv3"
+        );
+        assert_eq!(address.to_string().parse::<Address>().unwrap(), address);
+    }
+
+    #[test]
+    fn p2tr_address_test() {
+        // BIP86 first receiving address, m/86'/0'/0'/0/0
+        let internal_key = EccPoint::parse_sec(
+            &hex::decode("02cc8a4bc64d897bddc5fbc2f670f7a8ba0b386779106cf1223c6fc5d7cd6fc115")
+                .unwrap(),
+        )
+        .unwrap();
+        let address = Address::p2tr(&internal_key, BitcoinNetwork::MainNet).unwrap();
+        assert_eq!(
+            address.to_string(),
+            "bc1p5cyxnuxmeuwuvkwfem96lqzszd02n6xdcjrs20cac6yqjjwudpxqkedrcr"
+        );
+        assert!(matches!(address, Address::P2tr { .. }));
+        assert_eq!(address.to_string().parse::<Address>().unwrap(), address);
+
+        // only x(P) matters for the internal key
+        let odd = EccPoint::parse_sec(
+            &hex::decode("03cc8a4bc64d897bddc5fbc2f670f7a8ba0b386779106cf1223c6fc5d7cd6fc115")
+                .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            Address::p2tr(&odd, BitcoinNetwork::MainNet).unwrap(),
+            address
+        );
+    }
+
+    #[test]
+    fn invalid_segwit_address_test() {
+        // unknown hrp
+        assert!("ltc1qw508d6qejxtdg4y5r3zarvary0c5xw7kgmn4n9"
+            .parse::<Address>()
+            .is_err());
+        // bad checksum
+        assert!("bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t5"
+            .parse::<Address>()
+            .is_err());
+        // v0 programs must be 20 or 32 bytes
+        assert!("bc1qqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqj9pecr"
+            .parse::<Address>()
+            .is_err());
+        assert!(Address::from_script_pubkey(
+            &Script::witness_program(0, &[0; 21]).unwrap(),
+            BitcoinNetwork::MainNet
+        )
+        .is_err());
+        // the program has to be a direct push
+        let pushdata1 = [&[0x00, 0x4c, 0x14][..], &[0; 20]].concat();
+        assert!(Address::from_script_pubkey(
+            &Script::parse_raw(&pushdata1).unwrap(),
+            BitcoinNetwork::MainNet
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn script_pubkey_roundtrip_test() {
+        let key = Key::new(BigUint::from(1_u32));
+        let redeem = Script::new(vec![Command::Op(Opcode::OP_1)]);
+        let addresses = [
+            Address::p2pkh(&key.point, true, BitcoinNetwork::MainNet).unwrap(),
+            Address::p2sh(&redeem, BitcoinNetwork::TestNet),
+            Address::p2wpkh(&key.point, BitcoinNetwork::RegTest).unwrap(),
+            Address::p2wsh(&redeem, BitcoinNetwork::MainNet),
+            Address::p2tr(&key.point, BitcoinNetwork::TestNet).unwrap(),
+        ];
+        for address in addresses {
+            let output = TransactionOutput::new(1000, address.script_pubkey());
+            let script_pubkey = output.script_pubkey().unwrap();
+            assert_eq!(
+                Address::from_script_pubkey(&script_pubkey, address.network()).unwrap(),
+                address
+            );
+            assert_eq!(address.to_string().parse::<Address>().unwrap(), address);
+        }
+
+        assert_eq!(
+            hex::encode(
+                "bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4"
+                    .parse::<Address>()
+                    .unwrap()
+                    .script_pubkey()
+                    .raw_serialize()
+            ),
+            "0014751e76e8199196d454941c45d1b3a323f1433bd6"
+        );
+        assert_eq!(
+            hex::encode(
+                "1F1Pn2y6pDb68E5nYJJeba4TLg2U7B6KF1"
+                    .parse::<Address>()
+                    .unwrap()
+                    .script_pubkey()
+                    .raw_serialize()
+            ),
+            "76a91499a4c61750789253f69fd750ac0d02126337330588ac"
+        );
+
+        // bare multisig and OP_RETURN have no address
+        let op_return = Script::new(vec![
+            Command::Op(Opcode::OP_RETURN),
+            Command::Data(b"ritcoin".to_vec()),
+        ]);
+        assert!(Address::from_script_pubkey(&op_return, BitcoinNetwork::MainNet).is_err());
+        // future witness versions are valid bech32m but not supported here
+        assert!(Address::from_script_pubkey(
+            &Script::witness_program(2, &[0; 32]).unwrap(),
+            BitcoinNetwork::MainNet
+        )
+        .is_err());
+    }
+}
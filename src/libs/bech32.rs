@@ -0,0 +1,396 @@
+use std::fmt::Display;
+
+// https://github.com/bitcoin/bips/blob/master/bip-0173.mediawiki
+// https://github.com/bitcoin/bips/blob/master/bip-0350.mediawiki
+const BECH32_CHARSET: &[u8; 32] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+const BECH32_GENERATOR: [u32; 5] = [0x3b6a57b2, 0x26508e6d, 0x1ea119fa, 0x3d4233dd, 0x2a1462b3];
+const BECH32_CONST: u32 = 1;
+const BECH32M_CONST: u32 = 0x2bc830a3;
+const BECH32_MAX_LENGTH: usize = 90;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bech32Variant {
+    Bech32,
+    Bech32m,
+}
+
+impl Bech32Variant {
+    fn constant(&self) -> u32 {
+        match self {
+            Bech32Variant::Bech32 => BECH32_CONST,
+            Bech32Variant::Bech32m => BECH32M_CONST,
+        }
+    }
+
+    // witness v0 uses bech32, every later version bech32m
+    pub fn for_witness_version(version: u8) -> Bech32Variant {
+        if version == 0 {
+            Bech32Variant::Bech32
+        } else {
+            Bech32Variant::Bech32m
+        }
+    }
+}
+
+#[non_exhaustive]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Bech32Error {
+    InvalidLength { length: usize },
+    InvalidCharacter { character: char, index: usize },
+    MixedCase,
+    MissingSeparator,
+    InvalidHrp,
+    InvalidChecksum,
+    InvalidPadding,
+    InvalidWitnessVersion { version: u8 },
+    InvalidProgramLength { version: u8, length: usize },
+    WrongVariant { expected: Bech32Variant },
+    WrongHrp { expected: String, actual: String },
+}
+
+impl Display for Bech32Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Bech32Error::InvalidLength { length } => {
+                write!(f, "[bech32] invalid length {}", length)
+            }
+            Bech32Error::InvalidCharacter { character, index } => write!(
+                f,
+                "[bech32] invalid character {:?} at index {}",
+                character, index
+            ),
+            Bech32Error::MixedCase => write!(f, "[bech32] mixed upper and lower case"),
+            Bech32Error::MissingSeparator => write!(f, "[bech32] missing separator '1'"),
+            Bech32Error::InvalidHrp => write!(f, "[bech32] invalid human-readable part"),
+            Bech32Error::InvalidChecksum => write!(f, "[bech32] invalid checksum"),
+            Bech32Error::InvalidPadding => write!(f, "[bech32] invalid padding"),
+            Bech32Error::InvalidWitnessVersion { version } => {
+                write!(f, "[bech32] invalid witness version {}", version)
+            }
+            Bech32Error::InvalidProgramLength { version, length } => write!(
+                f,
+                "[bech32] invalid witness program length {} for version {}",
+                length, version
+            ),
+            Bech32Error::WrongVariant { expected } => {
+                write!(f, "[bech32] expected {:?} checksum", expected)
+            }
+            Bech32Error::WrongHrp { expected, actual } => {
+                write!(f, "[bech32] expected hrp {:?}, got {:?}", expected, actual)
+            }
+        }
+    }
+}
+
+impl std::error::Error for Bech32Error {}
+
+fn polymod(values: &[u8]) -> u32 {
+    let mut chk = 1_u32;
+    for &value in values {
+        let top = chk >> 25;
+        chk = ((chk & 0x1ffffff) << 5) ^ value as u32;
+        for (i, generator) in BECH32_GENERATOR.iter().enumerate() {
+            if (top >> i) & 1 == 1 {
+                chk ^= generator;
+            }
+        }
+    }
+    chk
+}
+
+fn hrp_expand(hrp: &str) -> Vec<u8> {
+    let mut res: Vec<u8> = hrp.bytes().map(|c| c >> 5).collect();
+    res.push(0);
+    res.extend(hrp.bytes().map(|c| c & 31));
+    res
+}
+
+fn create_checksum(hrp: &str, data: &[u8], variant: Bech32Variant) -> [u8; 6] {
+    let mut values = hrp_expand(hrp);
+    values.extend(data);
+    values.extend([0_u8; 6]);
+    let modulo = polymod(&values) ^ variant.constant();
+    let mut checksum = [0_u8; 6];
+    for (i, c) in checksum.iter_mut().enumerate() {
+        *c = ((modulo >> (5 * (5 - i))) & 31) as u8;
+    }
+    checksum
+}
+
+// `data` is a slice of 5-bit values
+pub fn bech32_encode(
+    hrp: &str,
+    data: &[u8],
+    variant: Bech32Variant,
+) -> Result<String, Bech32Error> {
+    if hrp.is_empty() || hrp.bytes().any(|c| !(33..=126).contains(&c)) {
+        return Err(Bech32Error::InvalidHrp);
+    }
+    let hrp = hrp.to_lowercase();
+    if let Some(index) = data.iter().position(|&d| d > 31) {
+        return Err(Bech32Error::InvalidCharacter {
+            character: char::from(data[index]),
+            index,
+        });
+    }
+    let length = hrp.len() + 1 + data.len() + 6;
+    if length > BECH32_MAX_LENGTH {
+        return Err(Bech32Error::InvalidLength { length });
+    }
+
+    let mut res = String::with_capacity(length);
+    res.push_str(&hrp);
+    res.push('1');
+    for &d in data
+        .iter()
+        .chain(create_checksum(&hrp, data, variant).iter())
+    {
+        res.push(char::from(BECH32_CHARSET[d as usize]));
+    }
+    Ok(res)
+}
+
+// Returns the lowercase hrp, the 5-bit data without checksum and the checksum variant
+pub fn bech32_decode(s: &str) -> Result<(String, Vec<u8>, Bech32Variant), Bech32Error> {
+    if s.len() > BECH32_MAX_LENGTH {
+        return Err(Bech32Error::InvalidLength { length: s.len() });
+    }
+    if let Some((index, character)) = s
+        .char_indices()
+        .find(|(_, c)| !(33..=126).contains(&(*c as u32)))
+    {
+        return Err(Bech32Error::InvalidCharacter { character, index });
+    }
+    if s.bytes().any(|c| c.is_ascii_lowercase()) && s.bytes().any(|c| c.is_ascii_uppercase()) {
+        return Err(Bech32Error::MixedCase);
+    }
+    let s = s.to_lowercase();
+    let separator = s.rfind('1').ok_or(Bech32Error::MissingSeparator)?;
+    if separator == 0 {
+        return Err(Bech32Error::InvalidHrp);
+    }
+    if separator + 7 > s.len() {
+        return Err(Bech32Error::InvalidLength { length: s.len() });
+    }
+
+    let hrp = &s[..separator];
+    let mut data = Vec::with_capacity(s.len() - separator - 1);
+    for (index, c) in s.bytes().enumerate().skip(separator + 1) {
+        let value =
+            BECH32_CHARSET
+                .iter()
+                .position(|&a| a == c)
+                .ok_or(Bech32Error::InvalidCharacter {
+                    character: char::from(c),
+                    index,
+                })?;
+        data.push(value as u8);
+    }
+
+    let mut values = hrp_expand(hrp);
+    values.extend(&data);
+    let variant = match polymod(&values) {
+        BECH32_CONST => Bech32Variant::Bech32,
+        BECH32M_CONST => Bech32Variant::Bech32m,
+        _ => return Err(Bech32Error::InvalidChecksum),
+    };
+    data.truncate(data.len() - 6);
+    Ok((hrp.to_string(), data, variant))
+}
+
+// Regroups `from`-bit values into `to`-bit values, like `convertbits` in BIP173
+pub fn convert_bits(data: &[u8], from: u32, to: u32, pad: bool) -> Result<Vec<u8>, Bech32Error> {
+    let mut acc = 0_u32;
+    let mut bits = 0_u32;
+    let max = (1_u32 << to) - 1;
+    let mut res = Vec::with_capacity(data.len() * from as usize / to as usize + 1);
+    for &value in data {
+        if (value as u32) >> from != 0 {
+            return Err(Bech32Error::InvalidPadding);
+        }
+        acc = (acc << from) | value as u32;
+        bits += from;
+        while bits >= to {
+            bits -= to;
+            res.push(((acc >> bits) & max) as u8);
+        }
+    }
+    if pad {
+        if bits > 0 {
+            res.push(((acc << (to - bits)) & max) as u8);
+        }
+    } else if bits >= from || ((acc << (to - bits)) & max) != 0 {
+        return Err(Bech32Error::InvalidPadding);
+    }
+    Ok(res)
+}
+
+fn check_witness_program(version: u8, program: &[u8]) -> Result<(), Bech32Error> {
+    if version > 16 {
+        return Err(Bech32Error::InvalidWitnessVersion { version });
+    }
+    let length = program.len();
+    if !(2..=40).contains(&length) || (version == 0 && length != 20 && length != 32) {
+        return Err(Bech32Error::InvalidProgramLength { version, length });
+    }
+    Ok(())
+}
+
+pub fn encode_segwit_address(
+    hrp: &str,
+    version: u8,
+    program: &[u8],
+) -> Result<String, Bech32Error> {
+    check_witness_program(version, program)?;
+    let mut data = vec![version];
+    data.extend(convert_bits(program, 8, 5, true)?);
+    bech32_encode(hrp, &data, Bech32Variant::for_witness_version(version))
+}
+
+// Returns the hrp, witness version and witness program
+pub fn decode_segwit_address(address: &str) -> Result<(String, u8, Vec<u8>), Bech32Error> {
+    let (hrp, data, variant) = bech32_decode(address)?;
+    let (&version, program) = data.split_first().ok_or(Bech32Error::InvalidLength {
+        length: address.len(),
+    })?;
+    let program = convert_bits(program, 5, 8, false)?;
+    check_witness_program(version, &program)?;
+    let expected = Bech32Variant::for_witness_version(version);
+    if variant != expected {
+        return Err(Bech32Error::WrongVariant { expected });
+    }
+    Ok((hrp, version, program))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        bech32_decode, bech32_encode, decode_segwit_address, encode_segwit_address, Bech32Error,
+        Bech32Variant,
+    };
+
+    #[test]
+    fn bech32_test() {
+        for (s, variant) in [
+            ("A12UEL5L", Bech32Variant::Bech32),
+            ("a12uel5l", Bech32Variant::Bech32),
+            ("A1LQFN3A", Bech32Variant::Bech32m),
+            ("a1lqfn3a", Bech32Variant::Bech32m),
+        ] {
+            let (hrp, data, decoded_variant) = bech32_decode(s).unwrap();
+            assert_eq!(hrp, "a");
+            assert!(data.is_empty());
+            assert_eq!(decoded_variant, variant);
+            assert_eq!(
+                bech32_encode(&hrp, &data, variant).unwrap(),
+                s.to_lowercase()
+            );
+        }
+    }
+
+    #[test]
+    fn bech32_invalid_test() {
+        assert_eq!(bech32_decode("A12UEl5L"), Err(Bech32Error::MixedCase));
+        assert_eq!(bech32_decode("a12uel5m"), Err(Bech32Error::InvalidChecksum));
+        assert_eq!(
+            bech32_decode("pzry9x0s0muk"),
+            Err(Bech32Error::MissingSeparator)
+        );
+        assert_eq!(bech32_decode("1pzry9x0s0muk"), Err(Bech32Error::InvalidHrp));
+        assert_eq!(
+            bech32_decode("a1b2uel5l"),
+            Err(Bech32Error::InvalidCharacter {
+                character: 'b',
+                index: 2
+            })
+        );
+        assert!(matches!(
+            bech32_decode("a1uel5l"),
+            Err(Bech32Error::InvalidLength { .. })
+        ));
+    }
+
+    #[test]
+    fn segwit_address_test() {
+        // BIP173 / BIP350 test vectors
+        let cases = [
+            (
+                "BC1QW508D6QEJXTDG4Y5R3ZARVARY0C5XW7KV8F3T4",
+                "bc",
+                0,
+                "751e76e8199196d454941c45d1b3a323f1433bd6",
+            ),
+            (
+                "tb1qrp33g0q5c5txsp9arysrx4k6zdkfs4nce4xj0gdcccefvpysxf3q0sl5k7",
+                "tb",
+                0,
+                "1863143c14c5166804bd19203356da136c985678cd4d27a1b8c6329604903262",
+            ),
+            (
+                "bc1pw508d6qejxtdg4y5r3zarvary0c5xw7kw508d6qejxtdg4y5r3zarvary0c5xw7kt5nd6y",
+                "bc",
+                1,
+                "751e76e8199196d454941c45d1b3a323f1433bd6751e76e8199196d454941c45d1b3a323f1433bd6",
+            ),
+            (
+                "bc1p5cyxnuxmeuwuvkwfem96lqzszd02n6xdcjrs20cac6yqjjwudpxqkedrcr",
+                "bc",
+                1,
+                "a60869f0dbcf1dc659c9cecbaf8050135ea9e8cdc487053f1dc6880949dc684c",
+            ),
+        ];
+        for (address, hrp, version, program) in cases {
+            let program = hex::decode(program).unwrap();
+            assert_eq!(
+                decode_segwit_address(address).unwrap(),
+                (hrp.to_string(), version, program.clone())
+            );
+            assert_eq!(
+                encode_segwit_address(hrp, version, &program).unwrap(),
+                address.to_lowercase()
+            );
+        }
+    }
+
+    #[test]
+    fn segwit_address_invalid_test() {
+        let program = [0x75_u8; 20];
+        // v0 with a bech32m checksum and v1 with a bech32 checksum
+        let mut data = vec![0_u8];
+        data.extend(super::convert_bits(&program, 8, 5, true).unwrap());
+        let v0_bech32m = bech32_encode("bc", &data, Bech32Variant::Bech32m).unwrap();
+        assert_eq!(
+            decode_segwit_address(&v0_bech32m),
+            Err(Bech32Error::WrongVariant {
+                expected: Bech32Variant::Bech32
+            })
+        );
+        data[0] = 1;
+        let v1_bech32 = bech32_encode("bc", &data, Bech32Variant::Bech32).unwrap();
+        assert_eq!(
+            decode_segwit_address(&v1_bech32),
+            Err(Bech32Error::WrongVariant {
+                expected: Bech32Variant::Bech32m
+            })
+        );
+
+        assert_eq!(
+            encode_segwit_address("bc", 0, &program[..16]),
+            Err(Bech32Error::InvalidProgramLength {
+                version: 0,
+                length: 16
+            })
+        );
+        assert_eq!(
+            encode_segwit_address("bc", 17, &program),
+            Err(Bech32Error::InvalidWitnessVersion { version: 17 })
+        );
+        assert_eq!(
+            encode_segwit_address("bc", 1, &[0x01]),
+            Err(Bech32Error::InvalidProgramLength {
+                version: 1,
+                length: 1
+            })
+        );
+    }
+}
@@ -0,0 +1,290 @@
+use anyhow::bail;
+use base64::{engine::general_purpose::STANDARD, Engine};
+
+use super::{
+    address::Address,
+    codec::{Decodable, Encodable},
+    ecc::{hash256, tagged_hash},
+    interpreter::{verify_script_with_witness, TransactionSignatureChecker},
+    key::Key,
+    script::{Command, Opcode, Script},
+    transaction::{
+        Transaction, TransactionInput, TransactionLocktime, TransactionOutput, TransactionVersion,
+        Witness, SIGHASH_ALL, SIGHASH_DEFAULT,
+    },
+};
+
+pub const BIP322_TAG: &str = "BIP0322-signed-message";
+
+pub fn message_hash(message: &str) -> [u8; 32] {
+    tagged_hash(BIP322_TAG, message.as_bytes())
+}
+
+// Virtual transaction whose only output is the address being proven, spending an
+// impossible outpoint with the message hash in its scriptSig
+pub fn to_spend(script_pubkey: &Script, message: &str) -> Transaction {
+    let script_sig = Script::new(vec![
+        Command::Op(Opcode::OP_0),
+        Command::Data(message_hash(message).to_vec()),
+    ]);
+    Transaction::new(
+        TransactionVersion::new(0),
+        vec![TransactionInput::new(
+            [0; 32],
+            0xffffffff_u32.to_le_bytes(),
+            script_sig,
+            [0; 4],
+        )],
+        vec![TransactionOutput::new(0, script_pubkey.clone())],
+        TransactionLocktime::new(0),
+    )
+}
+
+// Virtual transaction spending `to_spend`'s output into a single OP_RETURN; signing
+// its input is what proves control of the address
+pub fn to_sign(to_spend: &Transaction) -> Transaction {
+    let txid: [u8; 32] = hash256(&to_spend.serialize_without_witness())
+        .try_into()
+        .expect("hash256 must be 32 bytes");
+    Transaction::new(
+        TransactionVersion::new(0),
+        vec![TransactionInput::new(
+            txid,
+            0_u32.to_le_bytes(),
+            Script::default(),
+            [0; 4],
+        )],
+        vec![TransactionOutput::new(
+            0,
+            Script::new(vec![Command::Op(Opcode::OP_RETURN)]),
+        )],
+        TransactionLocktime::new(0),
+    )
+}
+
+// Signs the first input of `to_sign` the way a wallet would spend `address`: P2PKH
+// in scriptSig, P2WPKH and P2TR key path in the witness
+fn sign_to_sign(key: &Key, address: &Address, message: &str) -> anyhow::Result<Transaction> {
+    let to_spend = to_spend(&address.script_pubkey(), message);
+    let prevouts = to_spend.outputs().to_vec();
+    let mut to_sign = to_sign(&to_spend);
+    match address {
+        Address::P2pkh { .. } => {
+            to_sign.sign_input(0, key, &address.script_pubkey(), SIGHASH_ALL)?
+        }
+        Address::P2wpkh { .. } => to_sign.sign_input_p2wpkh(0, key, &prevouts[0], SIGHASH_ALL)?,
+        Address::P2tr { .. } => to_sign.sign_input_p2tr(0, key, &prevouts, SIGHASH_DEFAULT)?,
+        _ => bail!(
+            "[bip322] can't sign for {} addresses",
+            address_kind(address)
+        ),
+    }
+    Ok(to_sign)
+}
+
+// "simple" format: the base64 witness stack of `to_sign`, for segwit addresses only
+pub fn sign_simple(key: &Key, address: &str, message: &str) -> anyhow::Result<String> {
+    let address: Address = address.parse()?;
+    if matches!(address, Address::P2pkh { .. }) {
+        bail!("[bip322::sign_simple] P2PKH addresses need the full format");
+    }
+    let to_sign = sign_to_sign(key, &address, message)?;
+    Ok(STANDARD.encode(to_sign.inputs()[0].witness().serialize()))
+}
+
+// "full" format: the base64 serialization of the whole signed `to_sign`
+pub fn sign_full(key: &Key, address: &str, message: &str) -> anyhow::Result<String> {
+    let address: Address = address.parse()?;
+    let to_sign = sign_to_sign(key, &address, message)?;
+    Ok(STANDARD.encode(to_sign.serialize()))
+}
+
+pub fn verify_simple(address: &str, signature: &str, message: &str) -> anyhow::Result<()> {
+    let address: Address = address.parse()?;
+    let bytes = decode_base64(signature)?;
+    let mut reader = bytes.as_slice();
+    let witness = Witness::decode(&mut reader)?;
+    if !reader.is_empty() {
+        bail!("[bip322::verify_simple] trailing bytes after the witness");
+    }
+
+    let to_spend = to_spend(&address.script_pubkey(), message);
+    let mut to_sign = to_sign(&to_spend);
+    to_sign.inputs_mut()[0].set_witness(witness);
+    verify_to_sign(&to_spend, &to_sign)
+}
+
+// Only single input proofs are supported, i.e. no proof of funds
+pub fn verify_full(address: &str, signature: &str, message: &str) -> anyhow::Result<()> {
+    let address: Address = address.parse()?;
+    let to_sign = Transaction::parse(&decode_base64(signature)?)?;
+    let to_spend = to_spend(&address.script_pubkey(), message);
+
+    let expected = self::to_sign(&to_spend);
+    let input = match to_sign.inputs() {
+        [input] => input,
+        _ => bail!("[bip322::verify_full] to_sign must have exactly one input"),
+    };
+    if input.previous_tx_id() != expected.inputs()[0].previous_tx_id()
+        || input.previous_tx_idx() != 0
+    {
+        bail!("[bip322::verify_full] to_sign doesn't spend to_spend");
+    }
+    if to_sign.outputs() != expected.outputs() {
+        bail!("[bip322::verify_full] to_sign must have a single empty OP_RETURN output");
+    }
+    verify_to_sign(&to_spend, &to_sign)
+}
+
+fn verify_to_sign(to_spend: &Transaction, to_sign: &Transaction) -> anyhow::Result<()> {
+    let prevouts = to_spend.outputs();
+    let input = &to_sign.inputs()[0];
+    let checker = TransactionSignatureChecker::with_prevouts(to_sign, 0, prevouts);
+    verify_script_with_witness(
+        &input.script_signature()?,
+        &prevouts[0].script_pubkey()?,
+        input.witness().items(),
+        &checker,
+    )?;
+    Ok(())
+}
+
+fn decode_base64(signature: &str) -> anyhow::Result<Vec<u8>> {
+    match STANDARD.decode(signature) {
+        Ok(bytes) => Ok(bytes),
+        Err(e) => bail!("[bip322] signature is not valid base64: {}", e),
+    }
+}
+
+fn address_kind(address: &Address) -> &'static str {
+    match address {
+        Address::P2pkh { .. } => "P2PKH",
+        Address::P2sh { .. } => "P2SH",
+        Address::P2wpkh { .. } => "P2WPKH",
+        Address::P2wsh { .. } => "P2WSH",
+        Address::P2tr { .. } => "P2TR",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::libs::{address::Address, key::Key, network::BitcoinNetwork, script::Script};
+
+    use super::{
+        message_hash, sign_full, sign_simple, to_sign, to_spend, verify_full, verify_simple,
+    };
+
+    // test vectors from BIP-322
+    const WIF: &str = "L3VFeEujGtevx9w18HD1fhRbCH67Az2dpCymeRE1SoPK6XQtaN2k";
+    const P2WPKH_ADDRESS: &str = "bc1q9vza2e8x573nczrlzms0wvx3gsqjx7vavgkx0l";
+
+    #[test]
+    fn message_hash_test() {
+        assert_eq!(
+            hex::encode(message_hash("")),
+            "c90c269c4f8fcbe6880f72a721ddfbf1914268a794cbb21cfafee13770ae19f1"
+        );
+        assert_eq!(
+            hex::encode(message_hash("Hello World")),
+            "f0eb03b1a75ac6d9847f55c624a99169b5dccba2a31f5b23bea77ba270de0a7a"
+        );
+    }
+
+    #[test]
+    fn virtual_transactions_test() {
+        let address: Address = P2WPKH_ADDRESS.parse().unwrap();
+        for (message, spend_txid, sign_txid) in [
+            (
+                "",
+                "c5680aa69bb8d860bf82d4e9cd3504b55dde018de765a91bb566283c545a99a7",
+                "1e9654e951a5ba44c8604c4de6c67fd78a27e81dcadcfe1edf638ba3aaebaed6",
+            ),
+            (
+                "Hello World",
+                "b79d196740ad5217771c1098fc4a4b51e0535c32236c71f1ea4d61a2d603352b",
+                "88737ae86f2077145f93cc4b153ae9a1cb8d56afa511988c149c5c8c9d93bddf",
+            ),
+        ] {
+            let to_spend = to_spend(&address.script_pubkey(), message);
+            assert_eq!(to_spend.txid(), spend_txid);
+            assert_eq!(to_sign(&to_spend).txid(), sign_txid);
+        }
+    }
+
+    #[test]
+    fn simple_p2wpkh_test_vectors() {
+        let (key, _, _) = Key::from_wif(WIF).unwrap();
+        for (message, signature) in [
+            ("", "AkcwRAIgM2gBAQqvZX15ZiysmKmQpDrG83avLIT492QBzLnQIxYCIBaTpOaD20qRlEylyxFSeEA2ba9YOixpX8z46TSDtS40ASECx/EgAxlkQpQ9hYjgGu6EBCPMVPwVIVJqO4XCsMvViHI="),
+            ("Hello World", "AkcwRAIgZRfIY3p7/DoVTty6YZbWS71bc5Vct9p9Fia83eRmw2QCICK/ENGfwLtptFluMGs2KsqoNSk89pO7F29zJLUx9a/sASECx/EgAxlkQpQ9hYjgGu6EBCPMVPwVIVJqO4XCsMvViHI="),
+        ] {
+            verify_simple(P2WPKH_ADDRESS, signature, message).unwrap();
+            // ECDSA signatures aren't unique (the vectors grind for a low R), so the
+            // ones made here are checked by verifying them
+            let ours = sign_simple(&key, P2WPKH_ADDRESS, message).unwrap();
+            verify_simple(P2WPKH_ADDRESS, &ours, message).unwrap();
+        }
+        let signature = sign_simple(&key, P2WPKH_ADDRESS, "").unwrap();
+        assert!(verify_simple(P2WPKH_ADDRESS, &signature, "Hello World").is_err());
+    }
+
+    #[test]
+    fn simple_p2tr_test_vector() {
+        let address = "bc1ppv609nr0vr25u07u95waq5lucwfm6tde4nydujnu8npg4q75mr5sxq8lt3";
+        let signature = "AUHd69PrJQEv+oKTfZ8l+WROBHuy9HKrbFCJu7U1iK2iiEy1vMU5EfMtjc+VSHM7aU0SDbak5IUZRVno2P5mjSafAQ==";
+        verify_simple(address, signature, "Hello World").unwrap();
+        assert!(verify_simple(address, signature, "").is_err());
+    }
+
+    #[test]
+    fn sign_and_verify_roundtrip() {
+        let key = Key::new(0xdeadbeef_u32.into());
+        let network = BitcoinNetwork::MainNet;
+        let p2pkh = Address::p2pkh(&key.point, true, network)
+            .unwrap()
+            .to_string();
+        let p2wpkh = Address::p2wpkh(&key.point, network).unwrap().to_string();
+        let p2tr = Address::p2tr(&key.point, network).unwrap().to_string();
+
+        for address in [&p2wpkh, &p2tr] {
+            let signature = sign_simple(&key, address, "ritcoin").unwrap();
+            verify_simple(address, &signature, "ritcoin").unwrap();
+            assert!(verify_simple(address, &signature, "bitcoin").is_err());
+        }
+        for address in [&p2pkh, &p2wpkh, &p2tr] {
+            let signature = sign_full(&key, address, "ritcoin").unwrap();
+            verify_full(address, &signature, "ritcoin").unwrap();
+            assert!(verify_full(address, &signature, "bitcoin").is_err());
+        }
+
+        // a signature only proves the address it was made for
+        let signature = sign_simple(&key, &p2wpkh, "ritcoin").unwrap();
+        assert!(verify_simple(&p2tr, &signature, "ritcoin").is_err());
+        let other = Key::new(2019_u32.into());
+        assert!(sign_full(&other, &p2wpkh, "ritcoin").is_err());
+    }
+
+    #[test]
+    fn error_test() {
+        let key = Key::new(2019_u32.into());
+        let network = BitcoinNetwork::MainNet;
+        let p2pkh = Address::p2pkh(&key.point, true, network)
+            .unwrap()
+            .to_string();
+        let p2wpkh = Address::p2wpkh(&key.point, network).unwrap().to_string();
+        let p2wsh = Address::p2wsh(&Script::default(), network).to_string();
+
+        assert!(sign_simple(&key, &p2pkh, "hello").is_err());
+        assert!(sign_full(&key, &p2wsh, "hello").is_err());
+        assert!(verify_simple(&p2wpkh, "not base64!", "hello").is_err());
+
+        // P2PKH can't be proven by a witness
+        let signature = sign_simple(&key, &p2wpkh, "hello").unwrap();
+        assert!(verify_simple(&p2pkh, &signature, "hello").is_err());
+
+        // a simple signature isn't a full one and vice versa
+        let full = sign_full(&key, &p2wpkh, "hello").unwrap();
+        assert!(verify_full(&p2wpkh, &signature, "hello").is_err());
+        assert!(verify_simple(&p2wpkh, &full, "hello").is_err());
+    }
+}
@@ -0,0 +1,106 @@
+use std::{
+    fmt::Display,
+    io::{Read, Write},
+};
+
+// Bitcoin wire-format (de)serialization shared by transactions and network messages
+pub trait Encodable {
+    fn encode<W: Write>(&self, writer: &mut W) -> Result<usize, CodecError>;
+
+    fn serialize(&self) -> Vec<u8> {
+        let mut buffer = Vec::new();
+        self.encode(&mut buffer)
+            .expect("writing into Vec<u8> never fails");
+        buffer
+    }
+}
+
+pub trait Decodable: Sized {
+    fn decode<R: Read>(reader: &mut R) -> Result<Self, CodecError>;
+}
+
+#[non_exhaustive]
+#[derive(Debug)]
+pub enum CodecError {
+    Io(std::io::Error),
+    NonCanonicalVarint { prefix: u8, value: u64 },
+    ChecksumMismatch { expected: [u8; 4], actual: [u8; 4] },
+    LengthMismatch { expected: u64, actual: u64 },
+    InvalidSegwitFlag { flag: u8 },
+    SuperfluousWitness,
+    CommandTooLong { length: usize },
+}
+
+impl Display for CodecError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CodecError::Io(e) => write!(f, "[codec] io error: {}", e),
+            CodecError::NonCanonicalVarint { prefix, value } => write!(
+                f,
+                "[codec] non-canonical varint: {} encoded with prefix {:#04x}",
+                value, prefix
+            ),
+            CodecError::ChecksumMismatch { expected, actual } => write!(
+                f,
+                "[codec] checksum mismatch (expected: {}, actual: {})",
+                hex::encode(expected),
+                hex::encode(actual)
+            ),
+            CodecError::LengthMismatch { expected, actual } => write!(
+                f,
+                "[codec] expected {} bytes but only {} left",
+                expected, actual
+            ),
+            CodecError::InvalidSegwitFlag { flag } => {
+                write!(f, "[codec] invalid segwit flag {:#04x}", flag)
+            }
+            CodecError::SuperfluousWitness => {
+                f.write_str("[codec] segwit marker set but every witness is empty")
+            }
+            CodecError::CommandTooLong { length } => write!(
+                f,
+                "[codec] network command of {} bytes doesn't fit in 12",
+                length
+            ),
+        }
+    }
+}
+
+impl std::error::Error for CodecError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            CodecError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for CodecError {
+    fn from(e: std::io::Error) -> Self {
+        CodecError::Io(e)
+    }
+}
+
+pub fn read_array<const N: usize, R: Read>(reader: &mut R) -> Result<[u8; N], CodecError> {
+    let mut buffer = [0_u8; N];
+    reader.read_exact(&mut buffer)?;
+    Ok(buffer)
+}
+
+// reads through `take` so a bogus length prefix can't trigger a huge allocation
+pub fn read_vec<R: Read>(reader: &mut R, len: u64) -> Result<Vec<u8>, CodecError> {
+    let mut buffer = Vec::new();
+    let read = reader.take(len).read_to_end(&mut buffer)? as u64;
+    if read != len {
+        return Err(CodecError::LengthMismatch {
+            expected: len,
+            actual: read,
+        });
+    }
+    Ok(buffer)
+}
+
+pub fn write_all<W: Write>(writer: &mut W, bytes: &[u8]) -> Result<usize, CodecError> {
+    writer.write_all(bytes)?;
+    Ok(bytes.len())
+}
@@ -1,17 +1,24 @@
-use anyhow::bail;
+use std::borrow::Borrow;
+
+use anyhow::{anyhow, bail};
 use digest::Digest;
 use lazy_static::lazy_static;
-use num_bigint_dig::{BigInt, BigUint, ModInverse, ToBigInt, ToBigUint};
+use num_bigint_dig::{BigInt, BigUint, ModInverse, Sign, ToBigInt};
 use num_integer::Integer;
 use num_traits::{Num, One, Pow, Zero};
+use rand::Rng;
 use ripemd::Ripemd160;
 use sha2::Sha256;
 
-use crate::libs::finite_field::FiniteField;
+use crate::libs::{
+    field_element::FieldElement, finite_field::FiniteField, jacobian::JacobianPoint,
+    math::biguint_to_32_bytes, scalar::Scalar,
+};
 
 use super::{
-    network::{BitcoinNetwork, BITCOIN_MAINNET_PREFIX, BITCOIN_TESTNET_PREFIX},
-    signature::{base58_encode, base58_encode_with_checksum, Signature},
+    address::Address,
+    network::BitcoinNetwork,
+    signature::{RecoverableSignature, SchnorrSignature, Signature},
 };
 
 lazy_static! {
@@ -29,6 +36,11 @@ lazy_static! {
         (*SECP256K1GENS_X).to_bigint().unwrap().clone(),
         (*SECP256K1GENS_Y).to_bigint().unwrap().clone()
     );
+    pub static ref SECP256K1_PRIME: BigInt = BigInt::from_str_radix(
+        "FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFEFFFFFC2F",
+        16u32
+    )
+    .unwrap();
     pub static ref SECP256K1GENS_ORDER: BigUint = BigUint::from_str_radix(
         "FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFEBAAEDCE6AF48A03BBFD25E8CD0364141",
         16u32
@@ -84,9 +96,7 @@ impl Ecc {
     }
 
     fn new_secp256k1(x: BigInt, y: BigInt) -> Self {
-        let prime = 2.to_bigint().unwrap().pow(256u64)
-            - 2.to_bigint().unwrap().pow(32u64)
-            - 977.to_bigint().unwrap();
+        let prime = (*SECP256K1_PRIME).clone();
         Ecc::raw_new(
             BigInt::zero(),
             7.to_bigint().unwrap(),
@@ -95,6 +105,10 @@ impl Ecc {
             Some((*SECP256K1GENS_ORDER).clone()),
         )
     }
+
+    pub fn is_secp256k1(&self) -> bool {
+        self.a.is_zero() && self.b == 7.to_bigint().unwrap() && self.x.prime == *SECP256K1_PRIME
+    }
 }
 
 #[non_exhaustive]
@@ -125,126 +139,400 @@ impl EccPoint {
         }
     }
 
-    fn verify() {
-        todo!()
+    // k * G in constant time through the precomputed generator table, for secret
+    // scalars (private keys and nonces)
+    pub fn mul_generator(k: &Scalar) -> EccPoint {
+        JacobianPoint::mul_generator(k).to_affine()
+    }
+
+    // k * self in constant time, for secret scalars with an arbitrary secp256k1 point
+    pub fn mul_secret(&self, k: &Scalar) -> anyhow::Result<EccPoint> {
+        match JacobianPoint::from_affine(self) {
+            Some(point) => Ok(point.mul(k).to_affine()),
+            None => bail!("[EccPoint::mul_secret] not a secp256k1 point"),
+        }
+    }
+
+    // a * p + b * q with interleaved wNAF and a single doubling chain, using the
+    // precomputed odd multiples of G when p is the generator. Variable time, so only
+    // for public scalars; points off secp256k1 fall back to two `*` overloads.
+    pub fn double_mul(a: &BigUint, p: &EccPoint, b: &BigUint, q: &EccPoint) -> EccPoint {
+        match (JacobianPoint::from_affine(p), JacobianPoint::from_affine(q)) {
+            (Some(p_jacobian), Some(q_jacobian)) => {
+                let order = &(*SECP256K1GENS_ORDER);
+                let a = biguint_to_32_bytes(&(a % order));
+                let b = biguint_to_32_bytes(&(b % order));
+                let res = if *p == *SECP256K1GENS {
+                    JacobianPoint::mul_generator_add_vartime(&a, &b, &q_jacobian)
+                } else {
+                    JacobianPoint::double_mul_vartime(&a, &p_jacobian, &b, &q_jacobian)
+                };
+                res.to_affine()
+            }
+            _ => {
+                let term = |k: &BigUint, point: &EccPoint| match k.is_zero() {
+                    true => EccPoint::PointAtInfinity,
+                    false => k * point,
+                };
+                term(a, p) + term(b, q)
+            }
+        }
     }
 
     pub fn verify_secp256k1(&self, z: &BigUint, sig: &Signature) -> anyhow::Result<()> {
-        let s_inv = &sig
-            .s
-            .clone()
-            .mod_inverse((*SECP256K1GENS_ORDER).clone())
-            .unwrap()
-            .to_biguint()
-            .unwrap();
-        let u = BigUint::modpow(
-            &(z * s_inv),
-            &BigUint::one(),
-            &(*SECP256K1GENS_ORDER).clone(),
-        );
-        let v = BigUint::modpow(
-            &(&sig.r * s_inv),
-            &BigUint::one(),
-            &(*SECP256K1GENS_ORDER).clone(),
-        );
-        // let point = EccPoint::new_secp256k1(px, py);
-        let gen_point = EccPoint::new_secp256k1(
-            (*SECP256K1GENS_X).to_bigint().unwrap().clone(),
-            (*SECP256K1GENS_Y).to_bigint().unwrap().clone(),
-        );
-        let res = u * gen_point + v * self;
-
-        if let EccPoint::Point(point) = res {
-            if point.x.num
-                == sig
-                    .r
-                    .to_bigint()
-                    .unwrap_or_else(|| panic!("Signature.r is negative number"))
+        let order = &(*SECP256K1GENS_ORDER);
+        let point = match JacobianPoint::from_affine(self) {
+            Some(point) if !bool::from(point.is_infinity()) => point,
+            _ => bail!("[EccPoint::verify_secp256k1] not a secp256k1 point"),
+        };
+        for (name, value) in [("r", &sig.r), ("s", &sig.s)] {
+            if value.is_zero() || value >= order {
+                bail!("[EccPoint::verify_secp256k1] {} is out of range", name);
+            }
+        }
+        let s_inv = Scalar::from_biguint(&sig.s).invert();
+        let u = (Scalar::from_biguint(z) * s_inv).to_bytes();
+        let v = (Scalar::from_biguint(&sig.r) * s_inv).to_bytes();
+        let res = JacobianPoint::mul_generator_add_vartime(&u, &v, &point);
+
+        // x(R) mod n == r, where x(R) may also be r + n when that is still below p
+        let r_plus_n = &sig.r + order;
+        if res.has_affine_x(&FieldElement::from_biguint(&sig.r))
+            || (r_plus_n.to_bigint().unwrap() < *SECP256K1_PRIME
+                && res.has_affine_x(&FieldElement::from_biguint(&r_plus_n)))
+        {
+            return Ok(());
+        }
+        bail!("[EccPoint::verify_secp256k1] secp256k1 verification failed")
+    }
+
+    // Q = r^-1 * (s * R - z * G), where R is lifted from r and the recovery id
+    pub fn recover(z: &BigUint, sig: &RecoverableSignature) -> anyhow::Result<EccPoint> {
+        let order = &(*SECP256K1GENS_ORDER);
+        let (r, s) = (&sig.signature.r, &sig.signature.s);
+        for (name, value) in [("r", r), ("s", s)] {
+            if value.is_zero() || value >= order {
+                bail!("[EccPoint::recover] {} is out of range", name);
+            }
+        }
+
+        let x = match sig.recovery_id & 2 {
+            0 => r.clone(),
+            _ => r + order,
+        };
+        if x.to_bigint().unwrap() >= *SECP256K1_PRIME {
+            bail!("[EccPoint::recover] R.x is not a field element");
+        }
+        let mut sec = vec![0x02 | (sig.recovery_id & 1)];
+        sec.extend(biguint_to_32_bytes(&x));
+        let point_r = EccPoint::parse_sec(&sec)
+            .map_err(|_| anyhow!("[EccPoint::recover] no curve point has x == R.x"))?;
+
+        let r_inv = r.clone().mod_inverse(order).unwrap().to_biguint().unwrap();
+        let u = ((order - z % order) * &r_inv) % order;
+        let v = (s * &r_inv) % order;
+        match EccPoint::double_mul(&u, &SECP256K1GENS, &v, &point_r) {
+            EccPoint::PointAtInfinity => bail!("[EccPoint::recover] recovered point at infinity"),
+            point => Ok(point),
+        }
+    }
+
+    // Verifies many (pubkey, z, signature) triples together. The recovery id pins R
+    // down from r, so with random 128-bit a_i the batch holds exactly when
+    // (sum a_i * u_i) * G + sum (a_i * v_i) * P_i - sum a_i * R_i is the point at
+    // infinity, which is a single multi-scalar multiplication. The s values share one
+    // inversion. Malformed entries are reported first; if the combined check fails,
+    // every entry is checked on its own to point at the first failing one. A valid
+    // signature with the wrong recovery id fails like any other.
+    pub fn verify_secp256k1_batch(
+        items: &[(&EccPoint, &BigUint, &RecoverableSignature)],
+    ) -> Result<(), BatchVerifyError> {
+        let order = &(*SECP256K1GENS_ORDER);
+        let in_range = |num: &BigUint| !num.is_zero() && num < order;
+        let s_values: Vec<Scalar> = items
+            .iter()
+            .map(|(_, _, sig)| match in_range(&sig.signature.s) {
+                true => Scalar::from_biguint(&sig.signature.s),
+                false => Scalar::ZERO,
+            })
+            .collect();
+        let s_inverses = Scalar::batch_invert(&s_values);
+
+        let mut entries = Vec::with_capacity(items.len());
+        for (index, ((point, z, sig), s_inv)) in items.iter().zip(s_inverses).enumerate() {
+            let point = match JacobianPoint::from_affine(point) {
+                Some(point) if !bool::from(point.is_infinity()) => point,
+                _ => return Err(BatchVerifyError::InvalidPublicKey { index }),
+            };
+            let r = &sig.signature.r;
+            let x = match sig.recovery_id & 2 {
+                0 => r.clone(),
+                _ => r + order,
+            };
+            if !in_range(r)
+                || !in_range(&sig.signature.s)
+                || x.to_bigint().unwrap() >= *SECP256K1_PRIME
             {
+                return Err(BatchVerifyError::InvalidSignature { index });
+            }
+            // None when no curve point has x == R.x, which only fails the entry itself
+            let point_r =
+                JacobianPoint::lift_x(&FieldElement::from_biguint(&x), sig.recovery_id & 1 == 1);
+            let u = Scalar::from_biguint(z) * s_inv;
+            let v = Scalar::from_biguint(r) * s_inv;
+            entries.push((point, point_r, u, v));
+        }
+
+        if entries.iter().all(|(_, point_r, _, _)| point_r.is_some()) {
+            let mut rng = rand::thread_rng();
+            let mut g_coefficient = Scalar::ZERO;
+            let mut terms = Vec::with_capacity(2 * entries.len());
+            for (point, point_r, u, v) in &entries {
+                let mut a = [0_u8; 32];
+                rng.fill(&mut a[16..]);
+                // never zero, so no entry drops out of the sum
+                a[31] |= 1;
+                let a_scalar = Scalar::from_bytes_reduced(&a);
+                g_coefficient = g_coefficient + a_scalar * *u;
+                terms.push(((a_scalar * *v).to_bytes(), *point));
+                terms.push((a, point_r.expect("checked above").negate()));
+            }
+            let sum =
+                JacobianPoint::multi_mul_generator_add_vartime(&g_coefficient.to_bytes(), &terms);
+            if bool::from(sum.is_infinity()) {
                 return Ok(());
             }
         }
-        bail!("secp256k1 verification failed")
+
+        for (index, (point, point_r, u, v)) in entries.iter().enumerate() {
+            let res = JacobianPoint::mul_generator_add_vartime(&u.to_bytes(), &v.to_bytes(), point);
+            if *point_r != Some(res) {
+                return Err(BatchVerifyError::VerificationFailed { index });
+            }
+        }
+        Ok(())
     }
 
-    pub fn serialize_sec(&self) -> Vec<u8> {
-        if let EccPoint::Point(point) = self {
-            let mut prepend: Vec<u8> = vec![4];
-            let x = point.x.num.to_bytes_be().1;
-            let y = point.y.num.to_bytes_be().1;
-            prepend.extend(x);
-            prepend.extend(y);
-            prepend
-        } else {
-            vec![0, 0]
+    // coordinates are left-padded to 32 bytes, so the output is always 65 bytes
+    pub fn serialize_sec(&self) -> anyhow::Result<Vec<u8>> {
+        let (x, y) = self.sec_coordinates("serialize_sec")?;
+        let mut res = Vec::with_capacity(65);
+        res.push(4);
+        res.extend(x);
+        res.extend(y);
+        Ok(res)
+    }
+
+    // always 33 bytes
+    pub fn serialize_sec_compressed(&self) -> anyhow::Result<Vec<u8>> {
+        let (x, y) = self.sec_coordinates("serialize_sec_compressed")?;
+        let mut res = Vec::with_capacity(33);
+        res.push(if y[31] & 1 == 1 { 3 } else { 2 });
+        res.extend(x);
+        Ok(res)
+    }
+
+    fn sec_coordinates(&self, caller: &str) -> anyhow::Result<([u8; 32], [u8; 32])> {
+        match self {
+            EccPoint::Point(point) => {
+                let to_bytes = |num: &BigInt| {
+                    num.to_biguint()
+                        .filter(|num| num.bits() <= 256)
+                        .map(|num| biguint_to_32_bytes(&num))
+                        .ok_or_else(|| {
+                            anyhow!("[EccPoint::{}] coordinate doesn't fit in 32 bytes", caller)
+                        })
+                };
+                Ok((to_bytes(&point.x.num)?, to_bytes(&point.y.num)?))
+            }
+            EccPoint::PointAtInfinity => {
+                bail!(
+                    "[EccPoint::{}] point at infinity has no SEC encoding",
+                    caller
+                )
+            }
         }
     }
 
-    pub fn serialize_sec_compressed(&self) -> Vec<u8> {
-        if let EccPoint::Point(point) = self {
-            let mut prepend: Vec<u8>;
-            if point.y.num.is_odd() {
-                prepend = vec![3];
-            } else {
-                prepend = vec![2];
+    // Inverse of `serialize_sec`/`serialize_sec_compressed` for secp256k1 points
+    pub fn parse_sec(sec: &[u8]) -> anyhow::Result<EccPoint> {
+        let prime = &(*SECP256K1_PRIME);
+        let (x, y) = match (sec.first(), sec.len()) {
+            (Some(4), 65) => (
+                BigInt::from_bytes_be(Sign::Plus, &sec[1..33]),
+                BigInt::from_bytes_be(Sign::Plus, &sec[33..65]),
+            ),
+            (Some(prefix @ (2 | 3)), 33) => {
+                let x = BigInt::from_bytes_be(Sign::Plus, &sec[1..33]);
+                if &x >= prime {
+                    bail!("[EccPoint::parse_sec] x is not a field element");
+                }
+                // p % 4 == 3, so sqrt(a) == a^((p + 1) / 4) whenever a square root exists
+                let alpha = (&x * &x * &x + 7.to_bigint().unwrap()) % prime;
+                let beta = alpha.modpow(&((prime + BigInt::one()) >> 2), prime);
+                let y = if beta.is_odd() == (*prefix == 3) {
+                    beta
+                } else {
+                    prime - beta
+                };
+                (x, y)
             }
-            let x = point.x.num.to_bytes_be().1;
-            prepend.extend(x);
-            prepend
-        } else {
-            vec![0, 0]
+            _ => bail!(
+                "[EccPoint::parse_sec] invalid SEC encoding of length {}",
+                sec.len()
+            ),
+        };
+
+        // checked here since `Ecc::raw_new` panics on points off the curve
+        if &x >= prime || &y >= prime {
+            bail!("[EccPoint::parse_sec] coordinates are not field elements");
+        }
+        if (&y * &y) % prime != (&x * &x * &x + 7.to_bigint().unwrap()) % prime {
+            bail!("[EccPoint::parse_sec] point is not on secp256k1");
         }
+        Ok(EccPoint::new_secp256k1(x, y))
     }
 
-    pub fn hash_sec_compressed(&self) -> Vec<u8> {
-        hash160(&self.serialize_sec_compressed())
+    pub fn hash_sec_compressed(&self) -> anyhow::Result<Vec<u8>> {
+        Ok(hash160(&self.serialize_sec_compressed()?))
     }
 
-    pub fn hash_sec(&self) -> Vec<u8> {
-        hash160(&self.serialize_sec())
+    pub fn hash_sec(&self) -> anyhow::Result<Vec<u8>> {
+        Ok(hash160(&self.serialize_sec()?))
+    }
+
+    // BIP340 x-only public key
+    pub fn x_only(&self) -> anyhow::Result<[u8; 32]> {
+        let (x, _) = self.sec_coordinates("x_only")?;
+        Ok(x)
+    }
+
+    // the point with this x-coordinate and an even y, as BIP340 reads x-only keys
+    pub fn lift_x(x: &[u8; 32]) -> anyhow::Result<EccPoint> {
+        let mut sec = vec![0x02_u8];
+        sec.extend(x);
+        EccPoint::parse_sec(&sec)
+    }
+
+    pub fn has_odd_y(&self) -> anyhow::Result<bool> {
+        let (_, y) = self.sec_coordinates("has_odd_y")?;
+        Ok(y[31] & 1 == 1)
+    }
+
+    // BIP341 output key without a script tree: Q = lift_x(x(P)) + hash_TapTweak(x(P)) * G
+    pub fn taproot_output_key(&self) -> anyhow::Result<EccPoint> {
+        let x = self.x_only()?;
+        let internal = EccPoint::lift_x(&x)?;
+        let tweak = BigUint::from_bytes_be(&tagged_hash("TapTweak", &x));
+        if tweak.is_zero() || tweak >= *SECP256K1GENS_ORDER {
+            bail!("[EccPoint::taproot_output_key] tweak is out of range");
+        }
+        Ok(internal + &tweak * &(*SECP256K1GENS))
+    }
+
+    // BIP340 verification against the x-only key x(self):
+    // R = s * G - hash_BIP0340/challenge(r || x(P) || m) * P must have an even y and x(R) == r
+    pub fn verify_schnorr(&self, msg: &[u8; 32], sig: &SchnorrSignature) -> anyhow::Result<()> {
+        let order = &(*SECP256K1GENS_ORDER);
+        let px = self.x_only()?;
+        let point = EccPoint::lift_x(&px)?;
+        let r = BigUint::from_bytes_be(&sig.r);
+        let s = BigUint::from_bytes_be(&sig.s);
+        if r.to_bigint().unwrap() >= *SECP256K1_PRIME || &s >= order {
+            bail!("[EccPoint::verify_schnorr] r or s is out of range");
+        }
+
+        let mut challenge = Vec::with_capacity(96);
+        challenge.extend(sig.r);
+        challenge.extend(px);
+        challenge.extend(msg);
+        let e = BigUint::from_bytes_be(&tagged_hash("BIP0340/challenge", &challenge)) % order;
+        let res = EccPoint::double_mul(&s, &SECP256K1GENS, &((order - e) % order), &point);
+        match res {
+            EccPoint::Point(_) if !res.has_odd_y()? && res.x_only()? == sig.r => Ok(()),
+            _ => bail!("[EccPoint::verify_schnorr] schnorr verification failed"),
+        }
     }
 
     fn gernerate_address_from_sec_raw(
         &self,
         compressed: bool,
         network: BitcoinNetwork,
-    ) -> Result<String, std::string::FromUtf8Error> {
-        let sec = if compressed {
-            self.serialize_sec_compressed()
-        } else {
-            self.serialize_sec()
-        };
-        let prefix = match network {
-            BitcoinNetwork::MainNet => BITCOIN_MAINNET_PREFIX,
-            BitcoinNetwork::TestNet => BITCOIN_TESTNET_PREFIX,
-        };
-
-        let mut res = Vec::new();
-        res.push(prefix);
-        res.extend(sec);
-        base58_encode_with_checksum(&res)
+    ) -> anyhow::Result<String> {
+        Ok(Address::p2pkh(self, compressed, network)?.to_string())
     }
 
     pub fn gernerate_address_from_sec_compressed(
         &self,
         network: BitcoinNetwork,
-    ) -> Result<String, std::string::FromUtf8Error> {
+    ) -> anyhow::Result<String> {
         self.gernerate_address_from_sec_raw(true, network)
     }
 
-    pub fn gernerate_address_from_sec(
-        &self,
-        network: BitcoinNetwork,
-    ) -> Result<String, std::string::FromUtf8Error> {
+    pub fn gernerate_address_from_sec(&self, network: BitcoinNetwork) -> anyhow::Result<String> {
         self.gernerate_address_from_sec_raw(false, network)
     }
 }
 
+#[non_exhaustive]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BatchVerifyError {
+    InvalidPublicKey { index: usize },
+    InvalidSignature { index: usize },
+    VerificationFailed { index: usize },
+}
+
+impl BatchVerifyError {
+    // position of the failing entry in the batch
+    pub fn index(&self) -> usize {
+        match self {
+            BatchVerifyError::InvalidPublicKey { index }
+            | BatchVerifyError::InvalidSignature { index }
+            | BatchVerifyError::VerificationFailed { index } => *index,
+        }
+    }
+}
+
+impl std::fmt::Display for BatchVerifyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BatchVerifyError::InvalidPublicKey { index } => write!(
+                f,
+                "[ecc] entry {} has a public key that is not a secp256k1 point",
+                index
+            ),
+            BatchVerifyError::InvalidSignature { index } => {
+                write!(f, "[ecc] entry {} has r or s out of range", index)
+            }
+            BatchVerifyError::VerificationFailed { index } => {
+                write!(f, "[ecc] entry {} failed secp256k1 verification", index)
+            }
+        }
+    }
+}
+
+impl std::error::Error for BatchVerifyError {}
+
 pub fn hash160(plaintext: &[u8]) -> Vec<u8> {
     let slice = Ripemd160::digest(Sha256::digest(plaintext));
     Vec::from_iter(slice)
 }
 
+pub fn hash256(plaintext: &[u8]) -> Vec<u8> {
+    let slice = Sha256::digest(Sha256::digest(plaintext));
+    Vec::from_iter(slice)
+}
+
+// BIP340 tagged hash: sha256(sha256(tag) || sha256(tag) || msg)
+pub fn tagged_hash(tag: &str, msg: &[u8]) -> [u8; 32] {
+    let tag_hash = Sha256::digest(tag.as_bytes());
+    let mut hasher = Sha256::new();
+    hasher.update(tag_hash);
+    hasher.update(tag_hash);
+    hasher.update(msg);
+    hasher.finalize().into()
+}
+
 overloading!((lhs : EccPoint) + (rhs : EccPoint) => EccPoint as {
     match (lhs, rhs) {
         (EccPoint::PointAtInfinity, EccPoint::PointAtInfinity) => {
@@ -274,14 +562,14 @@ overloading!((lhs : EccPoint) + (rhs : EccPoint) => EccPoint as {
                     let s = (lx * lx * three + a_wrap) / (ly * &two);
                     let new_x = &s * &s - lx * &two;
                     let new_y = s * (lx - &new_x) - ly.clone();
-                    EccPoint::Point(Box::new(Ecc::new(l.a.clone(), l.b.clone(), new_x, new_y)))
+                    EccPoint::Point(Box::new(Ecc::raw_new(l.a.clone(), l.b.clone(), new_x, new_y, l.order.clone())))
                 }
                 (lx, _, rx, _) if lx == rx => EccPoint::PointAtInfinity,
                 (lx, ly, rx, ry) => {
                     let s = (ry - ly) / (rx - lx);
                     let new_x = &s * &s - (lx + rx);
                     let new_y = s * (lx - &new_x) - ly.clone();
-                    EccPoint::Point(Box::new(Ecc::new(l.a.clone(), l.b.clone(), new_x, new_y)))
+                    EccPoint::Point(Box::new(Ecc::raw_new(l.a.clone(), l.b.clone(), new_x, new_y, l.order.clone())))
                 }
             }
         }
@@ -289,91 +577,67 @@ overloading!((lhs : EccPoint) + (rhs : EccPoint) => EccPoint as {
 });
 
 overloading!(^(lhs : BigUint) * (rhs : EccPoint) => EccPoint as {
-    match (lhs, rhs) {
-        (l, _) if l == BigUint::zero() => {
-            panic!("Cannot multiple zero or minus value to EccPoint")
-        }
-        (l, r) if l == BigUint::one() => {
-            r.clone()
-        }
-        (l, r) => {
-            let mut coef;
-            if let EccPoint::Point(ecc) = r.clone() {
-                if let Some(order) = &ecc.order {
-                    coef = l.clone() % order;
-                } else {
-                    coef = l.clone();
-                }
-            } else {
-                coef = l.clone();
-            }
-            let mut current = r.clone();
-            let mut res = EccPoint::PointAtInfinity;
-            loop {
-                match &coef {
-                    c if c > &BigUint::zero() => {
-                        if (c & BigUint::one()) > BigUint::zero() {
-                            res = res + current.clone();
-                        }
-                        current = &current + &current;
-                        coef >>= 1;
-                    }
-                    _ => { break; }
-                }
-            }
-            res
-        }
-    }
+    scalar_mul(&lhs, rhs)
 });
 
 overloading!((lhs : BigUint) * ^(rhs : EccPoint) => EccPoint as {
-    match (lhs, rhs) {
-        (l, _) if l == &BigUint::zero() => {
-            panic!("Cannot multiple zero or minus value to EccPoint")
-        }
-        (l, r) if l == &BigUint::one() => {
-            r.clone()
-        }
-        (l, r) => {
-            let mut coef;
-            if let EccPoint::Point(ecc) = r.clone() {
-                if let Some(order) = &ecc.order {
-                    coef = l.clone() % order;
-                } else {
-                    coef = l.clone();
-                }
-            } else {
-                coef = l.clone();
-            }
-            let mut current = r.clone();
-            let mut res = EccPoint::PointAtInfinity;
-            loop {
-                match &coef {
-                    c if c > &BigUint::zero() => {
-                        if (c & BigUint::one()) > BigUint::zero() {
-                            res = res + current.clone();
-                        }
-                        current = &current + &current;
-                        coef >>= 1;
-                    }
-                    _ => { break; }
-                }
-            }
-            res
+    scalar_mul(lhs, rhs)
+});
+
+// Variable-time double-and-add, for public scalars only; secrets go through
+// `EccPoint::mul_generator`. secp256k1 points use Jacobian coordinates and are
+// converted back to affine once, other curves use the affine `Add` overload.
+fn scalar_mul<P: Borrow<EccPoint>>(scalar: &BigUint, point: P) -> EccPoint {
+    let point = point.borrow();
+    if scalar.is_zero() {
+        panic!("Cannot multiple zero or minus value to EccPoint")
+    }
+    if scalar.is_one() {
+        return point.clone();
+    }
+    let coef = match point {
+        EccPoint::Point(ecc) => match &ecc.order {
+            Some(order) => scalar % order,
+            None => scalar.clone(),
+        },
+        EccPoint::PointAtInfinity => scalar.clone(),
+    };
+
+    if let Some(jacobian) = JacobianPoint::from_affine(point) {
+        return jacobian.mul_vartime(&coef.to_bytes_be()).to_affine();
+    }
+
+    let mut coef = coef;
+    let mut current = point.clone();
+    let mut res = EccPoint::PointAtInfinity;
+    while coef > BigUint::zero() {
+        if (&coef & BigUint::one()) > BigUint::zero() {
+            res = res + current.clone();
         }
+        current = &current + &current;
+        coef >>= 1;
     }
-});
+    res
+}
 
 #[cfg(test)]
 mod tests {
-    use std::fmt::format;
-
-    use num_bigint_dig::{BigInt, BigUint, ModInverse, ToBigInt, ToBigUint};
+    use num_bigint_dig::{BigInt, BigUint, ModInverse, RandBigInt, ToBigInt, ToBigUint};
     use num_traits::{Num, One};
+    use rand::{rngs::StdRng, SeedableRng};
 
-    use crate::libs::{finite_field::FiniteField, key::Key, signature::Signature};
+    use crate::libs::{
+        finite_field::FiniteField,
+        key::Key,
+        math::biguint_to_32_bytes,
+        scalar::Scalar,
+        signature::{RecoverableSignature, Signature},
+    };
 
-    use super::{Ecc, EccPoint, SECP256K1GENS_ORDER, SECP256K1GENS_X, SECP256K1GENS_Y};
+    use super::{
+        BatchVerifyError, EccPoint, SECP256K1GENS, SECP256K1GENS_ORDER, SECP256K1GENS_X,
+        SECP256K1GENS_Y,
+    };
 
     #[test]
     fn secp256k1_test1() {
@@ -636,12 +900,309 @@ mod tests {
         point.verify_secp256k1(&z, &sig).unwrap();
     }
 
+    #[test]
+    fn secp256k1_signature_r_overflow_test() {
+        let order = &(*SECP256K1GENS_ORDER);
+        // a nonce whose R has x >= n turns up with probability ~2^-128, so pick such
+        // an R and build the public key around it: P = r^-1 * (s * R - z * G).
+        // x == n is on the curve too, but would give r == 0
+        let point_r = (1_u32..)
+            .find_map(|i| {
+                let mut sec = vec![0x02];
+                sec.extend(biguint_to_32_bytes(&(order + i)));
+                EccPoint::parse_sec(&sec).ok()
+            })
+            .unwrap();
+        let x = BigUint::from_bytes_be(&point_r.x_only().unwrap());
+        let r = &x - order;
+        let (z, s) = (BigUint::from(0xdead_beef_u32), BigUint::from(7_u32));
+        let r_inv = r.clone().mod_inverse(order).unwrap().to_biguint().unwrap();
+        let point = &r_inv * &EccPoint::double_mul(&s, &point_r, &(order - &z), &SECP256K1GENS);
+
+        let sig = Signature::new(r, s.clone());
+        point.verify_secp256k1(&z, &sig).unwrap();
+        let recoverable = RecoverableSignature::new(sig.clone(), 2).unwrap();
+        assert_eq!(EccPoint::recover(&z, &recoverable).unwrap(), point);
+        assert_eq!(
+            EccPoint::verify_secp256k1_batch(&[(&point, &z, &recoverable)]),
+            Ok(())
+        );
+        // x(R) itself is not a valid r
+        assert!(point.verify_secp256k1(&z, &Signature::new(x, s)).is_err());
+    }
+
+    #[test]
+    fn parse_sec_test() {
+        for secret in [5000_u32, 2019, 0xdeadbeef] {
+            let k = Key::new(secret.to_biguint().unwrap());
+            let uncompressed = k.point.serialize_sec().unwrap();
+            let compressed = k.point.serialize_sec_compressed().unwrap();
+            assert_eq!(EccPoint::parse_sec(&uncompressed).unwrap(), k.point);
+            assert_eq!(EccPoint::parse_sec(&compressed).unwrap(), k.point);
+        }
+    }
+
+    #[test]
+    fn parse_sec_invalid_test() {
+        let k = Key::new(5000.to_biguint().unwrap());
+        let mut sec = k.point.serialize_sec().unwrap();
+        // flip a bit of y
+        sec[64] ^= 0x01;
+        assert!(EccPoint::parse_sec(&sec).is_err());
+
+        // x = 5 has no square root of x^3 + 7
+        let mut compressed = vec![0x02_u8];
+        compressed.extend([0_u8; 31]);
+        compressed.push(5);
+        assert!(EccPoint::parse_sec(&compressed).is_err());
+        compressed[32] = 6;
+        assert!(EccPoint::parse_sec(&compressed).is_ok());
+
+        // x >= p
+        let mut too_big = vec![0x03_u8];
+        too_big.extend([0xff_u8; 32]);
+        assert!(EccPoint::parse_sec(&too_big).is_err());
+
+        assert!(EccPoint::parse_sec(&[]).is_err());
+        assert!(EccPoint::parse_sec(&k.point.serialize_sec().unwrap()[..64]).is_err());
+        let mut wrong_prefix = k.point.serialize_sec_compressed().unwrap();
+        wrong_prefix[0] = 0x04;
+        assert!(EccPoint::parse_sec(&wrong_prefix).is_err());
+    }
+
     #[test]
     fn sec_test() {
         let k = Key::new(5000.to_biguint().unwrap());
-        let sec = k.point.serialize_sec();
+        let sec = k.point.serialize_sec().unwrap();
         assert_eq!(sec.len(), 65);
         let lhs = hex::encode(sec);
         assert_eq!(lhs, "04ffe558e388852f0120e46af2d1b370f85854a8eb0841811ece0e3e03d282d57c315dc72890a4f10a1481c031b03b351b0dc79901ca18a00cf009dbdb157a1d10")
     }
+
+    #[test]
+    fn sec_fixed_width_test() {
+        // x = 6 is on the curve and needs 31 leading zero bytes
+        let mut compressed = vec![0x02_u8];
+        compressed.extend([0_u8; 31]);
+        compressed.push(6);
+        let point = EccPoint::parse_sec(&compressed).unwrap();
+        assert_eq!(point.serialize_sec_compressed().unwrap(), compressed);
+        let uncompressed = point.serialize_sec().unwrap();
+        assert_eq!(uncompressed.len(), 65);
+        assert_eq!(&uncompressed[1..33], &compressed[1..]);
+        assert_eq!(EccPoint::parse_sec(&uncompressed).unwrap(), point);
+    }
+
+    #[test]
+    fn sec_point_at_infinity_test() {
+        assert!(EccPoint::PointAtInfinity.serialize_sec().is_err());
+        assert!(EccPoint::PointAtInfinity
+            .serialize_sec_compressed()
+            .is_err());
+        assert!(EccPoint::PointAtInfinity.hash_sec().is_err());
+    }
+
+    #[test]
+    fn sec_roundtrip_random_keys() {
+        let mut rng = StdRng::seed_from_u64(12);
+        // 153 * G has an x coordinate starting with a zero byte
+        let edge_keys = [
+            BigUint::one(),
+            &(*SECP256K1GENS_ORDER) - 1_u32,
+            BigUint::from(153_u32),
+        ];
+        let random_keys =
+            (0..300).map(|_| rng.gen_biguint_range(&BigUint::one(), &SECP256K1GENS_ORDER));
+        for secret in edge_keys.into_iter().chain(random_keys) {
+            let k = Key::new(secret);
+            let uncompressed = k.point.serialize_sec().unwrap();
+            let compressed = k.point.serialize_sec_compressed().unwrap();
+            assert_eq!(uncompressed.len(), 65);
+            assert_eq!(compressed.len(), 33);
+            assert_eq!(EccPoint::parse_sec(&uncompressed).unwrap(), k.point);
+            assert_eq!(EccPoint::parse_sec(&compressed).unwrap(), k.point);
+        }
+        let compressed = Key::new(BigUint::from(153_u32))
+            .point
+            .serialize_sec_compressed()
+            .unwrap();
+        assert_eq!(compressed[1], 0);
+    }
+
+    #[test]
+    fn constant_time_mul_matches_vartime() {
+        let mut rng = rand::thread_rng();
+        let g = &(*SECP256K1GENS);
+        for _ in 0..8 {
+            let k = rng.gen_biguint_range(&BigUint::one(), &SECP256K1GENS_ORDER);
+            let scalar = Scalar::from_biguint(&k);
+            assert_eq!(EccPoint::mul_generator(&scalar), &k * g);
+            let p = EccPoint::mul_generator(&Scalar::from_biguint(&BigUint::from(7_u32)));
+            assert_eq!(p.mul_secret(&scalar).unwrap(), &k * &p);
+        }
+        assert_eq!(
+            EccPoint::mul_generator(&Scalar::ZERO),
+            EccPoint::PointAtInfinity
+        );
+        assert_eq!(
+            EccPoint::PointAtInfinity.mul_secret(&Scalar::ONE).unwrap(),
+            EccPoint::PointAtInfinity
+        );
+        let other_curve = EccPoint::new(
+            0.to_bigint().unwrap(),
+            7.to_bigint().unwrap(),
+            FiniteField::new(47.to_bigint().unwrap(), 223.to_bigint().unwrap()),
+            FiniteField::new(71.to_bigint().unwrap(), 223.to_bigint().unwrap()),
+        );
+        assert!(other_curve.mul_secret(&Scalar::ONE).is_err());
+    }
+
+    #[test]
+    fn verify_batch_test() {
+        let mut rng = rand::thread_rng();
+        let keys: Vec<Key> = (0..6)
+            .map(|_| Key::new(rng.gen_biguint_range(&BigUint::one(), &SECP256K1GENS_ORDER)))
+            .collect();
+        let zs: Vec<BigUint> = (0..6).map(|_| rng.gen_biguint(256)).collect();
+        let mut sigs: Vec<RecoverableSignature> = keys
+            .iter()
+            .zip(&zs)
+            .map(|(key, z)| key.sign_recoverable(z.clone()))
+            .collect();
+        // high-s signatures verify as well, as in `verify_secp256k1`; negating s
+        // negates R, so the y parity in the recovery id flips
+        sigs[1].signature.s = &(*SECP256K1GENS_ORDER) - &sigs[1].signature.s;
+        sigs[1].recovery_id ^= 1;
+
+        fn batch<'a>(
+            keys: &'a [Key],
+            zs: &'a [BigUint],
+            sigs: &'a [RecoverableSignature],
+        ) -> Vec<(&'a EccPoint, &'a BigUint, &'a RecoverableSignature)> {
+            keys.iter()
+                .zip(zs)
+                .zip(sigs)
+                .map(|((key, z), sig)| (&key.point, z, sig))
+                .collect()
+        }
+        assert_eq!(
+            EccPoint::verify_secp256k1_batch(&batch(&keys, &zs, &sigs)),
+            Ok(())
+        );
+        assert_eq!(EccPoint::verify_secp256k1_batch(&[]), Ok(()));
+
+        let mut tampered = sigs.clone();
+        tampered[3].signature.s += 1_u32;
+        tampered[4].signature.r += 1_u32;
+        let err = EccPoint::verify_secp256k1_batch(&batch(&keys, &zs, &tampered)).unwrap_err();
+        assert_eq!(err, BatchVerifyError::VerificationFailed { index: 3 });
+        assert_eq!(err.index(), 3);
+
+        // a valid signature with the other y parity
+        let mut wrong_parity = sigs.clone();
+        wrong_parity[2].recovery_id ^= 1;
+        assert_eq!(
+            EccPoint::verify_secp256k1_batch(&batch(&keys, &zs, &wrong_parity)),
+            Err(BatchVerifyError::VerificationFailed { index: 2 })
+        );
+
+        let mut out_of_range = sigs.clone();
+        out_of_range[2].signature.s = BigUint::from(0_u32);
+        assert_eq!(
+            EccPoint::verify_secp256k1_batch(&batch(&keys, &zs, &out_of_range)),
+            Err(BatchVerifyError::InvalidSignature { index: 2 })
+        );
+        // r + n is not a field element for any r of an honest signature
+        let mut overflow = sigs.clone();
+        overflow[0].recovery_id |= 2;
+        assert_eq!(
+            EccPoint::verify_secp256k1_batch(&batch(&keys, &zs, &overflow)),
+            Err(BatchVerifyError::InvalidSignature { index: 0 })
+        );
+
+        let mut items = batch(&keys, &zs, &sigs);
+        items[5].0 = &EccPoint::PointAtInfinity;
+        assert_eq!(
+            EccPoint::verify_secp256k1_batch(&items),
+            Err(BatchVerifyError::InvalidPublicKey { index: 5 })
+        );
+
+        // agrees with the single-signature path
+        for (point, z, sig) in batch(&keys, &zs, &tampered) {
+            let single = point.verify_secp256k1(z, &sig.signature).is_ok();
+            assert_eq!(
+                EccPoint::verify_secp256k1_batch(&[(point, z, sig)]).is_ok(),
+                single
+            );
+        }
+    }
+
+    #[test]
+    fn double_mul_test() {
+        let mut rng = rand::thread_rng();
+        let g = &(*SECP256K1GENS);
+        let p = Key::new(rng.gen_biguint_range(&BigUint::one(), &SECP256K1GENS_ORDER)).point;
+        for _ in 0..4 {
+            let a = rng.gen_biguint_range(&BigUint::one(), &SECP256K1GENS_ORDER);
+            let b = rng.gen_biguint_range(&BigUint::one(), &SECP256K1GENS_ORDER);
+            assert_eq!(EccPoint::double_mul(&a, g, &b, &p), &a * g + &b * &p);
+            assert_eq!(EccPoint::double_mul(&a, &p, &b, g), &a * &p + &b * g);
+            // scalars at or above the order are reduced first
+            let a_big = &a + &(*SECP256K1GENS_ORDER);
+            assert_eq!(EccPoint::double_mul(&a_big, g, &b, &p), &a * g + &b * &p);
+        }
+        assert_eq!(
+            EccPoint::double_mul(&BigUint::from(0_u32), g, &BigUint::from(0_u32), &p),
+            EccPoint::PointAtInfinity
+        );
+
+        // other curves take the affine path
+        let prime = 223.to_bigint().unwrap();
+        let point = EccPoint::new(
+            0.to_bigint().unwrap(),
+            7.to_bigint().unwrap(),
+            FiniteField::new(47.to_bigint().unwrap(), prime.clone()),
+            FiniteField::new(71.to_bigint().unwrap(), prime),
+        );
+        let (a, b) = (BigUint::from(3_u32), BigUint::from(0_u32));
+        assert_eq!(EccPoint::double_mul(&a, &point, &b, &point), &a * &point);
+    }
+
+    #[test]
+    fn recover_test() {
+        let mut rng = rand::thread_rng();
+        for _ in 0..8 {
+            let key = Key::new(rng.gen_biguint_range(&BigUint::one(), &SECP256K1GENS_ORDER));
+            let z = rng.gen_biguint(256);
+            let sig = key.sign_recoverable(z.clone());
+            assert!(sig.recovery_id < 2);
+            assert_eq!(EccPoint::recover(&z, &sig).unwrap(), key.point);
+
+            // the other parity gives another key, for which the signature is still valid
+            let flipped =
+                RecoverableSignature::new(sig.signature.clone(), sig.recovery_id ^ 1).unwrap();
+            let other = EccPoint::recover(&z, &flipped).unwrap();
+            assert_ne!(other, key.point);
+            other.verify_secp256k1(&z, &sig.signature).unwrap();
+            // a different message recovers a different key
+            let tampered = EccPoint::recover(&(&z + 1_u32), &sig).unwrap();
+            assert_ne!(tampered, key.point);
+        }
+    }
+
+    #[test]
+    fn recover_error_test() {
+        let order = &(*SECP256K1GENS_ORDER);
+        let r = order - 1_u32;
+        let s = BigUint::from(1_u32);
+        let z = BigUint::from(1_u32);
+        // r + n is above p
+        let overflow = RecoverableSignature::new(Signature::new(r.clone(), s.clone()), 2).unwrap();
+        assert!(EccPoint::recover(&z, &overflow).is_err());
+        let zero_r = RecoverableSignature::new(Signature::new(BigUint::from(0_u32), s), 0).unwrap();
+        assert!(EccPoint::recover(&z, &zero_r).is_err());
+        let big_s = RecoverableSignature::new(Signature::new(r, order.clone()), 0).unwrap();
+        assert!(EccPoint::recover(&z, &big_s).is_err());
+        assert!(RecoverableSignature::new(Signature::new(1_u32.into(), 1_u32.into()), 4).is_err());
+    }
 }
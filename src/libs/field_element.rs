@@ -0,0 +1,206 @@
+use num_bigint_dig::BigUint;
+use subtle::{Choice, ConditionallySelectable, ConstantTimeEq};
+
+use super::limbs::{self, Limbs};
+
+// p = 2^256 - 2^32 - 977
+const P: Limbs = [
+    0xFFFFFFFEFFFFFC2F,
+    0xFFFFFFFFFFFFFFFF,
+    0xFFFFFFFFFFFFFFFF,
+    0xFFFFFFFFFFFFFFFF,
+];
+// 2^256 - p
+const P_COMPLEMENT: u64 = 0x1000003D1;
+const P_MINUS_2: Limbs = [
+    0xFFFFFFFEFFFFFC2D,
+    0xFFFFFFFFFFFFFFFF,
+    0xFFFFFFFFFFFFFFFF,
+    0xFFFFFFFFFFFFFFFF,
+];
+// (p + 1) / 4, since p % 4 == 3
+const P_PLUS_1_DIV_4: Limbs = [
+    0xFFFFFFFFBFFFFF0C,
+    0xFFFFFFFFFFFFFFFF,
+    0xFFFFFFFFFFFFFFFF,
+    0x3FFFFFFFFFFFFFFF,
+];
+
+// Element of the secp256k1 base field, always kept fully reduced below p.
+// Arithmetic runs in constant time with respect to the values.
+#[derive(Debug, Clone, Copy)]
+pub struct FieldElement(Limbs);
+
+impl FieldElement {
+    pub const ZERO: FieldElement = FieldElement([0; 4]);
+    pub const ONE: FieldElement = FieldElement([1, 0, 0, 0]);
+
+    // None if `bytes` is not below p
+    pub fn from_bytes(bytes: &[u8; 32]) -> Option<FieldElement> {
+        let limbs = limbs::from_bytes_be(bytes);
+        let (_, borrow) = limbs::sub(&limbs, &P);
+        if borrow == 1 {
+            Some(FieldElement(limbs))
+        } else {
+            None
+        }
+    }
+
+    pub fn to_bytes(&self) -> [u8; 32] {
+        limbs::to_bytes_be(&self.0)
+    }
+
+    // reduces modulo p
+    pub fn from_biguint(num: &BigUint) -> FieldElement {
+        let reduced = num % BigUint::from_bytes_be(&limbs::to_bytes_be(&P));
+        let mut bytes = [0_u8; 32];
+        let raw = reduced.to_bytes_be();
+        bytes[32 - raw.len()..].copy_from_slice(&raw);
+        FieldElement(limbs::from_bytes_be(&bytes))
+    }
+
+    pub fn to_biguint(&self) -> BigUint {
+        BigUint::from_bytes_be(&self.to_bytes())
+    }
+
+    pub fn is_zero(&self) -> Choice {
+        self.ct_eq(&FieldElement::ZERO)
+    }
+
+    pub fn is_odd(&self) -> Choice {
+        Choice::from((self.0[0] & 1) as u8)
+    }
+
+    pub fn negate(&self) -> FieldElement {
+        FieldElement(limbs::sub_mod(&[0; 4], &self.0, &P))
+    }
+
+    pub fn double(&self) -> FieldElement {
+        self + self
+    }
+
+    pub fn square(&self) -> FieldElement {
+        self * self
+    }
+
+    fn pow(&self, exp: &Limbs) -> FieldElement {
+        limbs::pow_public(self, FieldElement::ONE, exp, |a, b| a * b)
+    }
+
+    // Fermat inversion; zero maps to zero
+    pub fn invert(&self) -> FieldElement {
+        self.pow(&P_MINUS_2)
+    }
+
+    // the root returned may be either of the two, use `is_odd` to pick one
+    pub fn sqrt(&self) -> Option<FieldElement> {
+        let root = self.pow(&P_PLUS_1_DIV_4);
+        if bool::from(root.square().ct_eq(self)) {
+            Some(root)
+        } else {
+            None
+        }
+    }
+}
+
+impl ConstantTimeEq for FieldElement {
+    fn ct_eq(&self, other: &Self) -> Choice {
+        self.0.ct_eq(&other.0)
+    }
+}
+
+impl ConditionallySelectable for FieldElement {
+    fn conditional_select(a: &Self, b: &Self, choice: Choice) -> Self {
+        FieldElement(limbs::select(&a.0, &b.0, choice))
+    }
+}
+
+impl PartialEq for FieldElement {
+    fn eq(&self, other: &Self) -> bool {
+        self.ct_eq(other).into()
+    }
+}
+
+impl Eq for FieldElement {}
+
+overloading!((lhs : FieldElement) + (rhs : FieldElement) => FieldElement as {
+    FieldElement(limbs::add_mod(&lhs.0, &rhs.0, &P))
+});
+overloading!((lhs : FieldElement) - (rhs : FieldElement) => FieldElement as {
+    FieldElement(limbs::sub_mod(&lhs.0, &rhs.0, &P))
+});
+overloading!((lhs : FieldElement) * (rhs : FieldElement) => FieldElement as {
+    FieldElement(limbs::reduce_wide_single(&limbs::mul_wide(&lhs.0, &rhs.0), P_COMPLEMENT, &P))
+});
+
+#[cfg(test)]
+mod tests {
+    use num_bigint_dig::{BigUint, RandBigInt};
+    use num_traits::{Num, One};
+
+    use crate::libs::ecc::{SECP256K1GENS_X, SECP256K1GENS_Y};
+
+    use super::FieldElement;
+
+    fn prime() -> BigUint {
+        BigUint::from_str_radix(
+            "FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFEFFFFFC2F",
+            16,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn field_element_arithmetic_test() {
+        let p = prime();
+        let mut rng = rand::thread_rng();
+        let mut samples = vec![BigUint::from(0_u32), BigUint::one(), &p - 1_u32, &p - 2_u32];
+        samples.extend((0..32).map(|_| rng.gen_biguint_below(&p)));
+
+        for a in &samples {
+            for b in samples.iter().take(8) {
+                let fa = FieldElement::from_biguint(a);
+                let fb = FieldElement::from_biguint(b);
+                assert_eq!((fa + fb).to_biguint(), (a + b) % &p);
+                assert_eq!((fa - fb).to_biguint(), (a + &p - b) % &p);
+                assert_eq!((fa * fb).to_biguint(), (a * b) % &p);
+            }
+            let fa = FieldElement::from_biguint(a);
+            assert_eq!(fa.negate().to_biguint(), (&p - a) % &p);
+            if a != &BigUint::from(0_u32) {
+                assert_eq!(fa * fa.invert(), FieldElement::ONE);
+            }
+        }
+        assert_eq!(FieldElement::ZERO.invert(), FieldElement::ZERO);
+    }
+
+    #[test]
+    fn field_element_bytes_test() {
+        let p = prime();
+        let mut bytes = [0_u8; 32];
+        bytes.copy_from_slice(&p.to_bytes_be());
+        assert!(FieldElement::from_bytes(&bytes).is_none());
+        bytes[31] -= 1;
+        let max = FieldElement::from_bytes(&bytes).unwrap();
+        assert_eq!(max.to_bytes(), bytes);
+        assert_eq!(max + FieldElement::ONE, FieldElement::ZERO);
+        assert_eq!(FieldElement::from_biguint(&p), FieldElement::ZERO);
+    }
+
+    #[test]
+    fn field_element_sqrt_test() {
+        // the generator is on y^2 = x^3 + 7
+        let x = FieldElement::from_biguint(&SECP256K1GENS_X);
+        let y = FieldElement::from_biguint(&SECP256K1GENS_Y);
+        let seven = FieldElement::from_biguint(&BigUint::from(7_u32));
+        let alpha = x.square() * x + seven;
+        assert_eq!(y.square(), alpha);
+        let root = alpha.sqrt().unwrap();
+        assert!(root == y || root == y.negate());
+        assert!(bool::from(y.is_odd()) != bool::from(y.negate().is_odd()));
+
+        // x = 5 is not on the curve
+        let five = FieldElement::from_biguint(&BigUint::from(5_u32));
+        assert!((five.square() * five + seven).sqrt().is_none());
+    }
+}
@@ -94,8 +94,8 @@ mod tests {
 
     use num_bigint::ToBigInt;
 
-    use num_bigint_dig::{BigInt, ModInverse};
-    use num_traits::{One, Pow};
+    use num_bigint_dig::BigInt;
+    use num_traits::Pow;
 
     use super::FiniteField;
 
@@ -0,0 +1,1765 @@
+use std::fmt::Display;
+
+use digest::Digest;
+use num_bigint_dig::BigUint;
+use ripemd::Ripemd160;
+use sha1::Sha1;
+use sha2::Sha256;
+
+use super::{
+    ecc::{hash160, hash256, EccPoint},
+    math::biguint_to_32_bytes,
+    script::{next_op, Command, Opcode, Script},
+    signature::{SchnorrSignature, Signature},
+    transaction::{Transaction, TransactionOutput, SIGHASH_DEFAULT},
+};
+
+pub const MAX_SCRIPT_ELEMENT_SIZE: usize = 520;
+pub const MAX_OPS_PER_SCRIPT: usize = 201;
+pub const MAX_STACK_SIZE: usize = 1000;
+pub const MAX_PUBKEYS_PER_MULTISIG: usize = 20;
+// arithmetic inputs are limited to 4 bytes, results may overflow into 5
+pub const MAX_NUM_SIZE: usize = 4;
+// BIP65/BIP112 operands may use 5 bytes so that they cover the whole u32 range
+pub const MAX_LOCKTIME_NUM_SIZE: usize = 5;
+// locktimes below this are block heights, from it on unix timestamps
+pub const LOCKTIME_THRESHOLD: u32 = 500_000_000;
+pub const SEQUENCE_FINAL: u32 = 0xffff_ffff;
+// BIP68 relative locktime fields of an input's sequence number
+pub const SEQUENCE_LOCKTIME_DISABLE_FLAG: i64 = 1 << 31;
+pub const SEQUENCE_LOCKTIME_TYPE_FLAG: i64 = 1 << 22;
+pub const SEQUENCE_LOCKTIME_MASK: i64 = 0x0000_ffff;
+
+// Which signature hash OP_CHECKSIG commits to: legacy scripts or BIP143 witness v0 scripts
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SigVersion {
+    Base,
+    WitnessV0,
+}
+
+// Supplies the digest a signature commits to, so the interpreter doesn't need to know
+// about the spending transaction
+pub trait SignatureChecker {
+    // `signature` still carries its trailing sighash-type byte
+    fn check_sig(
+        &self,
+        signature: &[u8],
+        pubkey: &[u8],
+        script_code: &[u8],
+        sig_version: SigVersion,
+    ) -> bool;
+
+    // BIP340 signature of a taproot key path spend against the 32-byte output key
+    fn check_schnorr_sig(&self, signature: &[u8], pubkey: &[u8]) -> bool;
+
+    // BIP65 OP_CHECKLOCKTIMEVERIFY, without a spending transaction it always fails
+    fn check_lock_time(&self, _lock_time: i64) -> bool {
+        false
+    }
+
+    // BIP112 OP_CHECKSEQUENCEVERIFY, without a spending transaction it always fails
+    fn check_sequence(&self, _sequence: i64) -> bool {
+        false
+    }
+}
+
+// Fixed message digest `z`, e.g. a precomputed SIGHASH_ALL of the spending transaction
+impl SignatureChecker for BigUint {
+    fn check_sig(
+        &self,
+        signature: &[u8],
+        pubkey: &[u8],
+        _script_code: &[u8],
+        _sig_version: SigVersion,
+    ) -> bool {
+        match signature.split_last() {
+            Some((_, der)) => verify_signature(self, der, pubkey),
+            None => false,
+        }
+    }
+
+    fn check_schnorr_sig(&self, signature: &[u8], pubkey: &[u8]) -> bool {
+        self.bits() <= 256
+            && verify_schnorr_signature(&biguint_to_32_bytes(self), signature, pubkey)
+    }
+}
+
+// Computes the sighash of `input_index` using the hash type appended to each signature.
+// Segwit signatures also commit to the spent amounts, so those need `with_prevouts`.
+pub struct TransactionSignatureChecker<'a> {
+    transaction: &'a Transaction,
+    input_index: usize,
+    prevouts: &'a [TransactionOutput],
+}
+
+impl<'a> TransactionSignatureChecker<'a> {
+    pub fn new(transaction: &'a Transaction, input_index: usize) -> Self {
+        Self::with_prevouts(transaction, input_index, &[])
+    }
+
+    // `prevouts` are the outputs spent by every input of `transaction`, in input order
+    pub fn with_prevouts(
+        transaction: &'a Transaction,
+        input_index: usize,
+        prevouts: &'a [TransactionOutput],
+    ) -> Self {
+        TransactionSignatureChecker {
+            transaction,
+            input_index,
+            prevouts,
+        }
+    }
+}
+
+impl SignatureChecker for TransactionSignatureChecker<'_> {
+    fn check_sig(
+        &self,
+        signature: &[u8],
+        pubkey: &[u8],
+        script_code: &[u8],
+        sig_version: SigVersion,
+    ) -> bool {
+        let (sighash_type, der) = match signature.split_last() {
+            Some((sighash_type, der)) => (*sighash_type as u32, der),
+            None => return false,
+        };
+        let z = match sig_version {
+            SigVersion::Base => {
+                self.transaction
+                    .sig_hash(self.input_index, script_code, sighash_type)
+            }
+            SigVersion::WitnessV0 => {
+                let amount = match self.prevouts.get(self.input_index) {
+                    Some(prevout) => prevout.amount(),
+                    None => return false,
+                };
+                match self.transaction.sig_hash_segwit_v0(
+                    self.input_index,
+                    script_code,
+                    amount,
+                    sighash_type,
+                ) {
+                    Ok(z) => z,
+                    Err(_) => return false,
+                }
+            }
+        };
+        verify_signature(&z, der, pubkey)
+    }
+
+    // 64 bytes sign with SIGHASH_DEFAULT, a 65th byte gives any other hash type
+    fn check_schnorr_sig(&self, signature: &[u8], pubkey: &[u8]) -> bool {
+        let (sighash_type, signature) = match signature.len() {
+            64 => (SIGHASH_DEFAULT, signature),
+            65 if signature[64] != SIGHASH_DEFAULT as u8 => {
+                (signature[64] as u32, &signature[..64])
+            }
+            _ => return false,
+        };
+        match self
+            .transaction
+            .sig_hash_taproot(self.input_index, self.prevouts, sighash_type)
+        {
+            Ok(msg) => verify_schnorr_signature(&msg, signature, pubkey),
+            Err(_) => false,
+        }
+    }
+
+    fn check_lock_time(&self, lock_time: i64) -> bool {
+        let input = match self.transaction.inputs().get(self.input_index) {
+            Some(input) => input,
+            None => return false,
+        };
+        let tx_lock_time = self.transaction.locktime().to_u32() as i64;
+        // heights can't be compared with timestamps
+        let threshold = LOCKTIME_THRESHOLD as i64;
+        if (lock_time < threshold) != (tx_lock_time < threshold) || lock_time > tx_lock_time {
+            return false;
+        }
+        // a final input ignores the transaction's locktime altogether
+        input.sequence() != SEQUENCE_FINAL
+    }
+
+    fn check_sequence(&self, sequence: i64) -> bool {
+        let input = match self.transaction.inputs().get(self.input_index) {
+            Some(input) => input,
+            None => return false,
+        };
+        let tx_sequence = input.sequence() as i64;
+        // relative locktimes only apply from version 2 and to inputs that enable them
+        if self.transaction.version().to_u32() < 2
+            || tx_sequence & SEQUENCE_LOCKTIME_DISABLE_FLAG != 0
+        {
+            return false;
+        }
+        let mask = SEQUENCE_LOCKTIME_TYPE_FLAG | SEQUENCE_LOCKTIME_MASK;
+        let (tx_sequence, sequence) = (tx_sequence & mask, sequence & mask);
+        // blocks can't be compared with 512-second units
+        (sequence < SEQUENCE_LOCKTIME_TYPE_FLAG) == (tx_sequence < SEQUENCE_LOCKTIME_TYPE_FLAG)
+            && sequence <= tx_sequence
+    }
+}
+// BIP66: a signature must be empty or strict DER followed by the hash type byte, anything
+// else fails the script instead of just evaluating to false
+fn check_signature_encoding(signature: &[u8]) -> Result<(), ScriptErrorKind> {
+    match signature.split_last() {
+        None => Ok(()),
+        Some((_, der)) => Signature::parse_der(der)
+            .map(|_| ())
+            .map_err(|_| ScriptErrorKind::SigDer),
+    }
+}
+
+fn verify_signature(z: &BigUint, der: &[u8], pubkey: &[u8]) -> bool {
+    match (Signature::parse_der(der), EccPoint::parse_sec(pubkey)) {
+        (Ok(sig), Ok(point)) => point.verify_secp256k1(z, &sig).is_ok(),
+        _ => false,
+    }
+}
+
+fn verify_schnorr_signature(msg: &[u8; 32], signature: &[u8], pubkey: &[u8]) -> bool {
+    let point = match <[u8; 32]>::try_from(pubkey) {
+        Ok(x) => EccPoint::lift_x(&x),
+        Err(_) => return false,
+    };
+    match (SchnorrSignature::from_bytes(signature), point) {
+        (Ok(sig), Ok(point)) => point.verify_schnorr(msg, &sig).is_ok(),
+        _ => false,
+    }
+}
+
+#[non_exhaustive]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScriptErrorKind {
+    StackUnderflow,
+    InvalidStackOperation,
+    UnbalancedConditional,
+    DisabledOpcode,
+    BadOpcode,
+    OpReturn,
+    VerifyFailed,
+    NumberOverflow,
+    PushSize,
+    StackSize,
+    OpCount,
+    PubkeyCount,
+    SigCount,
+    SigNullDummy,
+    SigDer,
+    SigPushOnly,
+    NegativeLocktime,
+    UnsatisfiedLocktime,
+    BadRedeemScript,
+    EvalFalse,
+    CleanStack,
+    WitnessProgramWrongLength,
+    WitnessProgramWitnessEmpty,
+    WitnessProgramMismatch,
+    WitnessMalleated,
+    WitnessMalleatedP2sh,
+    WitnessUnexpected,
+    SchnorrSig,
+    UnsupportedWitness,
+}
+
+impl Display for ScriptErrorKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let reason = match self {
+            ScriptErrorKind::StackUnderflow => "not enough elements on the stack",
+            ScriptErrorKind::InvalidStackOperation => "stack index out of range",
+            ScriptErrorKind::UnbalancedConditional => "unbalanced conditional",
+            ScriptErrorKind::DisabledOpcode => "opcode is disabled",
+            ScriptErrorKind::BadOpcode => "opcode is reserved or not valid here",
+            ScriptErrorKind::OpReturn => "OP_RETURN was executed",
+            ScriptErrorKind::VerifyFailed => "verification failed",
+            ScriptErrorKind::NumberOverflow => "script number exceeds 4 bytes",
+            ScriptErrorKind::PushSize => "push exceeds 520 bytes",
+            ScriptErrorKind::StackSize => "stack exceeds 1000 elements",
+            ScriptErrorKind::OpCount => "script exceeds 201 opcodes",
+            ScriptErrorKind::PubkeyCount => "pubkey count out of range",
+            ScriptErrorKind::SigCount => "signature count out of range",
+            ScriptErrorKind::SigNullDummy => "multisig dummy element must be empty",
+            ScriptErrorKind::SigDer => "signature is not strict DER",
+            ScriptErrorKind::SigPushOnly => "scriptSig must only push data",
+            ScriptErrorKind::NegativeLocktime => "negative locktime",
+            ScriptErrorKind::UnsatisfiedLocktime => "locktime requirement not satisfied",
+            ScriptErrorKind::BadRedeemScript => "redeem script can't be parsed",
+            ScriptErrorKind::EvalFalse => "script evaluated to false",
+            ScriptErrorKind::CleanStack => "witness script must leave exactly one element",
+            ScriptErrorKind::WitnessProgramWrongLength => "witness program has the wrong length",
+            ScriptErrorKind::WitnessProgramWitnessEmpty => "witness is empty",
+            ScriptErrorKind::WitnessProgramMismatch => "witness doesn't match the program",
+            ScriptErrorKind::WitnessMalleated => "native witness spend with a scriptSig",
+            ScriptErrorKind::WitnessMalleatedP2sh => {
+                "P2SH witness spend with more than the redeem script in scriptSig"
+            }
+            ScriptErrorKind::WitnessUnexpected => "witness given for a non-witness output",
+            ScriptErrorKind::SchnorrSig => "invalid Schnorr signature",
+            ScriptErrorKind::UnsupportedWitness => {
+                "taproot script path and annex spends are not supported"
+            }
+        };
+        f.write_str(reason)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScriptError {
+    pub opcode: Option<Opcode>,
+    pub kind: ScriptErrorKind,
+}
+
+impl ScriptError {
+    pub fn new(opcode: Option<Opcode>, kind: ScriptErrorKind) -> Self {
+        ScriptError { opcode, kind }
+    }
+}
+
+impl Display for ScriptError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.opcode {
+            Some(op) => write!(f, "[script] {} failed: {}", op, self.kind),
+            None => write!(f, "[script] {}", self.kind),
+        }
+    }
+}
+
+impl std::error::Error for ScriptError {}
+
+pub fn encode_num(num: i64) -> Vec<u8> {
+    let mut res = Vec::new();
+    let negative = num < 0;
+    let mut abs = num.unsigned_abs();
+    while abs > 0 {
+        res.push((abs & 0xff) as u8);
+        abs >>= 8;
+    }
+    // the sign lives in the most significant bit of the last byte
+    if let Some(last) = res.last_mut() {
+        if *last & 0x80 != 0 {
+            res.push(if negative { 0x80 } else { 0x00 });
+        } else if negative {
+            *last |= 0x80;
+        }
+    }
+    res
+}
+
+pub fn decode_num(bytes: &[u8]) -> Result<i64, ScriptErrorKind> {
+    decode_num_with_size(bytes, MAX_NUM_SIZE)
+}
+
+fn decode_num_with_size(bytes: &[u8], max_size: usize) -> Result<i64, ScriptErrorKind> {
+    if bytes.len() > max_size {
+        return Err(ScriptErrorKind::NumberOverflow);
+    }
+    let mut res: i64 = 0;
+    for (i, byte) in bytes.iter().enumerate() {
+        res |= (*byte as i64) << (8 * i);
+    }
+    if let Some(last) = bytes.last() {
+        if last & 0x80 != 0 {
+            let sign_bit = 0x80_i64 << (8 * (bytes.len() - 1));
+            return Ok(-(res & !sign_bit));
+        }
+    }
+    Ok(res)
+}
+
+// empty vectors, zeros and negative zero are false
+pub fn cast_to_bool(bytes: &[u8]) -> bool {
+    match bytes.split_last() {
+        Some((last, rest)) => rest.iter().any(|b| *b != 0) || (last & 0x7f) != 0,
+        None => false,
+    }
+}
+
+fn is_disabled(op: Opcode) -> bool {
+    matches!(
+        op,
+        Opcode::OP_CAT
+            | Opcode::OP_SUBSTR
+            | Opcode::OP_LEFT
+            | Opcode::OP_RIGHT
+            | Opcode::OP_INVERT
+            | Opcode::OP_AND
+            | Opcode::OP_OR
+            | Opcode::OP_XOR
+            | Opcode::OP_2MUL
+            | Opcode::OP_2DIV
+            | Opcode::OP_MUL
+            | Opcode::OP_DIV
+            | Opcode::OP_MOD
+            | Opcode::OP_LSHIFT
+            | Opcode::OP_RSHIFT
+    )
+}
+
+pub struct Interpreter<'a, C: SignatureChecker> {
+    checker: &'a C,
+    sig_version: SigVersion,
+    stack: Vec<Vec<u8>>,
+    alt_stack: Vec<Vec<u8>>,
+    op_count: usize,
+}
+
+impl<'a, C: SignatureChecker> Interpreter<'a, C> {
+    pub fn new(checker: &'a C) -> Self {
+        Self::with_sig_version(checker, SigVersion::Base)
+    }
+
+    pub fn with_sig_version(checker: &'a C, sig_version: SigVersion) -> Self {
+        Interpreter {
+            checker,
+            sig_version,
+            stack: Vec::new(),
+            alt_stack: Vec::new(),
+            op_count: 0,
+        }
+    }
+
+    pub fn stack(&self) -> &[Vec<u8>] {
+        &self.stack
+    }
+
+    pub fn evaluate(&mut self, script: &Script) -> Result<(), ScriptError> {
+        let mut exec: Vec<bool> = Vec::new();
+        // byte offset just past the last executed OP_CODESEPARATOR
+        let mut code_separator = 0;
+        let mut pc = 0;
+        self.op_count = 0;
+        self.alt_stack.clear();
+
+        let raw = script.as_bytes();
+        for command in script.commands() {
+            let executing = !exec.contains(&false);
+            pc = next_op(raw, pc).map_or(raw.len(), |(_, end)| end);
+            match command {
+                Command::Data(data) => {
+                    if data.len() > MAX_SCRIPT_ELEMENT_SIZE {
+                        return Err(ScriptError::new(None, ScriptErrorKind::PushSize));
+                    }
+                    if executing {
+                        self.stack.push(data.clone());
+                    }
+                }
+                Command::Op(op) => {
+                    let op = *op;
+                    let fail = |kind| ScriptError::new(Some(op), kind);
+                    if op.to_u8() > Opcode::OP_16.to_u8() {
+                        self.count_ops(1).map_err(fail)?;
+                    }
+                    // these fail even inside an unexecuted branch
+                    if is_disabled(op) {
+                        return Err(fail(ScriptErrorKind::DisabledOpcode));
+                    }
+                    if matches!(op, Opcode::OP_VERIF | Opcode::OP_VERNOTIF) {
+                        return Err(fail(ScriptErrorKind::BadOpcode));
+                    }
+
+                    match op {
+                        Opcode::OP_IF | Opcode::OP_NOTIF => {
+                            let mut value = false;
+                            if executing {
+                                value = cast_to_bool(&self.pop().map_err(fail)?);
+                                if op == Opcode::OP_NOTIF {
+                                    value = !value;
+                                }
+                            }
+                            exec.push(value);
+                        }
+                        Opcode::OP_ELSE => match exec.last_mut() {
+                            Some(last) => *last = !*last,
+                            None => return Err(fail(ScriptErrorKind::UnbalancedConditional)),
+                        },
+                        Opcode::OP_ENDIF => {
+                            exec.pop()
+                                .ok_or_else(|| fail(ScriptErrorKind::UnbalancedConditional))?;
+                        }
+                        Opcode::OP_CODESEPARATOR if executing => code_separator = pc,
+                        _ if executing => {
+                            let script_code = &raw[code_separator..];
+                            self.execute(op, script_code).map_err(fail)?;
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            if self.stack.len() + self.alt_stack.len() > MAX_STACK_SIZE {
+                return Err(ScriptError::new(None, ScriptErrorKind::StackSize));
+            }
+        }
+
+        if !exec.is_empty() {
+            return Err(ScriptError::new(
+                None,
+                ScriptErrorKind::UnbalancedConditional,
+            ));
+        }
+        Ok(())
+    }
+
+    fn count_ops(&mut self, n: usize) -> Result<(), ScriptErrorKind> {
+        self.op_count += n;
+        if self.op_count > MAX_OPS_PER_SCRIPT {
+            return Err(ScriptErrorKind::OpCount);
+        }
+        Ok(())
+    }
+
+    fn pop(&mut self) -> Result<Vec<u8>, ScriptErrorKind> {
+        self.stack.pop().ok_or(ScriptErrorKind::StackUnderflow)
+    }
+
+    fn pop_num(&mut self) -> Result<i64, ScriptErrorKind> {
+        decode_num(&self.pop()?)
+    }
+
+    fn push_num(&mut self, num: i64) {
+        self.stack.push(encode_num(num));
+    }
+
+    fn push_bool(&mut self, value: bool) {
+        self.push_num(value as i64);
+    }
+
+    // n-th element from the top, 1-indexed
+    fn peek(&self, n: usize) -> Result<&Vec<u8>, ScriptErrorKind> {
+        if n == 0 || self.stack.len() < n {
+            return Err(ScriptErrorKind::StackUnderflow);
+        }
+        Ok(&self.stack[self.stack.len() - n])
+    }
+
+    fn require(&self, n: usize) -> Result<(), ScriptErrorKind> {
+        if self.stack.len() < n {
+            return Err(ScriptErrorKind::StackUnderflow);
+        }
+        Ok(())
+    }
+
+    fn verify(&mut self) -> Result<(), ScriptErrorKind> {
+        if cast_to_bool(&self.pop()?) {
+            Ok(())
+        } else {
+            Err(ScriptErrorKind::VerifyFailed)
+        }
+    }
+
+    fn check_sig(&mut self, script_code: &[u8]) -> Result<bool, ScriptErrorKind> {
+        let pubkey = self.pop()?;
+        let signature = self.pop()?;
+        check_signature_encoding(&signature)?;
+        let script_code = self.script_code(script_code, std::slice::from_ref(&signature));
+        Ok(!signature.is_empty()
+            && self
+                .checker
+                .check_sig(&signature, &pubkey, &script_code, self.sig_version))
+    }
+
+    // <dummy> <sig1>..<sigm> m <pubkey1>..<pubkeyn> n OP_CHECKMULTISIG
+    fn check_multisig(&mut self, script_code: &[u8]) -> Result<bool, ScriptErrorKind> {
+        let n = self.pop_num()?;
+        if n < 0 || n as usize > MAX_PUBKEYS_PER_MULTISIG {
+            return Err(ScriptErrorKind::PubkeyCount);
+        }
+        self.count_ops(n as usize)?;
+        self.require(n as usize)?;
+        let pubkeys = self.stack.split_off(self.stack.len() - n as usize);
+
+        let m = self.pop_num()?;
+        if m < 0 || m > n {
+            return Err(ScriptErrorKind::SigCount);
+        }
+        self.require(m as usize)?;
+        let signatures = self.stack.split_off(self.stack.len() - m as usize);
+
+        // the original implementation pops one element too many, BIP147 requires it be empty
+        if !self.pop()?.is_empty() {
+            return Err(ScriptErrorKind::SigNullDummy);
+        }
+
+        let script_code = self.script_code(script_code, &signatures);
+        // signatures must match pubkeys in the same order, each pubkey is tried at most once.
+        // Like Bitcoin Core, give up once fewer keys than signatures remain, so the encoding
+        // of a signature that is never tried isn't checked
+        let mut keys = pubkeys.iter();
+        for (idx, signature) in signatures.iter().enumerate() {
+            loop {
+                if signatures.len() - idx > keys.len() {
+                    return Ok(false);
+                }
+                check_signature_encoding(signature)?;
+                let pubkey = match keys.next() {
+                    Some(pubkey) => pubkey,
+                    None => return Ok(false),
+                };
+                if !signature.is_empty()
+                    && self
+                        .checker
+                        .check_sig(signature, pubkey, &script_code, self.sig_version)
+                {
+                    break;
+                }
+            }
+        }
+        Ok(true)
+    }
+
+    // legacy sighash removes the signatures themselves from the script code, BIP143 doesn't
+    fn script_code(&self, script_code: &[u8], signatures: &[Vec<u8>]) -> Vec<u8> {
+        match self.sig_version {
+            SigVersion::Base => remove_signatures(script_code, signatures),
+            SigVersion::WitnessV0 => script_code.to_vec(),
+        }
+    }
+
+    fn execute(&mut self, op: Opcode, script_code: &[u8]) -> Result<(), ScriptErrorKind> {
+        match op {
+            // constants
+            Opcode::OP_0 => self.stack.push(Vec::new()),
+            Opcode::OP_1NEGATE => self.push_num(-1),
+            op if (Opcode::OP_1.to_u8()..=Opcode::OP_16.to_u8()).contains(&op.to_u8()) => {
+                self.push_num((op.to_u8() - Opcode::OP_1.to_u8() + 1) as i64)
+            }
+
+            // flow control
+            Opcode::OP_NOP
+            | Opcode::OP_NOP1
+            | Opcode::OP_NOP4
+            | Opcode::OP_NOP5
+            | Opcode::OP_NOP6
+            | Opcode::OP_NOP7
+            | Opcode::OP_NOP8
+            | Opcode::OP_NOP9
+            | Opcode::OP_NOP10 => {}
+            Opcode::OP_VERIFY => self.verify()?,
+            Opcode::OP_RETURN => return Err(ScriptErrorKind::OpReturn),
+
+            // locktime, the operand is left on the stack
+            Opcode::OP_CHECKLOCKTIMEVERIFY => {
+                let lock_time = decode_num_with_size(self.peek(1)?, MAX_LOCKTIME_NUM_SIZE)?;
+                if lock_time < 0 {
+                    return Err(ScriptErrorKind::NegativeLocktime);
+                }
+                if !self.checker.check_lock_time(lock_time) {
+                    return Err(ScriptErrorKind::UnsatisfiedLocktime);
+                }
+            }
+            Opcode::OP_CHECKSEQUENCEVERIFY => {
+                let sequence = decode_num_with_size(self.peek(1)?, MAX_LOCKTIME_NUM_SIZE)?;
+                if sequence < 0 {
+                    return Err(ScriptErrorKind::NegativeLocktime);
+                }
+                // with the disable flag set the opcode stays a NOP for future soft forks
+                if sequence & SEQUENCE_LOCKTIME_DISABLE_FLAG == 0
+                    && !self.checker.check_sequence(sequence)
+                {
+                    return Err(ScriptErrorKind::UnsatisfiedLocktime);
+                }
+            }
+
+            // stack
+            Opcode::OP_TOALTSTACK => {
+                let top = self.pop()?;
+                self.alt_stack.push(top);
+            }
+            Opcode::OP_FROMALTSTACK => {
+                let top = self
+                    .alt_stack
+                    .pop()
+                    .ok_or(ScriptErrorKind::InvalidStackOperation)?;
+                self.stack.push(top);
+            }
+            Opcode::OP_2DROP => {
+                self.require(2)?;
+                self.stack.truncate(self.stack.len() - 2);
+            }
+            Opcode::OP_2DUP => {
+                let (a, b) = (self.peek(2)?.clone(), self.peek(1)?.clone());
+                self.stack.extend([a, b]);
+            }
+            Opcode::OP_3DUP => {
+                let (a, b, c) = (
+                    self.peek(3)?.clone(),
+                    self.peek(2)?.clone(),
+                    self.peek(1)?.clone(),
+                );
+                self.stack.extend([a, b, c]);
+            }
+            Opcode::OP_2OVER => {
+                let (a, b) = (self.peek(4)?.clone(), self.peek(3)?.clone());
+                self.stack.extend([a, b]);
+            }
+            Opcode::OP_2ROT => {
+                self.require(6)?;
+                let idx = self.stack.len() - 6;
+                let moved: Vec<Vec<u8>> = self.stack.drain(idx..idx + 2).collect();
+                self.stack.extend(moved);
+            }
+            Opcode::OP_2SWAP => {
+                self.require(4)?;
+                let len = self.stack.len();
+                self.stack.swap(len - 4, len - 2);
+                self.stack.swap(len - 3, len - 1);
+            }
+            Opcode::OP_IFDUP => {
+                let top = self.peek(1)?.clone();
+                if cast_to_bool(&top) {
+                    self.stack.push(top);
+                }
+            }
+            Opcode::OP_DEPTH => self.push_num(self.stack.len() as i64),
+            Opcode::OP_DROP => {
+                self.pop()?;
+            }
+            Opcode::OP_DUP => {
+                let top = self.peek(1)?.clone();
+                self.stack.push(top);
+            }
+            Opcode::OP_NIP => {
+                self.require(2)?;
+                let len = self.stack.len();
+                self.stack.remove(len - 2);
+            }
+            Opcode::OP_OVER => {
+                let second = self.peek(2)?.clone();
+                self.stack.push(second);
+            }
+            Opcode::OP_PICK | Opcode::OP_ROLL => {
+                let n = self.pop_num()?;
+                if n < 0 || n as usize >= self.stack.len() {
+                    return Err(ScriptErrorKind::InvalidStackOperation);
+                }
+                let idx = self.stack.len() - 1 - n as usize;
+                let item = if op == Opcode::OP_PICK {
+                    self.stack[idx].clone()
+                } else {
+                    self.stack.remove(idx)
+                };
+                self.stack.push(item);
+            }
+            Opcode::OP_ROT => {
+                self.require(3)?;
+                let idx = self.stack.len() - 3;
+                let item = self.stack.remove(idx);
+                self.stack.push(item);
+            }
+            Opcode::OP_SWAP => {
+                self.require(2)?;
+                let len = self.stack.len();
+                self.stack.swap(len - 2, len - 1);
+            }
+            Opcode::OP_TUCK => {
+                self.require(2)?;
+                let top = self.peek(1)?.clone();
+                let len = self.stack.len();
+                self.stack.insert(len - 2, top);
+            }
+
+            // splice
+            Opcode::OP_SIZE => {
+                let size = self.peek(1)?.len();
+                self.push_num(size as i64);
+            }
+
+            // bitwise logic
+            Opcode::OP_EQUAL | Opcode::OP_EQUALVERIFY => {
+                let (b, a) = (self.pop()?, self.pop()?);
+                self.push_bool(a == b);
+                if op == Opcode::OP_EQUALVERIFY {
+                    self.verify()?;
+                }
+            }
+
+            // arithmetic
+            Opcode::OP_1ADD
+            | Opcode::OP_1SUB
+            | Opcode::OP_NEGATE
+            | Opcode::OP_ABS
+            | Opcode::OP_NOT
+            | Opcode::OP_0NOTEQUAL => {
+                let a = self.pop_num()?;
+                let res = match op {
+                    Opcode::OP_1ADD => a + 1,
+                    Opcode::OP_1SUB => a - 1,
+                    Opcode::OP_NEGATE => -a,
+                    Opcode::OP_ABS => a.abs(),
+                    Opcode::OP_NOT => (a == 0) as i64,
+                    _ => (a != 0) as i64,
+                };
+                self.push_num(res);
+            }
+            Opcode::OP_ADD
+            | Opcode::OP_SUB
+            | Opcode::OP_BOOLAND
+            | Opcode::OP_BOOLOR
+            | Opcode::OP_NUMEQUAL
+            | Opcode::OP_NUMEQUALVERIFY
+            | Opcode::OP_NUMNOTEQUAL
+            | Opcode::OP_LESSTHAN
+            | Opcode::OP_GREATERTHAN
+            | Opcode::OP_LESSTHANOREQUAL
+            | Opcode::OP_GREATERTHANOREQUAL
+            | Opcode::OP_MIN
+            | Opcode::OP_MAX => {
+                let (b, a) = (self.pop_num()?, self.pop_num()?);
+                let res = match op {
+                    Opcode::OP_ADD => a + b,
+                    Opcode::OP_SUB => a - b,
+                    Opcode::OP_BOOLAND => (a != 0 && b != 0) as i64,
+                    Opcode::OP_BOOLOR => (a != 0 || b != 0) as i64,
+                    Opcode::OP_NUMEQUAL | Opcode::OP_NUMEQUALVERIFY => (a == b) as i64,
+                    Opcode::OP_NUMNOTEQUAL => (a != b) as i64,
+                    Opcode::OP_LESSTHAN => (a < b) as i64,
+                    Opcode::OP_GREATERTHAN => (a > b) as i64,
+                    Opcode::OP_LESSTHANOREQUAL => (a <= b) as i64,
+                    Opcode::OP_GREATERTHANOREQUAL => (a >= b) as i64,
+                    Opcode::OP_MIN => a.min(b),
+                    _ => a.max(b),
+                };
+                self.push_num(res);
+                if op == Opcode::OP_NUMEQUALVERIFY {
+                    self.verify()?;
+                }
+            }
+            Opcode::OP_WITHIN => {
+                let (max, min, x) = (self.pop_num()?, self.pop_num()?, self.pop_num()?);
+                self.push_bool(min <= x && x < max);
+            }
+
+            // crypto
+            Opcode::OP_RIPEMD160 => {
+                let top = self.pop()?;
+                self.stack.push(Ripemd160::digest(top).to_vec());
+            }
+            Opcode::OP_SHA1 => {
+                let top = self.pop()?;
+                self.stack.push(Sha1::digest(top).to_vec());
+            }
+            Opcode::OP_SHA256 => {
+                let top = self.pop()?;
+                self.stack.push(Sha256::digest(top).to_vec());
+            }
+            Opcode::OP_HASH160 => {
+                let top = self.pop()?;
+                self.stack.push(hash160(&top));
+            }
+            Opcode::OP_HASH256 => {
+                let top = self.pop()?;
+                self.stack.push(hash256(&top));
+            }
+            Opcode::OP_CHECKSIG | Opcode::OP_CHECKSIGVERIFY => {
+                let valid = self.check_sig(script_code)?;
+                self.push_bool(valid);
+                if op == Opcode::OP_CHECKSIGVERIFY {
+                    self.verify()?;
+                }
+            }
+            Opcode::OP_CHECKMULTISIG | Opcode::OP_CHECKMULTISIGVERIFY => {
+                let valid = self.check_multisig(script_code)?;
+                self.push_bool(valid);
+                if op == Opcode::OP_CHECKMULTISIGVERIFY {
+                    self.verify()?;
+                }
+            }
+
+            _ => return Err(ScriptErrorKind::BadOpcode),
+        }
+        Ok(())
+    }
+}
+
+// FindAndDelete: each signature is removed wherever its minimal push starts on an opcode
+// boundary, so the same bytes pushed with a longer PUSHDATA encoding stay signed
+fn remove_signatures(script_code: &[u8], signatures: &[Vec<u8>]) -> Vec<u8> {
+    let mut script_code = script_code.to_vec();
+    for signature in signatures {
+        let push = Script::new(vec![Command::Data(signature.clone())]).raw_serialize();
+        let mut result = Vec::with_capacity(script_code.len());
+        let mut pc = 0;
+        loop {
+            while script_code[pc..].starts_with(&push) {
+                pc += push.len();
+            }
+            match next_op(&script_code, pc) {
+                Some((_, end)) => {
+                    result.extend(&script_code[pc..end]);
+                    pc = end;
+                }
+                None => {
+                    result.extend(&script_code[pc..]);
+                    break;
+                }
+            }
+        }
+        script_code = result;
+    }
+    script_code
+}
+
+fn check_top(stack: &[Vec<u8>]) -> Result<(), ScriptError> {
+    match stack.last() {
+        Some(top) if cast_to_bool(top) => Ok(()),
+        _ => Err(ScriptError::new(None, ScriptErrorKind::EvalFalse)),
+    }
+}
+
+// Runs scriptSig then scriptPubKey on a shared stack; the spend is valid when the
+// top of the final stack is true. Pay-to-script-hash outputs additionally run the
+// redeem script pushed last by scriptSig (BIP16)
+pub fn verify_script<C: SignatureChecker>(
+    script_sig: &Script,
+    script_pubkey: &Script,
+    checker: &C,
+) -> Result<(), ScriptError> {
+    verify_script_with_witness(script_sig, script_pubkey, &[], checker)
+}
+
+// `verify_script` for inputs carrying a witness: native and P2SH-wrapped witness programs
+// (BIP141) run v0 P2WPKH/P2WSH and v1 taproot key path spends (BIP341). Taproot script
+// path spends and witnesses with an annex are not implemented and fail closed with
+// `UnsupportedWitness`. Unknown witness versions are left unencumbered for future soft
+// forks.
+pub fn verify_script_with_witness<C: SignatureChecker>(
+    script_sig: &Script,
+    script_pubkey: &Script,
+    witness: &[Vec<u8>],
+    checker: &C,
+) -> Result<(), ScriptError> {
+    let mut interpreter = Interpreter::new(checker);
+    interpreter.evaluate(script_sig)?;
+    let stack_copy = interpreter.stack.clone();
+    interpreter.evaluate(script_pubkey)?;
+    check_top(interpreter.stack())?;
+
+    let mut has_witness_program = false;
+    if let Some((version, program)) = script_pubkey.witness_version_and_program() {
+        has_witness_program = true;
+        if !script_sig.commands().is_empty() {
+            return Err(ScriptError::new(None, ScriptErrorKind::WitnessMalleated));
+        }
+        verify_witness_program(version, program, witness, false, checker)?;
+    }
+
+    if script_pubkey.is_p2sh() {
+        if !script_sig.is_push_only() {
+            return Err(ScriptError::new(None, ScriptErrorKind::SigPushOnly));
+        }
+        interpreter.stack = stack_copy;
+        let raw = interpreter
+            .stack
+            .pop()
+            .ok_or(ScriptError::new(None, ScriptErrorKind::BadRedeemScript))?;
+        let redeem_script = Script::parse_raw(&raw)
+            .map_err(|_| ScriptError::new(None, ScriptErrorKind::BadRedeemScript))?;
+        interpreter.evaluate(&redeem_script)?;
+        check_top(interpreter.stack())?;
+
+        if let Some((version, program)) = redeem_script.witness_version_and_program() {
+            has_witness_program = true;
+            // exactly the canonical push of the redeem script, byte for byte
+            let push = Script::new(vec![Command::Data(raw.clone())]);
+            if script_sig.as_bytes() != push.as_bytes() {
+                return Err(ScriptError::new(
+                    None,
+                    ScriptErrorKind::WitnessMalleatedP2sh,
+                ));
+            }
+            verify_witness_program(version, program, witness, true, checker)?;
+        }
+    }
+
+    if !has_witness_program && !witness.is_empty() {
+        return Err(ScriptError::new(None, ScriptErrorKind::WitnessUnexpected));
+    }
+    Ok(())
+}
+
+fn verify_witness_program<C: SignatureChecker>(
+    version: u8,
+    program: &[u8],
+    witness: &[Vec<u8>],
+    is_p2sh: bool,
+    checker: &C,
+) -> Result<(), ScriptError> {
+    let error = |kind| ScriptError::new(None, kind);
+    let (script, stack) = match (version, program.len()) {
+        // P2WPKH: <signature> <pubkey> against the equivalent P2PKH script
+        (0, 20) => {
+            if witness.len() != 2 {
+                return Err(error(ScriptErrorKind::WitnessProgramMismatch));
+            }
+            (Script::p2pkh(program), witness.to_vec())
+        }
+        // P2WSH: the last item is the witness script, committed to by its sha256
+        (0, 32) => {
+            let (witness_script, stack) = witness
+                .split_last()
+                .ok_or(error(ScriptErrorKind::WitnessProgramWitnessEmpty))?;
+            if Sha256::digest(witness_script)[..] != *program {
+                return Err(error(ScriptErrorKind::WitnessProgramMismatch));
+            }
+            let script = Script::parse_raw(witness_script)
+                .map_err(|_| error(ScriptErrorKind::BadRedeemScript))?;
+            (script, stack.to_vec())
+        }
+        (0, _) => return Err(error(ScriptErrorKind::WitnessProgramWrongLength)),
+        // taproot only applies to native outputs. Only the key path without an annex is
+        // supported, i.e. a witness of exactly one signature; anything longer is either a
+        // script path spend or carries an annex, and is rejected rather than let through
+        (1, 32) if !is_p2sh => {
+            return match witness {
+                [] => Err(error(ScriptErrorKind::WitnessProgramWitnessEmpty)),
+                [signature] if checker.check_schnorr_sig(signature, program) => Ok(()),
+                [_] => Err(error(ScriptErrorKind::SchnorrSig)),
+                _ => Err(error(ScriptErrorKind::UnsupportedWitness)),
+            };
+        }
+        _ => return Ok(()),
+    };
+
+    if stack
+        .iter()
+        .any(|item| item.len() > MAX_SCRIPT_ELEMENT_SIZE)
+    {
+        return Err(error(ScriptErrorKind::PushSize));
+    }
+    let mut interpreter = Interpreter::with_sig_version(checker, SigVersion::WitnessV0);
+    interpreter.stack = stack;
+    interpreter.evaluate(&script)?;
+    if interpreter.stack.len() != 1 {
+        return Err(error(ScriptErrorKind::CleanStack));
+    }
+    check_top(interpreter.stack())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+
+    use num_bigint_dig::BigUint;
+    use num_traits::{Num, Zero};
+
+    use digest::Digest;
+    use sha2::Sha256;
+
+    use crate::libs::{
+        ecc::hash160,
+        key::Key,
+        math::biguint_to_32_bytes,
+        script::{Command, Opcode, Script},
+        transaction::{Transaction, TransactionInput, TransactionLocktime, TransactionVersion},
+    };
+
+    use super::{
+        decode_num, encode_num, verify_script, verify_script_with_witness, ScriptError,
+        ScriptErrorKind, SigVersion, SignatureChecker, TransactionSignatureChecker,
+    };
+
+    fn ops(commands: &[Opcode]) -> Script {
+        Script::new(commands.iter().map(|op| Command::Op(*op)).collect())
+    }
+
+    #[test]
+    fn script_num_test() {
+        for num in [
+            0_i64, 1, -1, 127, -127, 128, -128, 255, 256, -32768, 2147483647,
+        ] {
+            assert_eq!(decode_num(&encode_num(num)).unwrap(), num);
+        }
+        assert_eq!(encode_num(-1), vec![0x81]);
+        assert_eq!(encode_num(128), vec![0x80, 0x00]);
+        assert_eq!(encode_num(-128), vec![0x80, 0x80]);
+        assert_eq!(
+            decode_num(&[0, 0, 0, 0, 1]),
+            Err(ScriptErrorKind::NumberOverflow)
+        );
+    }
+
+    #[test]
+    fn arithmetic_test() {
+        // 2 3 OP_ADD 5 OP_EQUAL
+        let script_sig = ops(&[Opcode::OP_2, Opcode::OP_3]);
+        let script_pubkey = ops(&[Opcode::OP_ADD, Opcode::OP_5, Opcode::OP_EQUAL]);
+        verify_script(&script_sig, &script_pubkey, &BigUint::zero()).unwrap();
+
+        // 2 3 OP_SUB -> -1
+        let script_pubkey = ops(&[Opcode::OP_SUB, Opcode::OP_1NEGATE, Opcode::OP_NUMEQUAL]);
+        verify_script(&script_sig, &script_pubkey, &BigUint::zero()).unwrap();
+
+        let script_pubkey = ops(&[Opcode::OP_MUL, Opcode::OP_6, Opcode::OP_EQUAL]);
+        assert_eq!(
+            verify_script(&script_sig, &script_pubkey, &BigUint::zero()),
+            Err(ScriptError::new(
+                Some(Opcode::OP_MUL),
+                ScriptErrorKind::DisabledOpcode
+            ))
+        );
+    }
+
+    #[test]
+    fn flow_control_test() {
+        // OP_0 OP_IF OP_RETURN OP_ELSE OP_1 OP_NOTIF OP_RETURN OP_ENDIF OP_2 OP_ENDIF
+        // OP_2 OP_EQUAL: both OP_RETURNs sit in skipped branches, the ELSE branch pushes 2
+        let script = ops(&[
+            Opcode::OP_0,
+            Opcode::OP_IF,
+            Opcode::OP_RETURN,
+            Opcode::OP_ELSE,
+            Opcode::OP_1,
+            Opcode::OP_NOTIF,
+            Opcode::OP_RETURN,
+            Opcode::OP_ENDIF,
+            Opcode::OP_2,
+            Opcode::OP_ENDIF,
+            Opcode::OP_2,
+            Opcode::OP_EQUAL,
+        ]);
+        verify_script(&Script::default(), &script, &BigUint::zero()).unwrap();
+
+        let unbalanced = ops(&[Opcode::OP_1, Opcode::OP_IF, Opcode::OP_1]);
+        assert_eq!(
+            verify_script(&Script::default(), &unbalanced, &BigUint::zero())
+                .unwrap_err()
+                .kind,
+            ScriptErrorKind::UnbalancedConditional
+        );
+        let dangling = ops(&[Opcode::OP_1, Opcode::OP_ENDIF]);
+        assert_eq!(
+            verify_script(&Script::default(), &dangling, &BigUint::zero()),
+            Err(ScriptError::new(
+                Some(Opcode::OP_ENDIF),
+                ScriptErrorKind::UnbalancedConditional
+            ))
+        );
+    }
+
+    #[test]
+    fn stack_test() {
+        // 1 2 3 OP_ROT -> 2 3 1
+        let script = ops(&[
+            Opcode::OP_1,
+            Opcode::OP_2,
+            Opcode::OP_3,
+            Opcode::OP_ROT,
+            Opcode::OP_1,
+            Opcode::OP_EQUALVERIFY,
+            Opcode::OP_3,
+            Opcode::OP_EQUALVERIFY,
+            Opcode::OP_DEPTH,
+            Opcode::OP_1,
+            Opcode::OP_EQUALVERIFY,
+            Opcode::OP_DUP,
+            Opcode::OP_TOALTSTACK,
+            Opcode::OP_FROMALTSTACK,
+            Opcode::OP_2DUP,
+            Opcode::OP_EQUALVERIFY,
+            Opcode::OP_EQUAL,
+        ]);
+        verify_script(&Script::default(), &script, &BigUint::zero()).unwrap();
+
+        assert_eq!(
+            verify_script(
+                &Script::default(),
+                &ops(&[Opcode::OP_DUP]),
+                &BigUint::zero()
+            ),
+            Err(ScriptError::new(
+                Some(Opcode::OP_DUP),
+                ScriptErrorKind::StackUnderflow
+            ))
+        );
+    }
+
+    fn locktime_tx(version: u32, sequence: u32, locktime: u32) -> Transaction {
+        let input =
+            TransactionInput::new([0; 32], [0; 4], Script::default(), sequence.to_le_bytes());
+        Transaction::new(
+            TransactionVersion::new(version),
+            vec![input],
+            Vec::new(),
+            TransactionLocktime::new(locktime),
+        )
+    }
+
+    // error of <n> `op` OP_DROP OP_1 spent by the only input of `tx`
+    fn locktime_error(tx: &Transaction, n: i64, op: Opcode) -> Option<ScriptErrorKind> {
+        let script_pubkey = Script::new(vec![
+            Command::Data(encode_num(n)),
+            Command::Op(op),
+            Command::Op(Opcode::OP_DROP),
+            Command::Op(Opcode::OP_1),
+        ]);
+        let checker = TransactionSignatureChecker::new(tx, 0);
+        verify_script(&Script::default(), &script_pubkey, &checker)
+            .err()
+            .map(|e| e.kind)
+    }
+
+    #[test]
+    fn check_lock_time_verify_test() {
+        let cltv = Opcode::OP_CHECKLOCKTIMEVERIFY;
+        let unsatisfied = Some(ScriptErrorKind::UnsatisfiedLocktime);
+        let tx = locktime_tx(1, 0xffff_fffe, 700_000);
+        assert_eq!(locktime_error(&tx, 700_000, cltv), None);
+        assert_eq!(locktime_error(&tx, 699_999, cltv), None);
+        assert_eq!(locktime_error(&tx, 700_001, cltv), unsatisfied);
+        // a timestamp can't be compared with a height
+        assert_eq!(locktime_error(&tx, 600_000_000, cltv), unsatisfied);
+        assert_eq!(
+            locktime_error(&tx, -1, cltv),
+            Some(ScriptErrorKind::NegativeLocktime)
+        );
+        // a final input doesn't enforce the transaction's locktime
+        let final_input = locktime_tx(1, 0xffff_ffff, 700_000);
+        assert_eq!(locktime_error(&final_input, 700_000, cltv), unsatisfied);
+        // operands take 5 bytes to cover the whole u32 range
+        let tx = locktime_tx(1, 0, 0xffff_fffe);
+        assert_eq!(locktime_error(&tx, 0xffff_fffe, cltv), None);
+
+        // a fixed digest has no transaction to check against
+        let script_pubkey = Script::new(vec![Command::Op(Opcode::OP_1), Command::Op(cltv)]);
+        assert_eq!(
+            verify_script(&Script::default(), &script_pubkey, &BigUint::zero())
+                .unwrap_err()
+                .kind,
+            ScriptErrorKind::UnsatisfiedLocktime
+        );
+    }
+
+    #[test]
+    fn check_sequence_verify_test() {
+        let csv = Opcode::OP_CHECKSEQUENCEVERIFY;
+        let unsatisfied = Some(ScriptErrorKind::UnsatisfiedLocktime);
+        let tx = locktime_tx(2, 10, 0);
+        assert_eq!(locktime_error(&tx, 10, csv), None);
+        assert_eq!(locktime_error(&tx, 9, csv), None);
+        assert_eq!(locktime_error(&tx, 11, csv), unsatisfied);
+        // 512-second units can't be compared with blocks
+        assert_eq!(locktime_error(&tx, (1 << 22) | 5, csv), unsatisfied);
+        assert_eq!(
+            locktime_error(&tx, -1, csv),
+            Some(ScriptErrorKind::NegativeLocktime)
+        );
+        // relative locktimes need version 2 and an input that doesn't disable them
+        assert_eq!(locktime_error(&locktime_tx(1, 10, 0), 10, csv), unsatisfied);
+        assert_eq!(
+            locktime_error(&locktime_tx(2, (1 << 31) | 10, 0), 10, csv),
+            unsatisfied
+        );
+        // the disable flag in the operand keeps the opcode a NOP
+        let tx = locktime_tx(1, 0xffff_ffff, 0);
+        assert_eq!(locktime_error(&tx, 1 << 31, csv), None);
+    }
+
+    #[test]
+    fn hash_test() {
+        // OP_SHA256 of "" and OP_HASH160 of a pubkey
+        let script_pubkey = Script::new(vec![
+            Command::Op(Opcode::OP_SHA256),
+            Command::Data(
+                hex::decode("e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855")
+                    .unwrap(),
+            ),
+            Command::Op(Opcode::OP_EQUAL),
+        ]);
+        let script_sig = Script::new(vec![Command::Data(vec![])]);
+        verify_script(&script_sig, &script_pubkey, &BigUint::zero()).unwrap();
+
+        let script_pubkey = Script::new(vec![
+            Command::Op(Opcode::OP_HASH160),
+            Command::Data(hex::decode("b472a266d0bd89c13706a4132ccfb16f7c3b9fcb").unwrap()),
+            Command::Op(Opcode::OP_EQUALVERIFY),
+            Command::Op(Opcode::OP_1),
+        ]);
+        let script_sig = Script::new(vec![Command::Data(vec![])]);
+        verify_script(&script_sig, &script_pubkey, &BigUint::zero()).unwrap();
+    }
+
+    #[test]
+    fn p2pk_checksig_test() {
+        let z = BigUint::from_str_radix(
+            "7c076ff316692a3d7eb3c3bb0f8b1488cf72e1afcd929e29307032997a838a3d",
+            16,
+        )
+        .unwrap();
+        let sec = hex::decode(
+            "04887387e452b8eacc4acfde10d9aaf7f6d9a0f975aabb10d006e4da568744d06c61de6d95231cd8\
+            9026e286df3b6ae4a894a3378e393e93a0f45b666329a0ae34",
+        )
+        .unwrap();
+        let sig = hex::decode(
+            "3045022000eff69ef2b1bd93a66ed5219add4fb51e11a840f404876325a1e8ffe0529a2c022100c7\
+            207fee197d27c618aea621406f6bf5ef6fca38681d82b2f06fddbdce6feab601",
+        )
+        .unwrap();
+        let script_pubkey = Script::new(vec![
+            Command::Data(sec.clone()),
+            Command::Op(Opcode::OP_CHECKSIG),
+        ]);
+        let script_sig = Script::new(vec![Command::Data(sig.clone())]);
+        verify_script(&script_sig, &script_pubkey, &z).unwrap();
+
+        let wrong_z = &z + 1_u32;
+        assert_eq!(
+            verify_script(&script_sig, &script_pubkey, &wrong_z)
+                .unwrap_err()
+                .kind,
+            ScriptErrorKind::EvalFalse
+        );
+
+        // <pubkey> OP_CHECKSIG OP_NOT: only empty or well-formed failing signatures pass
+        let not_checksig = Script::new(vec![
+            Command::Data(sec),
+            Command::Op(Opcode::OP_CHECKSIG),
+            Command::Op(Opcode::OP_NOT),
+        ]);
+        verify_script(&script_sig, &not_checksig, &wrong_z).unwrap();
+        verify_script(&Script::new(vec![Command::Data(vec![])]), &not_checksig, &z).unwrap();
+        let mut not_der = sig;
+        not_der[1] += 1;
+        assert_eq!(
+            verify_script(
+                &Script::new(vec![Command::Data(not_der)]),
+                &not_checksig,
+                &z
+            ),
+            Err(ScriptError::new(
+                Some(Opcode::OP_CHECKSIG),
+                ScriptErrorKind::SigDer
+            ))
+        );
+    }
+
+    // accepts every ECDSA signature and records the script code it was asked to sign
+    struct ScriptCodeRecorder(RefCell<Vec<Vec<u8>>>);
+
+    impl SignatureChecker for ScriptCodeRecorder {
+        fn check_sig(&self, _: &[u8], _: &[u8], script_code: &[u8], _: SigVersion) -> bool {
+            self.0.borrow_mut().push(script_code.to_vec());
+            true
+        }
+
+        fn check_schnorr_sig(&self, _: &[u8], _: &[u8]) -> bool {
+            false
+        }
+    }
+
+    #[test]
+    fn find_and_delete_test() {
+        let sig = hex::decode("300602010102010101").unwrap();
+        let pubkey = vec![0x02; 33];
+        // <sig> OP_DROP OP_CODESEPARATOR PUSHDATA1 <sig> OP_DROP <sig> OP_DROP <pubkey>
+        // OP_CHECKSIG, signed with the same <sig> pushed by scriptSig
+        let push_sig = [&[0x09][..], &sig].concat();
+        let pushdata1_sig = [&[0x4c, 0x09][..], &sig].concat();
+        let push_pubkey = [&[0x21][..], &pubkey].concat();
+        let script_pubkey = [
+            &push_sig[..],
+            &[0x75, 0xab],
+            &pushdata1_sig,
+            &[0x75],
+            &push_sig,
+            &[0x75],
+            &push_pubkey,
+            &[0xac],
+        ]
+        .concat();
+        let script_pubkey = Script::parse_raw(&script_pubkey).unwrap();
+        let script_sig = Script::new(vec![Command::Data(sig)]);
+
+        let checker = ScriptCodeRecorder(RefCell::new(Vec::new()));
+        verify_script(&script_sig, &script_pubkey, &checker).unwrap();
+        // only the minimal push is deleted, the PUSHDATA1 encoding is signed as is
+        let expected = [&pushdata1_sig[..], &[0x75, 0x75], &push_pubkey, &[0xac]].concat();
+        assert_eq!(checker.0.into_inner(), vec![expected]);
+    }
+
+    #[test]
+    fn witness_script_code_test() {
+        let sig = hex::decode("300602010102010101").unwrap();
+        let push_sig = [&[0x09][..], &sig].concat();
+        let pushdata1_sig = [&[0x4c, 0x09][..], &sig].concat();
+        let push_pubkey = [&[0x21][..], &[0x02; 33]].concat();
+        // OP_CODESEPARATOR PUSHDATA1 <sig> OP_DROP <sig> OP_DROP <pubkey> OP_CHECKSIGVERIFY
+        // OP_CODESEPARATOR <pubkey> OP_CHECKSIG
+        let second = [&[0xab][..], &push_pubkey, &[0xac]].concat();
+        let first = [
+            &pushdata1_sig[..],
+            &[0x75],
+            &push_sig,
+            &[0x75],
+            &push_pubkey,
+            &[0xad],
+            &second,
+        ]
+        .concat();
+        let witness_script = [&[0xab][..], &first].concat();
+        let p2wsh = Script::witness_program(0, &Sha256::digest(&witness_script)).unwrap();
+
+        let checker = ScriptCodeRecorder(RefCell::new(Vec::new()));
+        let witness = vec![sig.clone(), sig, witness_script];
+        verify_script_with_witness(&Script::default(), &p2wsh, &witness, &checker).unwrap();
+        // BIP143 signs the witness script as encoded from after the last executed
+        // OP_CODESEPARATOR, without FindAndDelete
+        assert_eq!(checker.0.into_inner(), vec![first, second[1..].to_vec()]);
+    }
+
+    fn multisig_fixture(z: &BigUint) -> (Vec<Vec<u8>>, Vec<Vec<u8>>) {
+        let keys: Vec<Key> = (1..=3_u32).map(|secret| Key::new(secret.into())).collect();
+        let pubkeys = keys
+            .iter()
+            .map(|k| k.point.serialize_sec().unwrap())
+            .collect();
+        let signatures = keys
+            .iter()
+            .map(|k| {
+                let mut sig = k.sign(z.clone()).der();
+                sig.push(0x01);
+                sig
+            })
+            .collect();
+        (pubkeys, signatures)
+    }
+
+    // m <pubkeys..> n OP_CHECKMULTISIG
+    fn multisig_script(m: Opcode, pubkeys: &[Vec<u8>], n: Opcode) -> Script {
+        let mut commands = vec![Command::Op(m)];
+        commands.extend(pubkeys.iter().map(|p| Command::Data(p.clone())));
+        commands.extend([Command::Op(n), Command::Op(Opcode::OP_CHECKMULTISIG)]);
+        Script::new(commands)
+    }
+
+    #[test]
+    fn bare_multisig_test() {
+        let z = BigUint::from_str_radix(
+            "e71bfa115715d6fd33796948126f40a8cdd39f187e4afb03896795189fe1423c",
+            16,
+        )
+        .unwrap();
+        let (pubkeys, sigs) = multisig_fixture(&z);
+        let script_pubkey = multisig_script(Opcode::OP_2, &pubkeys, Opcode::OP_3);
+
+        let script_sig = Script::new(vec![
+            Command::Op(Opcode::OP_0),
+            Command::Data(sigs[0].clone()),
+            Command::Data(sigs[2].clone()),
+        ]);
+        verify_script(&script_sig, &script_pubkey, &z).unwrap();
+
+        // signatures out of pubkey order
+        let script_sig = Script::new(vec![
+            Command::Op(Opcode::OP_0),
+            Command::Data(sigs[2].clone()),
+            Command::Data(sigs[0].clone()),
+        ]);
+        assert_eq!(
+            verify_script(&script_sig, &script_pubkey, &z)
+                .unwrap_err()
+                .kind,
+            ScriptErrorKind::EvalFalse
+        );
+
+        // NULLDUMMY
+        let script_sig = Script::new(vec![
+            Command::Op(Opcode::OP_1),
+            Command::Data(sigs[0].clone()),
+            Command::Data(sigs[1].clone()),
+        ]);
+        assert_eq!(
+            verify_script(&script_sig, &script_pubkey, &z),
+            Err(ScriptError::new(
+                Some(Opcode::OP_CHECKMULTISIG),
+                ScriptErrorKind::SigNullDummy
+            ))
+        );
+
+        // a malformed signature fails the script once it is tried against a key
+        let script_sig = Script::new(vec![
+            Command::Op(Opcode::OP_0),
+            Command::Data(sigs[0].clone()),
+            Command::Data(vec![0x30, 0x01]),
+        ]);
+        assert_eq!(
+            verify_script(&script_sig, &script_pubkey, &z),
+            Err(ScriptError::new(
+                Some(Opcode::OP_CHECKMULTISIG),
+                ScriptErrorKind::SigDer
+            ))
+        );
+        // but not when no keys are left to try it against
+        let script_sig = Script::new(vec![
+            Command::Op(Opcode::OP_0),
+            Command::Data(sigs[2].clone()),
+            Command::Data(vec![0x30, 0x01]),
+        ]);
+        assert_eq!(
+            verify_script(&script_sig, &script_pubkey, &z)
+                .unwrap_err()
+                .kind,
+            ScriptErrorKind::EvalFalse
+        );
+
+        // missing dummy element
+        let script_sig = Script::new(vec![
+            Command::Data(sigs[0].clone()),
+            Command::Data(sigs[1].clone()),
+        ]);
+        assert_eq!(
+            verify_script(&script_sig, &script_pubkey, &z),
+            Err(ScriptError::new(
+                Some(Opcode::OP_CHECKMULTISIG),
+                ScriptErrorKind::StackUnderflow
+            ))
+        );
+    }
+
+    #[test]
+    fn p2sh_multisig_test() {
+        let z = BigUint::from_str_radix(
+            "e71bfa115715d6fd33796948126f40a8cdd39f187e4afb03896795189fe1423c",
+            16,
+        )
+        .unwrap();
+        let (pubkeys, sigs) = multisig_fixture(&z);
+        let redeem_script =
+            multisig_script(Opcode::OP_2, &pubkeys[..2], Opcode::OP_2).raw_serialize();
+        let script_pubkey = Script::new(vec![
+            Command::Op(Opcode::OP_HASH160),
+            Command::Data(hash160(&redeem_script)),
+            Command::Op(Opcode::OP_EQUAL),
+        ]);
+        assert!(script_pubkey.is_p2sh());
+
+        let script_sig = Script::new(vec![
+            Command::Op(Opcode::OP_0),
+            Command::Data(sigs[0].clone()),
+            Command::Data(sigs[1].clone()),
+            Command::Data(redeem_script.clone()),
+        ]);
+        verify_script(&script_sig, &script_pubkey, &z).unwrap();
+
+        // the hash matches but the redeem script isn't satisfied
+        let script_sig = Script::new(vec![
+            Command::Op(Opcode::OP_0),
+            Command::Data(sigs[0].clone()),
+            Command::Data(sigs[2].clone()),
+            Command::Data(redeem_script.clone()),
+        ]);
+        assert_eq!(
+            verify_script(&script_sig, &script_pubkey, &z)
+                .unwrap_err()
+                .kind,
+            ScriptErrorKind::EvalFalse
+        );
+
+        let script_sig = Script::new(vec![
+            Command::Op(Opcode::OP_0),
+            Command::Data(sigs[0].clone()),
+            Command::Data(sigs[1].clone()),
+            Command::Op(Opcode::OP_NOP),
+            Command::Data(redeem_script),
+        ]);
+        assert_eq!(
+            verify_script(&script_sig, &script_pubkey, &z),
+            Err(ScriptError::new(None, ScriptErrorKind::SigPushOnly))
+        );
+    }
+
+    #[test]
+    fn p2sh_pushdata1_hash_test() {
+        // the redeem script OP_0 would fail the spend if it were evaluated
+        let redeem_script = vec![0x00];
+        let script_sig = Script::new(vec![Command::Data(redeem_script.clone())]);
+        let p2sh = Script::p2sh(&hash160(&redeem_script));
+        assert_eq!(
+            verify_script(&script_sig, &p2sh, &BigUint::zero())
+                .unwrap_err()
+                .kind,
+            ScriptErrorKind::EvalFalse
+        );
+
+        // OP_HASH160 PUSHDATA1 <hash> OP_EQUAL is not BIP16, only the hash is compared
+        let raw = [&[0xa9, 0x4c, 0x14][..], &hash160(&redeem_script), &[0x87]].concat();
+        let script_pubkey = Script::parse_raw(&raw).unwrap();
+        verify_script(&script_sig, &script_pubkey, &BigUint::zero()).unwrap();
+    }
+
+    #[test]
+    fn witness_program_test() {
+        let z = BigUint::from(0x1234_u32);
+        let key = Key::new(BigUint::from(8675309_u32));
+        let sec = key.point.serialize_sec_compressed().unwrap();
+        let mut sig = key.sign(z.clone()).der();
+        sig.push(0x01);
+        let witness = vec![sig, sec.clone()];
+        let kind = |result: Result<(), ScriptError>| result.unwrap_err().kind;
+
+        // native P2WPKH
+        let p2wpkh = Script::witness_program(0, &hash160(&sec)).unwrap();
+        verify_script_with_witness(&Script::default(), &p2wpkh, &witness, &z).unwrap();
+        assert_eq!(
+            kind(verify_script_with_witness(
+                &Script::default(),
+                &p2wpkh,
+                &witness,
+                &(&z + 1_u32)
+            )),
+            ScriptErrorKind::EvalFalse
+        );
+        assert_eq!(
+            kind(verify_script_with_witness(
+                &Script::default(),
+                &p2wpkh,
+                &witness[..1],
+                &z
+            )),
+            ScriptErrorKind::WitnessProgramMismatch
+        );
+        let script_sig = Script::new(vec![Command::Op(Opcode::OP_1)]);
+        assert_eq!(
+            kind(verify_script_with_witness(
+                &script_sig,
+                &p2wpkh,
+                &witness,
+                &z
+            )),
+            ScriptErrorKind::WitnessMalleated
+        );
+
+        // P2SH-wrapped P2WPKH
+        let redeem_script = p2wpkh.raw_serialize();
+        let p2sh = Script::p2sh(&hash160(&redeem_script));
+        let script_sig = Script::new(vec![Command::Data(redeem_script.clone())]);
+        verify_script_with_witness(&script_sig, &p2sh, &witness, &z).unwrap();
+        let script_sig = Script::new(vec![
+            Command::Op(Opcode::OP_0),
+            Command::Data(redeem_script.clone()),
+        ]);
+        assert_eq!(
+            kind(verify_script_with_witness(&script_sig, &p2sh, &witness, &z)),
+            ScriptErrorKind::WitnessMalleatedP2sh
+        );
+        // the same redeem script behind a PUSHDATA1
+        let script_sig = Script::parse_raw(&[&[0x4c, 0x16][..], &redeem_script].concat()).unwrap();
+        assert_eq!(
+            kind(verify_script_with_witness(&script_sig, &p2sh, &witness, &z)),
+            ScriptErrorKind::WitnessMalleatedP2sh
+        );
+
+        // P2WSH must leave a clean stack
+        let witness_script =
+            Script::new(vec![Command::Op(Opcode::OP_1), Command::Op(Opcode::OP_1)]);
+        let raw = witness_script.raw_serialize();
+        let p2wsh = Script::witness_program(0, &Sha256::digest(&raw)).unwrap();
+        assert_eq!(
+            kind(verify_script_with_witness(
+                &Script::default(),
+                &p2wsh,
+                &[raw],
+                &z
+            )),
+            ScriptErrorKind::CleanStack
+        );
+        assert_eq!(
+            kind(verify_script_with_witness(
+                &Script::default(),
+                &p2wsh,
+                &[],
+                &z
+            )),
+            ScriptErrorKind::WitnessProgramWitnessEmpty
+        );
+
+        assert_eq!(
+            kind(verify_script_with_witness(
+                &Script::default(),
+                &Script::witness_program(0, &[1; 25]).unwrap(),
+                &witness,
+                &z
+            )),
+            ScriptErrorKind::WitnessProgramWrongLength
+        );
+        // unknown versions are anyone-can-spend
+        verify_script_with_witness(
+            &Script::default(),
+            &Script::witness_program(2, &[1; 32]).unwrap(),
+            &[],
+            &z,
+        )
+        .unwrap();
+        // witness on a legacy output
+        let p2pkh = Script::p2pkh(&hash160(&sec));
+        let script_sig = Script::new(witness.iter().cloned().map(Command::Data).collect());
+        verify_script(&script_sig, &p2pkh, &z).unwrap();
+        assert_eq!(
+            kind(verify_script_with_witness(
+                &script_sig,
+                &p2pkh,
+                &witness,
+                &z
+            )),
+            ScriptErrorKind::WitnessUnexpected
+        );
+    }
+
+    #[test]
+    fn taproot_key_path_test() {
+        let z = BigUint::from(0x1234_u32);
+        let key = Key::new(BigUint::from(8675309_u32));
+        let output_key = key.point.x_only().unwrap();
+        let signature = key
+            .sign_schnorr(&biguint_to_32_bytes(&z), &[0; 32])
+            .unwrap()
+            .to_bytes()
+            .to_vec();
+        let p2tr = Script::witness_program(1, &output_key).unwrap();
+        let kind = |result: Result<(), ScriptError>| result.unwrap_err().kind;
+
+        verify_script_with_witness(
+            &Script::default(),
+            &p2tr,
+            std::slice::from_ref(&signature),
+            &z,
+        )
+        .unwrap();
+        assert_eq!(
+            kind(verify_script_with_witness(
+                &Script::default(),
+                &p2tr,
+                std::slice::from_ref(&signature),
+                &(&z + 1_u32)
+            )),
+            ScriptErrorKind::SchnorrSig
+        );
+        assert_eq!(
+            kind(verify_script_with_witness(
+                &Script::default(),
+                &p2tr,
+                &[],
+                &z
+            )),
+            ScriptErrorKind::WitnessProgramWitnessEmpty
+        );
+        assert_eq!(
+            kind(verify_script_with_witness(
+                &Script::default(),
+                &p2tr,
+                &[signature.clone(), vec![0x50]],
+                &z
+            )),
+            ScriptErrorKind::UnsupportedWitness
+        );
+        // a script path spend: the script and a control block
+        assert_eq!(
+            kind(verify_script_with_witness(
+                &Script::default(),
+                &p2tr,
+                &[signature, vec![0x51], vec![0xc0; 33]],
+                &z
+            )),
+            ScriptErrorKind::UnsupportedWitness
+        );
+    }
+}
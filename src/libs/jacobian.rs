@@ -0,0 +1,604 @@
+use lazy_static::lazy_static;
+use num_bigint_dig::ToBigInt;
+use subtle::{Choice, ConditionallySelectable, ConstantTimeEq};
+
+use super::{
+    ecc::{EccPoint, SECP256K1GENS},
+    field_element::FieldElement,
+    scalar::Scalar,
+};
+
+// secp256k1 point in Jacobian coordinates: (X, Y, Z) stands for (X / Z^2, Y / Z^3).
+// Z == 0 is the point at infinity. Additions and doublings need no inversion; only
+// `to_affine` inverts once at the end.
+#[derive(Debug, Clone, Copy)]
+pub struct JacobianPoint {
+    x: FieldElement,
+    y: FieldElement,
+    z: FieldElement,
+}
+
+impl JacobianPoint {
+    pub const INFINITY: JacobianPoint = JacobianPoint {
+        x: FieldElement::ONE,
+        y: FieldElement::ONE,
+        z: FieldElement::ZERO,
+    };
+
+    // None for points that are not on secp256k1
+    pub fn from_affine(point: &EccPoint) -> Option<JacobianPoint> {
+        match point {
+            EccPoint::PointAtInfinity => Some(JacobianPoint::INFINITY),
+            EccPoint::Point(ecc) if ecc.is_secp256k1() => Some(JacobianPoint {
+                x: FieldElement::from_biguint(&ecc.x.num.to_biguint()?),
+                y: FieldElement::from_biguint(&ecc.y.num.to_biguint()?),
+                z: FieldElement::ONE,
+            }),
+            EccPoint::Point(_) => None,
+        }
+    }
+
+    // the point with affine x-coordinate `x` and the given y parity, None if x^3 + 7
+    // has no square root
+    pub fn lift_x(x: &FieldElement, odd_y: bool) -> Option<JacobianPoint> {
+        let y = (x.square() * *x + FieldElement::from_biguint(&7_u32.into())).sqrt()?;
+        let y = match bool::from(y.is_odd()) == odd_y {
+            true => y,
+            false => y.negate(),
+        };
+        Some(JacobianPoint {
+            x: *x,
+            y,
+            z: FieldElement::ONE,
+        })
+    }
+
+    pub fn to_affine(&self) -> EccPoint {
+        if self.is_infinity().into() {
+            return EccPoint::PointAtInfinity;
+        }
+        let z_inv = self.z.invert();
+        let z_inv2 = z_inv.square();
+        let x = self.x * z_inv2;
+        let y = self.y * z_inv2 * z_inv;
+        EccPoint::new_secp256k1(
+            x.to_biguint().to_bigint().unwrap(),
+            y.to_biguint().to_bigint().unwrap(),
+        )
+    }
+
+    pub fn is_infinity(&self) -> Choice {
+        self.z.is_zero()
+    }
+
+    pub fn negate(&self) -> JacobianPoint {
+        JacobianPoint {
+            x: self.x,
+            y: self.y.negate(),
+            z: self.z,
+        }
+    }
+
+    // dbl-2009-l for a = 0; infinity doubles to infinity since Z3 = 2 * Y1 * Z1
+    pub fn double(&self) -> JacobianPoint {
+        let a = self.x.square();
+        let b = self.y.square();
+        let c = b.square();
+        let d = ((self.x + b).square() - a - c).double();
+        let e = a.double() + a;
+        let f = e.square();
+        let x3 = f - d.double();
+        let y3 = e * (d - x3) - c.double().double().double();
+        let z3 = (self.y * self.z).double();
+        JacobianPoint {
+            x: x3,
+            y: y3,
+            z: z3,
+        }
+    }
+
+    // add-2007-bl, with every special case computed and then selected, so the
+    // sequence of field operations doesn't depend on the inputs
+    pub fn add(&self, other: &JacobianPoint) -> JacobianPoint {
+        let z1z1 = self.z.square();
+        let z2z2 = other.z.square();
+        let u1 = self.x * z2z2;
+        let u2 = other.x * z1z1;
+        let s1 = self.y * other.z * z2z2;
+        let s2 = other.y * self.z * z1z1;
+        let h = u2 - u1;
+        let i = h.double().square();
+        let j = h * i;
+        let r = (s2 - s1).double();
+        let v = u1 * i;
+        let x3 = r.square() - j - v.double();
+        let y3 = r * (v - x3) - (s1 * j).double();
+        // h == 0 makes this infinity, which is right for P + (-P)
+        let z3 = ((self.z + other.z).square() - z1z1 - z2z2) * h;
+        let sum = JacobianPoint {
+            x: x3,
+            y: y3,
+            z: z3,
+        };
+
+        let same_point = u1.ct_eq(&u2) & s1.ct_eq(&s2);
+        let res = JacobianPoint::conditional_select(&sum, &self.double(), same_point);
+        let res = JacobianPoint::conditional_select(&res, other, self.is_infinity());
+        JacobianPoint::conditional_select(&res, self, other.is_infinity())
+    }
+
+    // Same as `add`, but returns early on the special cases; only for public inputs
+    pub fn add_vartime(&self, other: &JacobianPoint) -> JacobianPoint {
+        if self.is_infinity().into() {
+            return *other;
+        }
+        if other.is_infinity().into() {
+            return *self;
+        }
+        let z1z1 = self.z.square();
+        let z2z2 = other.z.square();
+        let u1 = self.x * z2z2;
+        let u2 = other.x * z1z1;
+        let s1 = self.y * other.z * z2z2;
+        let s2 = other.y * self.z * z1z1;
+        if u1 == u2 {
+            return if s1 == s2 {
+                self.double()
+            } else {
+                JacobianPoint::INFINITY
+            };
+        }
+        let h = u2 - u1;
+        let i = h.double().square();
+        let j = h * i;
+        let r = (s2 - s1).double();
+        let v = u1 * i;
+        let x3 = r.square() - j - v.double();
+        let y3 = r * (v - x3) - (s1 * j).double();
+        let z3 = ((self.z + other.z).square() - z1z1 - z2z2) * h;
+        JacobianPoint {
+            x: x3,
+            y: y3,
+            z: z3,
+        }
+    }
+
+    // Double-and-add over big-endian scalar bytes; leaks the scalar through timing,
+    // so only for public scalars such as the ones in signature verification
+    pub fn mul_vartime(&self, scalar: &[u8]) -> JacobianPoint {
+        let mut res = JacobianPoint::INFINITY;
+        for byte in scalar {
+            for bit in (0..8).rev() {
+                res = res.double();
+                if (byte >> bit) & 1 == 1 {
+                    res = res.add_vartime(self);
+                }
+            }
+        }
+        res
+    }
+
+    // [P, 3P, 5P, ...]: the odd multiples a width-w NAF digit can select
+    fn odd_multiples_vartime(&self, count: usize) -> Vec<JacobianPoint> {
+        let double = self.double();
+        let mut table = Vec::with_capacity(count);
+        table.push(*self);
+        for i in 1..count {
+            table.push(table[i - 1].add_vartime(&double));
+        }
+        table
+    }
+
+    // a * P + b * Q with interleaved width-5 NAFs and one shared doubling chain.
+    // Variable time, so only for public scalars.
+    pub fn double_mul_vartime(
+        a: &[u8; 32],
+        p: &JacobianPoint,
+        b: &[u8; 32],
+        q: &JacobianPoint,
+    ) -> JacobianPoint {
+        let p_table = p.odd_multiples_vartime(1 << (WNAF_WIDTH - 2));
+        let q_table = q.odd_multiples_vartime(1 << (WNAF_WIDTH - 2));
+        interleaved_wnaf(&[
+            (&p_table, wnaf(a, WNAF_WIDTH)),
+            (&q_table, wnaf(b, WNAF_WIDTH)),
+        ])
+    }
+
+    // a * G + b * Q, where G's odd multiples are precomputed once with a wider window
+    pub fn mul_generator_add_vartime(
+        a: &[u8; 32],
+        b: &[u8; 32],
+        q: &JacobianPoint,
+    ) -> JacobianPoint {
+        let q_table = q.odd_multiples_vartime(1 << (WNAF_WIDTH - 2));
+        interleaved_wnaf(&[
+            (&GENERATOR_ODD_MULTIPLES, wnaf(a, GENERATOR_WNAF_WIDTH)),
+            (&q_table, wnaf(b, WNAF_WIDTH)),
+        ])
+    }
+
+    // g * G + sum(k_i * P_i) over a single doubling chain, so a whole batch costs about
+    // one scalar multiplication plus an addition per non-zero NAF digit
+    pub fn multi_mul_generator_add_vartime(
+        g: &[u8; 32],
+        terms: &[([u8; 32], JacobianPoint)],
+    ) -> JacobianPoint {
+        let tables: Vec<Vec<JacobianPoint>> = terms
+            .iter()
+            .map(|(_, point)| point.odd_multiples_vartime(1 << (WNAF_WIDTH - 2)))
+            .collect();
+        let mut nafs = Vec::with_capacity(terms.len() + 1);
+        nafs.push((
+            GENERATOR_ODD_MULTIPLES.as_slice(),
+            wnaf(g, GENERATOR_WNAF_WIDTH),
+        ));
+        for (table, (k, _)) in tables.iter().zip(terms) {
+            nafs.push((table.as_slice(), wnaf(k, WNAF_WIDTH)));
+        }
+        interleaved_wnaf(&nafs)
+    }
+
+    // Whether the affine x-coordinate equals `x`, compared as X == x * Z^2 so that
+    // no inversion is needed
+    pub fn has_affine_x(&self, x: &FieldElement) -> bool {
+        !bool::from(self.is_infinity()) && self.x == *x * self.z.square()
+    }
+
+    // Fixed 4-bit window: 256 doublings and 64 additions whatever the scalar,
+    // with table entries picked by a constant-time scan
+    pub fn mul(&self, scalar: &Scalar) -> JacobianPoint {
+        let mut table = [JacobianPoint::INFINITY; 16];
+        for i in 1..16 {
+            table[i] = table[i - 1].add(self);
+        }
+        let mut res = JacobianPoint::INFINITY;
+        for limb in scalar.limbs().iter().rev() {
+            for window in (0..16).rev() {
+                for _ in 0..4 {
+                    res = res.double();
+                }
+                let nibble = ((limb >> (4 * window)) & 0xf) as u8;
+                res = res.add(&lookup(&table, nibble));
+            }
+        }
+        res
+    }
+
+    // k * G from the precomputed table of `GENERATOR_TABLE`: 64 constant-time
+    // additions and no doublings
+    pub fn mul_generator(scalar: &Scalar) -> JacobianPoint {
+        let mut res = JacobianPoint::INFINITY;
+        let mut window = 0;
+        for limb in scalar.limbs() {
+            for shift in 0..16 {
+                let nibble = ((limb >> (4 * shift)) & 0xf) as u8;
+                res = res.add(&lookup(&GENERATOR_TABLE[window], nibble));
+                window += 1;
+            }
+        }
+        res
+    }
+}
+
+const WNAF_WIDTH: usize = 5;
+const GENERATOR_WNAF_WIDTH: usize = 8;
+
+// Width-w NAF of a big-endian scalar, least significant digit first: every non-zero
+// digit is odd, below 2^(w-1) in absolute value, and followed by at least w - 1 zeros
+fn wnaf(scalar: &[u8; 32], width: usize) -> [i32; 257] {
+    let bit = |i: usize| -> i32 {
+        match i < 256 {
+            true => ((scalar[31 - i / 8] >> (i % 8)) & 1) as i32,
+            false => 0,
+        }
+    };
+    let mut naf = [0; 257];
+    let mut carry = 0;
+    let mut i = 0;
+    while i < 256 {
+        if bit(i) == carry {
+            i += 1;
+            continue;
+        }
+        // bit(i) + carry is odd here, so the window value is odd as well
+        let mut word = (0..width).map(|j| bit(i + j) << j).sum::<i32>() + carry;
+        carry = (word >> (width - 1)) & 1;
+        word -= carry << width;
+        naf[i] = word;
+        i += width;
+    }
+    naf[256] = carry;
+    naf
+}
+
+fn interleaved_wnaf(terms: &[(&[JacobianPoint], [i32; 257])]) -> JacobianPoint {
+    let top = (0..257)
+        .rev()
+        .find(|&i| terms.iter().any(|(_, naf)| naf[i] != 0));
+    let mut res = JacobianPoint::INFINITY;
+    for i in (0..=top.unwrap_or(0)).rev() {
+        res = res.double();
+        for (table, naf) in terms {
+            let digit = naf[i];
+            if digit > 0 {
+                res = res.add_vartime(&table[(digit / 2) as usize]);
+            } else if digit < 0 {
+                res = res.add_vartime(&table[(-digit / 2) as usize].negate());
+            }
+        }
+    }
+    res
+}
+
+// reads every entry, so the access pattern doesn't reveal `index`
+fn lookup(table: &[JacobianPoint; 16], index: u8) -> JacobianPoint {
+    let mut res = JacobianPoint::INFINITY;
+    for (i, point) in table.iter().enumerate() {
+        res = JacobianPoint::conditional_select(&res, point, (i as u8).ct_eq(&index));
+    }
+    res
+}
+
+lazy_static! {
+    // GENERATOR_TABLE[i][j] == j * 16^i * G
+    static ref GENERATOR_TABLE: Vec<[JacobianPoint; 16]> = {
+        let mut base = JacobianPoint::from_affine(&SECP256K1GENS).unwrap();
+        let mut table = Vec::with_capacity(64);
+        for _ in 0..64 {
+            let mut row = [JacobianPoint::INFINITY; 16];
+            for j in 1..16 {
+                row[j] = row[j - 1].add_vartime(&base);
+            }
+            base = row[15].add_vartime(&base);
+            table.push(row);
+        }
+        table
+    };
+
+    static ref GENERATOR_ODD_MULTIPLES: Vec<JacobianPoint> = JacobianPoint::from_affine(&SECP256K1GENS)
+        .unwrap()
+        .odd_multiples_vartime(1 << (GENERATOR_WNAF_WIDTH - 2));
+}
+
+impl ConditionallySelectable for JacobianPoint {
+    fn conditional_select(a: &Self, b: &Self, choice: Choice) -> Self {
+        JacobianPoint {
+            x: FieldElement::conditional_select(&a.x, &b.x, choice),
+            y: FieldElement::conditional_select(&a.y, &b.y, choice),
+            z: FieldElement::conditional_select(&a.z, &b.z, choice),
+        }
+    }
+}
+
+// (X1, Y1, Z1) == (X2, Y2, Z2) iff X1 * Z2^2 == X2 * Z1^2 and Y1 * Z2^3 == Y2 * Z1^3
+impl ConstantTimeEq for JacobianPoint {
+    fn ct_eq(&self, other: &Self) -> Choice {
+        let z1z1 = self.z.square();
+        let z2z2 = other.z.square();
+        let same_x = (self.x * z2z2).ct_eq(&(other.x * z1z1));
+        let same_y = (self.y * z2z2 * other.z).ct_eq(&(other.y * z1z1 * self.z));
+        let both_infinity = self.is_infinity() & other.is_infinity();
+        let neither_infinity = !self.is_infinity() & !other.is_infinity();
+        both_infinity | (neither_infinity & same_x & same_y)
+    }
+}
+
+impl PartialEq for JacobianPoint {
+    fn eq(&self, other: &Self) -> bool {
+        self.ct_eq(other).into()
+    }
+}
+
+impl Eq for JacobianPoint {}
+
+#[cfg(test)]
+mod tests {
+    use num_bigint_dig::{BigInt, BigUint, RandBigInt, ToBigInt};
+
+    use crate::libs::{
+        ecc::{EccPoint, SECP256K1GENS, SECP256K1GENS_ORDER, SECP256K1GENS_X},
+        field_element::FieldElement,
+        finite_field::FiniteField,
+        math::biguint_to_32_bytes,
+        scalar::Scalar,
+    };
+
+    use super::{wnaf, JacobianPoint, GENERATOR_WNAF_WIDTH, WNAF_WIDTH};
+
+    #[test]
+    fn jacobian_matches_affine_test() {
+        let g = JacobianPoint::from_affine(&SECP256K1GENS).unwrap();
+        let g_affine: &EccPoint = &SECP256K1GENS;
+        assert_eq!(g.to_affine(), *g_affine);
+
+        // 2G, 3G and 4G against the affine formulas
+        let two = g.double();
+        assert_eq!(two.to_affine(), g_affine + g_affine);
+        let three = two.add(&g);
+        assert_eq!(three.to_affine(), &two.to_affine() + g_affine);
+        assert_eq!(three.add(&g), two.double());
+        assert_eq!(g.add(&g), two);
+
+        assert_eq!(three.add_vartime(&g), two.double());
+        assert_eq!(g.add_vartime(&g), two);
+
+        // adding in a non-normalized representation
+        let four = two.double();
+        assert_eq!(three.add(&g), four);
+        let normalized = JacobianPoint::from_affine(&four.to_affine()).unwrap();
+        assert_eq!(normalized, four);
+    }
+
+    #[test]
+    fn jacobian_infinity_test() {
+        let g = JacobianPoint::from_affine(&SECP256K1GENS).unwrap();
+        let inf = JacobianPoint::INFINITY;
+        assert_eq!(g.add(&g.negate()), inf);
+        assert_eq!(g.add_vartime(&g.negate()), inf);
+        assert_eq!(inf.add(&inf), inf);
+        assert_eq!(inf.add(&g), g);
+        assert_eq!(g.add(&inf), g);
+        assert_eq!(inf.double(), inf);
+        assert_eq!(inf.to_affine(), EccPoint::PointAtInfinity);
+        assert_ne!(g, inf);
+    }
+
+    #[test]
+    fn jacobian_rejects_other_curves_test() {
+        let toy = EccPoint::new(
+            0.to_bigint().unwrap(),
+            7.to_bigint().unwrap(),
+            FiniteField::new_from_i64(47, 223),
+            FiniteField::new_from_i64(71, 223),
+        );
+        assert!(JacobianPoint::from_affine(&toy).is_none());
+    }
+
+    #[test]
+    fn jacobian_mul_test() {
+        let g = JacobianPoint::from_affine(&SECP256K1GENS).unwrap();
+        let n = &(*SECP256K1GENS_ORDER);
+        let mut rng = rand::thread_rng();
+        let mut scalars = vec![
+            BigUint::from(1_u32),
+            BigUint::from(2_u32),
+            BigUint::from(15_u32),
+            BigUint::from(16_u32),
+            n - 1_u32,
+        ];
+        scalars.extend((0..8).map(|_| rng.gen_biguint_below(n)));
+
+        for k in &scalars {
+            let expected = g.mul_vartime(&k.to_bytes_be());
+            let scalar = Scalar::from_biguint(k);
+            assert_eq!(g.mul(&scalar), expected);
+            assert_eq!(JacobianPoint::mul_generator(&scalar), expected);
+        }
+        assert_eq!(
+            JacobianPoint::mul_generator(&Scalar::from_biguint(&(n - 1_u32))),
+            g.negate()
+        );
+        assert_eq!(
+            JacobianPoint::mul_generator(&Scalar::ZERO),
+            JacobianPoint::INFINITY
+        );
+        assert_eq!(g.mul(&Scalar::ZERO), JacobianPoint::INFINITY);
+
+        // some other base point
+        let p = g.mul_vartime(&[0x12, 0x34]);
+        let k = rng.gen_biguint_below(n);
+        let expected = g.mul_vartime(&((k.clone() * 0x1234_u32) % n).to_bytes_be());
+        assert_eq!(p.mul(&Scalar::from_biguint(&k)), expected);
+    }
+
+    #[test]
+    fn wnaf_test() {
+        let mut rng = rand::thread_rng();
+        let n = &(*SECP256K1GENS_ORDER);
+        let mut scalars = vec![BigUint::from(0_u32), BigUint::from(1_u32), n - 1_u32];
+        scalars.push(BigUint::from_bytes_be(&[0xff; 32]));
+        scalars.extend((0..8).map(|_| rng.gen_biguint(256)));
+
+        for k in &scalars {
+            for width in [WNAF_WIDTH, GENERATOR_WNAF_WIDTH] {
+                let naf = wnaf(&biguint_to_32_bytes(k), width);
+                let mut value = BigInt::from(0);
+                for (i, &digit) in naf.iter().enumerate() {
+                    value += BigInt::from(digit) << i;
+                    if digit != 0 {
+                        assert!(digit % 2 != 0 && digit.abs() < 1 << (width - 1));
+                        assert!(naf[i + 1..].iter().take(width - 1).all(|&d| d == 0));
+                    }
+                }
+                assert_eq!(value, k.to_bigint().unwrap());
+            }
+        }
+    }
+
+    #[test]
+    fn jacobian_double_mul_test() {
+        let g = JacobianPoint::from_affine(&SECP256K1GENS).unwrap();
+        let n = &(*SECP256K1GENS_ORDER);
+        let mut rng = rand::thread_rng();
+        let p = g.mul_vartime(&rng.gen_biguint_below(n).to_bytes_be());
+        let q = g.mul_vartime(&rng.gen_biguint_below(n).to_bytes_be());
+
+        let mut pairs = vec![
+            (BigUint::from(0_u32), BigUint::from(0_u32)),
+            (BigUint::from(1_u32), BigUint::from(0_u32)),
+            (BigUint::from(0_u32), n - 1_u32),
+        ];
+        pairs.extend((0..8).map(|_| (rng.gen_biguint_below(n), rng.gen_biguint_below(n))));
+        for (a, b) in &pairs {
+            let (a_bytes, b_bytes) = (biguint_to_32_bytes(a), biguint_to_32_bytes(b));
+            let expected = g
+                .mul_vartime(&a_bytes)
+                .add_vartime(&p.mul_vartime(&b_bytes));
+            assert_eq!(
+                JacobianPoint::mul_generator_add_vartime(&a_bytes, &b_bytes, &p),
+                expected
+            );
+            assert_eq!(
+                JacobianPoint::double_mul_vartime(&a_bytes, &g, &b_bytes, &p),
+                expected
+            );
+            let expected = q
+                .mul_vartime(&a_bytes)
+                .add_vartime(&p.mul_vartime(&b_bytes));
+            assert_eq!(
+                JacobianPoint::double_mul_vartime(&a_bytes, &q, &b_bytes, &p),
+                expected
+            );
+        }
+        // the same sums through the multi-scalar path
+        let (a, b) = (&pairs[3].0, &pairs[3].1);
+        let (a_bytes, b_bytes) = (biguint_to_32_bytes(a), biguint_to_32_bytes(b));
+        let terms = [(b_bytes, p), (a_bytes, q), ([0; 32], g)];
+        assert_eq!(
+            JacobianPoint::multi_mul_generator_add_vartime(&a_bytes, &terms),
+            g.mul_vartime(&a_bytes)
+                .add_vartime(&p.mul_vartime(&b_bytes))
+                .add_vartime(&q.mul_vartime(&a_bytes))
+        );
+        assert_eq!(
+            JacobianPoint::multi_mul_generator_add_vartime(&a_bytes, &[]),
+            g.mul_vartime(&a_bytes)
+        );
+
+        // u * G + v * P == 0 when P == -(u / v) * G
+        let a = biguint_to_32_bytes(&BigUint::from(5_u32));
+        let b = biguint_to_32_bytes(&(n - 5_u32));
+        assert_eq!(
+            JacobianPoint::mul_generator_add_vartime(&a, &b, &g),
+            JacobianPoint::INFINITY
+        );
+    }
+
+    #[test]
+    fn has_affine_x_test() {
+        let g = JacobianPoint::from_affine(&SECP256K1GENS).unwrap();
+        // a non-normalized representation of 3G
+        let three = g.double().add(&g);
+        if let EccPoint::Point(ecc) = three.to_affine() {
+            let x = FieldElement::from_biguint(&ecc.x.num.to_biguint().unwrap());
+            assert!(three.has_affine_x(&x));
+            assert!(!three.has_affine_x(&(x + FieldElement::ONE)));
+        }
+        assert!(!JacobianPoint::INFINITY.has_affine_x(&FieldElement::ZERO));
+    }
+
+    #[test]
+    fn lift_x_test() {
+        let g = JacobianPoint::from_affine(&SECP256K1GENS).unwrap();
+        let x = FieldElement::from_biguint(&SECP256K1GENS_X);
+        // G has an even y
+        assert_eq!(JacobianPoint::lift_x(&x, false), Some(g));
+        assert_eq!(JacobianPoint::lift_x(&x, true), Some(g.negate()));
+        // 5^3 + 7 is not a square mod p
+        assert_eq!(
+            JacobianPoint::lift_x(&FieldElement::from_biguint(&5_u32.into()), false),
+            None
+        );
+    }
+}
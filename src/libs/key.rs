@@ -1,75 +1,223 @@
-use num_bigint_dig::{BigUint, ModInverse, RandBigInt, ToBigUint};
-use num_traits::{One, Zero};
-
-use crate::libs::ecc::Ecc;
+use anyhow::bail;
+use hmac::{Hmac, Mac};
+use num_bigint_dig::BigUint;
+use num_integer::Integer;
+use rand::Rng;
+use sha2::Sha256;
+use subtle::{Choice, ConditionallySelectable};
 
 use super::{
-    ecc::{EccPoint, SECP256K1GENS, SECP256K1GENS_ORDER},
+    ecc::{tagged_hash, EccPoint, SECP256K1GENS_ORDER},
+    math::biguint_to_32_bytes,
     network::BitcoinNetwork,
-    signature::{base58_encode_with_checksum, Signature},
+    scalar::Scalar,
+    signature::{
+        base58_decode_with_checksum, base58_encode_with_checksum, RecoverableSignature,
+        SchnorrSignature, Signature,
+    },
 };
 
+const WIF_MAINNET_PREFIX: u8 = 0x80_u8;
+const WIF_TESTNET_PREFIX: u8 = 0xef_u8;
+
+type HmacSha256 = Hmac<Sha256>;
+
+fn hmac_sha256(key: &[u8], data: &[&[u8]]) -> [u8; 32] {
+    let mut mac = HmacSha256::new_from_slice(key).expect("HMAC accepts keys of any length");
+    for chunk in data {
+        mac.update(chunk);
+    }
+    mac.finalize().into_bytes().into()
+}
+
 #[non_exhaustive]
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Key {
-    pub secret: BigUint,
+    pub secret: Scalar,
     pub point: EccPoint,
 }
 
 impl Key {
     pub fn new(secret: BigUint) -> Self {
-        let point = &secret * &(*SECP256K1GENS);
+        if secret >= *SECP256K1GENS_ORDER {
+            panic!("[Key::new] secret is not below the group order")
+        }
+        let secret = Scalar::from_bytes(&biguint_to_32_bytes(&secret))
+            .expect("secret was checked to be below n");
+        Key::from_scalar(secret)
+    }
+
+    pub fn from_scalar(secret: Scalar) -> Self {
+        if bool::from(secret.is_zero()) {
+            panic!("Cannot multiple zero or minus value to EccPoint")
+        }
+        let point = EccPoint::mul_generator(&secret);
         Key { secret, point }
     }
 
-    fn rfc6979(&self, z: BigUint) -> BigUint {
-        todo!()
+    // Deterministic nonce from RFC 6979 section 3.2 with HMAC-SHA256
+    pub fn rfc6979(&self, z: &BigUint) -> Scalar {
+        let order = &(*SECP256K1GENS_ORDER);
+        let x = self.secret.to_bytes();
+        // bits2octets(z): qlen == hlen == 256, so only a single reduction is needed
+        let h1 = biguint_to_32_bytes(&(z % order));
+
+        let mut v = [0x01_u8; 32];
+        let mut k = [0x00_u8; 32];
+        k = hmac_sha256(&k, &[&v, &[0x00], &x, &h1]);
+        v = hmac_sha256(&k, &[&v]);
+        k = hmac_sha256(&k, &[&v, &[0x01], &x, &h1]);
+        v = hmac_sha256(&k, &[&v]);
+
+        loop {
+            v = hmac_sha256(&k, &[&v]);
+            match Scalar::from_bytes(&v) {
+                Some(candidate) if !bool::from(candidate.is_zero()) => return candidate,
+                _ => {}
+            }
+            k = hmac_sha256(&k, &[&v, &[0x00]]);
+            v = hmac_sha256(&k, &[&v]);
+        }
     }
 
+    // Signs with an RFC 6979 nonce, so the same key and message always give the same signature
     pub fn sign(&self, z: BigUint) -> Signature {
+        self.sign_recoverable(z).signature
+    }
+
+    // Same signature as `sign`, plus the recovery id for `EccPoint::recover`
+    pub fn sign_recoverable(&self, z: BigUint) -> RecoverableSignature {
+        let k = self.rfc6979(&z);
+        self.sign_with_nonce(z, k)
+    }
+
+    // Signs with a nonce drawn from the thread RNG; a weak RNG leaks the secret
+    pub fn sign_randomized(&self, z: BigUint) -> Signature {
         let mut rng = rand::thread_rng();
-        let k = rng.gen_biguint_range(&BigUint::zero(), &SECP256K1GENS_ORDER);
-        if let EccPoint::Point(point) = k.clone() * &(*SECP256K1GENS) {
-            let r = point.x.num.clone().to_biguint().unwrap();
-            let k_inv = k
-                .mod_inverse(&(*SECP256K1GENS_ORDER))
-                .unwrap()
-                .to_biguint()
-                .unwrap();
-            let mut s = BigUint::modpow(
-                &((z + &r * &self.secret) * k_inv),
-                &BigUint::one(),
-                &SECP256K1GENS_ORDER,
-            );
-            // Transaction's Malleability
-            if s > &(*SECP256K1GENS_ORDER) / 2.to_biguint().unwrap() {
-                s = &(*SECP256K1GENS_ORDER) - s;
+        loop {
+            match Scalar::from_bytes(&rng.gen()) {
+                Some(k) if !bool::from(k.is_zero()) => return self.sign_with_nonce(z, k).signature,
+                _ => {}
             }
-            return Signature::new(r, s);
+        }
+    }
+
+    fn sign_with_nonce(&self, z: BigUint, k: Scalar) -> RecoverableSignature {
+        let order = &(*SECP256K1GENS_ORDER);
+        if let EccPoint::Point(point) = EccPoint::mul_generator(&k) {
+            let x = point.x.num.to_biguint().unwrap();
+            let r = &x % order;
+            // s = (z + r * e) / k in constant time, since it mixes in the secret and the nonce
+            let s =
+                (Scalar::from_biguint(&z) + Scalar::from_biguint(&r) * self.secret) * k.invert();
+            // Transaction's Malleability
+            let is_high = s.is_high();
+            let s = Scalar::conditional_select(&s, &s.negate(), is_high);
+            // n - s is the signature made with -k, whose R has the opposite y
+            let y_odd = point.y.num.is_odd() != bool::from(is_high);
+            let recovery_id = u8::from(y_odd) | (u8::from(&x >= order) << 1);
+            return RecoverableSignature {
+                signature: Signature::new(r, s.to_biguint()),
+                recovery_id,
+            };
         }
         panic!("Generator is POI");
     }
 
+    // BIP340 default signing. `aux_rand` should be fresh randomness, though a fixed
+    // value still gives a valid (deterministic) signature
+    pub fn sign_schnorr(
+        &self,
+        msg: &[u8; 32],
+        aux_rand: &[u8; 32],
+    ) -> anyhow::Result<SchnorrSignature> {
+        let px = self.point.x_only()?;
+        // negate the secret if needed so that it matches lift_x(x(P))
+        let d = Scalar::conditional_select(
+            &self.secret,
+            &self.secret.negate(),
+            Choice::from(self.point.has_odd_y()? as u8),
+        );
+        let mut t = d.to_bytes();
+        for (byte, mask) in t.iter_mut().zip(tagged_hash("BIP0340/aux", aux_rand)) {
+            *byte ^= mask;
+        }
+        let nonce = tagged_hash("BIP0340/nonce", &[&t[..], &px, msg].concat());
+        let k = Scalar::from_bytes_reduced(&nonce);
+        if bool::from(k.is_zero()) {
+            bail!("[Key::sign_schnorr] nonce is zero");
+        }
+
+        let point_r = EccPoint::mul_generator(&k);
+        let k =
+            Scalar::conditional_select(&k, &k.negate(), Choice::from(point_r.has_odd_y()? as u8));
+        let rx = point_r.x_only()?;
+        let challenge = tagged_hash("BIP0340/challenge", &[&rx[..], &px, msg].concat());
+        let s = k + Scalar::from_bytes_reduced(&challenge) * d;
+        Ok(SchnorrSignature::new(rx, s.to_bytes()))
+    }
+
+    // Key-path signing key of a taproot output without a script tree (BIP341/BIP86):
+    // d' = d + hash_TapTweak(x(P)), with d negated first when P has an odd y
+    pub fn taproot_tweak(&self) -> anyhow::Result<Key> {
+        let tweak = tagged_hash("TapTweak", &self.point.x_only()?);
+        let tweak = match Scalar::from_bytes(&tweak) {
+            Some(tweak) => tweak,
+            None => bail!("[Key::taproot_tweak] tweak is out of range"),
+        };
+        let secret = Scalar::conditional_select(
+            &self.secret,
+            &self.secret.negate(),
+            Choice::from(self.point.has_odd_y()? as u8),
+        );
+        let tweaked = secret + tweak;
+        if bool::from(tweaked.is_zero()) {
+            bail!("[Key::taproot_tweak] tweaked secret is zero");
+        }
+        Ok(Key::from_scalar(tweaked))
+    }
+
+    // https://en.bitcoin.it/wiki/Wallet_import_format
     fn generate_wif_raw(
         &self,
         compressed: bool,
         network: BitcoinNetwork,
     ) -> Result<String, std::string::FromUtf8Error> {
         let prefix = match network {
-            BitcoinNetwork::MainNet => 0x80_u8,
-            BitcoinNetwork::TestNet => 0xef_u8,
+            BitcoinNetwork::MainNet => WIF_MAINNET_PREFIX,
+            BitcoinNetwork::TestNet | BitcoinNetwork::RegTest => WIF_TESTNET_PREFIX,
         };
-        let mut res: Vec<u8> = Vec::new();
+        let mut res: Vec<u8> = Vec::with_capacity(34);
         res.push(prefix);
-        eprintln!("[generate_wif_raw] prefix: {:?}", hex::encode(&res));
-        res.extend(&self.secret.to_bytes_be());
+        res.extend(self.secret.to_bytes());
         if compressed {
-            res.extend(b"01");
+            res.push(0x01);
         }
-        eprintln!("[generate_wif_raw] res: {:?}", hex::encode(&res));
         base58_encode_with_checksum(&res)
     }
 
+    // Returns the key, its network and whether the public key is meant to be compressed.
+    // Regtest shares the testnet prefix, so it comes back as `BitcoinNetwork::TestNet`.
+    pub fn from_wif(wif: &str) -> anyhow::Result<(Key, BitcoinNetwork, bool)> {
+        let raw = base58_decode_with_checksum(wif)?;
+        let compressed = match raw.len() {
+            33 => false,
+            34 if raw[33] == 0x01 => true,
+            34 => bail!("[Key::from_wif] invalid compression flag {:#04x}", raw[33]),
+            len => bail!("[Key::from_wif] invalid payload length {}", len),
+        };
+        let network = match raw[0] {
+            WIF_MAINNET_PREFIX => BitcoinNetwork::MainNet,
+            WIF_TESTNET_PREFIX => BitcoinNetwork::TestNet,
+            prefix => bail!("[Key::from_wif] unknown prefix {:#04x}", prefix),
+        };
+        let secret = match Scalar::from_bytes(raw[1..33].try_into()?) {
+            Some(secret) if !bool::from(secret.is_zero()) => secret,
+            _ => bail!("[Key::from_wif] secret is out of range"),
+        };
+        Ok((Key::from_scalar(secret), network, compressed))
+    }
+
     pub fn generate_wif_compressed(
         &self,
         network: BitcoinNetwork,
@@ -87,13 +235,160 @@ impl Key {
 
 #[cfg(test)]
 mod tests {
+    use digest::Digest;
     use num_bigint_dig::BigUint;
-    use num_traits::FromPrimitive;
+    use num_traits::{FromPrimitive, Num, Pow};
+    use sha2::Sha256;
 
-    use crate::libs::network::BitcoinNetwork;
+    use crate::libs::{
+        ecc::{EccPoint, SECP256K1GENS_ORDER},
+        network::BitcoinNetwork,
+        signature::base58_encode_with_checksum,
+    };
 
     use super::Key;
 
+    fn sha256_z(message: &str) -> BigUint {
+        BigUint::from_bytes_be(&Sha256::digest(message.as_bytes()))
+    }
+
+    fn from_hex(hex: &str) -> BigUint {
+        BigUint::from_str_radix(hex, 16).unwrap()
+    }
+
+    #[test]
+    fn rfc6979_test_vectors() {
+        // secp256k1 vectors shared by python-ecdsa, bitcoinjs-lib and trezor-crypto
+        let one = Key::new(BigUint::from(1_u32));
+        let vectors = [
+            (
+                &one,
+                "Satoshi Nakamoto",
+                "8f8a276c19f4149656b280621e358cce24f5f52542772691ee69063b74f15d15",
+            ),
+            (
+                &one,
+                "All those moments will be lost in time, like tears in rain. Time to die...",
+                "38aa22d72376b4dbc472e06c3ba403ee0a394da63fc58d88686c611aba98d6b3",
+            ),
+        ];
+        for (key, message, k) in vectors {
+            assert_eq!(key.rfc6979(&sha256_z(message)).to_biguint(), from_hex(k));
+        }
+
+        let max = Key::new(&(*SECP256K1GENS_ORDER) - 1_u32);
+        assert_eq!(
+            max.rfc6979(&sha256_z("Satoshi Nakamoto")).to_biguint(),
+            from_hex("33a19b60e25fb6f4435af53a3d42d493644827367e6453928554f43e49aa6f90")
+        );
+
+        let turing = Key::new(from_hex(
+            "f8b8af8ce3c7cca5e300d33939540c10d45ce001b8f252bfbc57ba0342904181",
+        ));
+        assert_eq!(
+            turing.rfc6979(&sha256_z("Alan Turing")).to_biguint(),
+            from_hex("525a82b70e67874398067543fd84c83d30c175fdc45fdeee082fe13b1d7cfdf1")
+        );
+    }
+
+    #[test]
+    fn deterministic_sign() {
+        let key = Key::new(BigUint::from(1_u32));
+        let z = sha256_z("Satoshi Nakamoto");
+        let sig = key.sign(z.clone());
+        assert_eq!(
+            sig.r,
+            from_hex("934b1ea10a4b3c1757e2b0c017d0b6143ce3c9a7e6a4a49860d7a6ab210ee3d8")
+        );
+        assert_eq!(
+            sig.s,
+            from_hex("2442ce9d2b916064108014783e923ec36b49743e2ffa1c4496f01a512aafd9e5")
+        );
+        assert_eq!(key.sign(z.clone()), sig);
+        key.point.verify_secp256k1(&z, &sig).unwrap();
+
+        let randomized = key.sign_randomized(z.clone());
+        key.point.verify_secp256k1(&z, &randomized).unwrap();
+    }
+
+    fn bytes32(hex: &str) -> [u8; 32] {
+        hex::decode(hex).unwrap().try_into().unwrap()
+    }
+
+    #[test]
+    fn schnorr_test_vectors() {
+        // BIP340 test vectors 0 to 2: secret key, x-only public key, aux_rand, message, signature
+        let vectors = [
+            (
+                "0000000000000000000000000000000000000000000000000000000000000003",
+                "f9308a019258c31049344f85f89d5229b531c845836f99b08601f113bce036f9",
+                "0000000000000000000000000000000000000000000000000000000000000000",
+                "0000000000000000000000000000000000000000000000000000000000000000",
+                "e907831f80848d1069a5371b402410364bdf1c5f8307b0084c55f1ce2dca821525f66a4a85ea8b71e482a74f382d2ce5ebeee8fdb2172f477df4900d310536c0",
+            ),
+            (
+                "b7e151628aed2a6abf7158809cf4f3c762e7160f38b4da56a784d9045190cfef",
+                "dff1d77f2a671c5f36183726db2341be58feae1da2deced843240f7b502ba659",
+                "0000000000000000000000000000000000000000000000000000000000000001",
+                "243f6a8885a308d313198a2e03707344a4093822299f31d0082efa98ec4e6c89",
+                "6896bd60eeae296db48a229ff71dfe071bde413e6d43f917dc8dcf8c78de33418906d11ac976abccb20b091292bff4ea897efcb639ea871cfa95f6de339e4b0a",
+            ),
+            (
+                "c90fdaa22168c234c4c6628b80dc1cd129024e088a67cc74020bbea63b14e5c9",
+                "dd308afec5777e13121fa72b9cc1b7cc0139715309b086c960e18fd969774eb8",
+                "c87aa53824b4d7ae2eb035a2b5bbbccc080e76cdc6d1692c4b0b62d798e6d906",
+                "7e2d58d8b3bcdf1abadec7829054f90dda9805aab56c77333024b9d0a508b75c",
+                "5831aaeed7b44bb74e5eab94ba9d4294c49bcf2a60728d8b4c200f50dd313c1bab745879a5ad954a72c45a91c3a51d3c7adea98d82f8481e0e1e03674a6f3fb7",
+            ),
+        ];
+        for (secret, pubkey, aux, msg, expected) in vectors {
+            let key = Key::new(from_hex(secret));
+            assert_eq!(hex::encode(key.point.x_only().unwrap()), pubkey);
+            let sig = key.sign_schnorr(&bytes32(msg), &bytes32(aux)).unwrap();
+            assert_eq!(hex::encode(sig.to_bytes()), expected);
+            key.point.verify_schnorr(&bytes32(msg), &sig).unwrap();
+
+            let mut tampered = sig.clone();
+            tampered.s[31] ^= 1;
+            assert!(key.point.verify_schnorr(&bytes32(msg), &tampered).is_err());
+        }
+    }
+
+    #[test]
+    fn taproot_tweak_test() {
+        for secret in [1_u32, 2, 3, 0xdeadbeef] {
+            let key = Key::new(BigUint::from(secret));
+            let tweaked = key.taproot_tweak().unwrap();
+            let output_key = key.point.taproot_output_key().unwrap();
+            assert_eq!(tweaked.point, output_key);
+
+            let msg = [0x42_u8; 32];
+            let sig = tweaked.sign_schnorr(&msg, &[0_u8; 32]).unwrap();
+            output_key.verify_schnorr(&msg, &sig).unwrap();
+            assert!(key.point.verify_schnorr(&msg, &sig).is_err());
+        }
+    }
+
+    #[test]
+    fn sign_recoverable_test() {
+        let key = Key::new(BigUint::from(1_u32));
+        let z = sha256_z("Satoshi Nakamoto");
+        let sig = key.sign_recoverable(z.clone());
+        assert_eq!(sig.signature, key.sign(z.clone()));
+        assert_eq!(sig.recovery_id, 1);
+        assert_eq!(EccPoint::recover(&z, &sig).unwrap(), key.point);
+
+        // low-s normalization flips the parity of R.y
+        for secret in [2_u32, 3, 0xdeadbeef, 0x1234567] {
+            let key = Key::new(BigUint::from(secret));
+            for message in ["a", "b", "c", "d"] {
+                let z = sha256_z(message);
+                let sig = key.sign_recoverable(z.clone());
+                assert_eq!(EccPoint::recover(&z, &sig).unwrap(), key.point);
+            }
+        }
+    }
+
     #[test]
     fn secret_key_to_address() {
         let k = Key::new(BigUint::from_u128(5002_u128).unwrap());
@@ -101,16 +396,42 @@ mod tests {
             .point
             .gernerate_address_from_sec(BitcoinNetwork::TestNet)
             .unwrap();
-        eprintln!("{:?}", t);
-        panic!("")
+        assert_eq!(t, "mmTPbXQFxboEtNRkwfh6K51jvdtHLxGeMA");
     }
 
     #[test]
     fn wif_test() {
-        let key = Key::new(BigUint::from_i32(5003).unwrap());
-        let res = key.generate_wif_compressed(BitcoinNetwork::TestNet);
-        eprintln!("{}", res.unwrap());
-        panic!("")
+        // Programming Bitcoin, chapter 4 exercise 6
+        let cases = [
+            (
+                BigUint::from_i32(5003).unwrap(),
+                true,
+                BitcoinNetwork::TestNet,
+                "cMahea7zqjxrtgAbB7LSGbcQUr1uX1ojuat9jZodMN8rFTv2sfUK",
+            ),
+            (
+                BigUint::from_i32(2021).unwrap().pow(5_u32),
+                false,
+                BitcoinNetwork::TestNet,
+                "91avARGdfge8E4tZfYLoxeJ5sGBdNJQH4kvjpWAxgzczjbCwxic",
+            ),
+            (
+                BigUint::parse_bytes(b"54321deadbeef", 16).unwrap(),
+                true,
+                BitcoinNetwork::MainNet,
+                "KwDiBf89QgGbjEhKnhXJuH7LrciVrZi3qYjgiuQJv1h8Ytr2S53a",
+            ),
+        ];
+        for (secret, compressed, network, expected) in cases {
+            let key = Key::new(secret);
+            let wif = if compressed {
+                key.generate_wif_compressed(network).unwrap()
+            } else {
+                key.generate_wif(network).unwrap()
+            };
+            assert_eq!(wif, expected);
+            assert_eq!(Key::from_wif(&wif).unwrap(), (key, network, compressed));
+        }
     }
 
     #[test]
@@ -122,7 +443,32 @@ mod tests {
             )
             .unwrap(),
         );
-        eprintln!("{}", key.generate_wif(BitcoinNetwork::MainNet).unwrap());
-        panic!("pan")
+        let wif = key.generate_wif(BitcoinNetwork::MainNet).unwrap();
+        assert_eq!(wif, "5HueCGU8rMjxEXxiPuD5BDku4MkFqeZyd4dZ1jvhTVqvbTLvyTJ");
+        assert_eq!(
+            Key::from_wif(&wif).unwrap(),
+            (key, BitcoinNetwork::MainNet, false)
+        );
+    }
+
+    #[test]
+    fn from_wif_invalid_test() {
+        // bad checksum
+        assert!(Key::from_wif("5HueCGU8rMjxEXxiPuD5BDku4MkFqeZyd4dZ1jvhTVqvbTLvyTK").is_err());
+        // zero secret
+        let mut raw = vec![0x80_u8];
+        raw.extend([0_u8; 32]);
+        let zero = base58_encode_with_checksum(&raw).unwrap();
+        assert!(Key::from_wif(&zero).is_err());
+        // compression flag other than 0x01
+        raw[32] = 1;
+        raw.push(0x02);
+        let bad_flag = base58_encode_with_checksum(&raw).unwrap();
+        assert!(Key::from_wif(&bad_flag).is_err());
+        // unknown prefix
+        raw.pop();
+        raw[0] = 0x00;
+        let bad_prefix = base58_encode_with_checksum(&raw).unwrap();
+        assert!(Key::from_wif(&bad_prefix).is_err());
     }
 }
@@ -0,0 +1,193 @@
+use subtle::{Choice, ConditionallySelectable};
+
+// 256-bit numbers as four little-endian u64 limbs, shared by `FieldElement` and `Scalar`.
+// Nothing in here branches on or indexes by limb values.
+pub type Limbs = [u64; 4];
+
+#[inline(always)]
+fn adc(a: u64, b: u64, carry: u64) -> (u64, u64) {
+    let t = a as u128 + b as u128 + carry as u128;
+    (t as u64, (t >> 64) as u64)
+}
+
+#[inline(always)]
+fn sbb(a: u64, b: u64, borrow: u64) -> (u64, u64) {
+    let t = (a as u128).wrapping_sub(b as u128 + borrow as u128);
+    (t as u64, (t >> 127) as u64)
+}
+
+// returns (a + b) mod 2^256 and the carry
+pub fn add(a: &Limbs, b: &Limbs) -> (Limbs, u64) {
+    let mut res = [0_u64; 4];
+    let mut carry = 0;
+    for i in 0..4 {
+        (res[i], carry) = adc(a[i], b[i], carry);
+    }
+    (res, carry)
+}
+
+// returns (a - b) mod 2^256 and the borrow
+pub fn sub(a: &Limbs, b: &Limbs) -> (Limbs, u64) {
+    let mut res = [0_u64; 4];
+    let mut borrow = 0;
+    for i in 0..4 {
+        (res[i], borrow) = sbb(a[i], b[i], borrow);
+    }
+    (res, borrow)
+}
+
+// `b` if `choice` is set, `a` otherwise
+pub fn select(a: &Limbs, b: &Limbs, choice: Choice) -> Limbs {
+    let mut res = [0_u64; 4];
+    for i in 0..4 {
+        res[i] = u64::conditional_select(&a[i], &b[i], choice);
+    }
+    res
+}
+
+pub fn mul_wide(a: &Limbs, b: &Limbs) -> [u64; 8] {
+    let mut res = [0_u64; 8];
+    for i in 0..4 {
+        let mut carry = 0_u128;
+        for j in 0..4 {
+            let t = res[i + j] as u128 + a[i] as u128 * b[j] as u128 + carry;
+            res[i + j] = t as u64;
+            carry = t >> 64;
+        }
+        res[i + 4] = carry as u64;
+    }
+    res
+}
+
+// (a + b) mod m for a, b < m
+pub fn add_mod(a: &Limbs, b: &Limbs, m: &Limbs) -> Limbs {
+    let (sum, carry) = add(a, b);
+    let (diff, borrow) = sub(&sum, m);
+    // sum is already reduced only if it didn't overflow and is below m
+    let keep_sum = Choice::from(((carry ^ 1) & borrow) as u8);
+    select(&diff, &sum, keep_sum)
+}
+
+// (a - b) mod m for a, b < m
+pub fn sub_mod(a: &Limbs, b: &Limbs, m: &Limbs) -> Limbs {
+    let (diff, borrow) = sub(a, b);
+    let correction = select(&[0; 4], m, Choice::from(borrow as u8));
+    add(&diff, &correction).0
+}
+
+// Reduces a 512-bit number modulo m, where c = 2^256 - m and m > 2^255.
+// Folds the high half with 2^256 == c (mod m); four rounds are enough for
+// any c below 2^129, which covers both the field prime and the group order.
+pub fn reduce_wide(wide: &[u64; 8], c: &Limbs, m: &Limbs) -> Limbs {
+    let mut lo: Limbs = [wide[0], wide[1], wide[2], wide[3]];
+    let mut hi: Limbs = [wide[4], wide[5], wide[6], wide[7]];
+    for _ in 0..4 {
+        let prod = mul_wide(&hi, c);
+        let (sum, carry) = add(&lo, &[prod[0], prod[1], prod[2], prod[3]]);
+        let (next_hi, _) = add(&[prod[4], prod[5], prod[6], prod[7]], &[carry, 0, 0, 0]);
+        lo = sum;
+        hi = next_hi;
+    }
+    let (diff, borrow) = sub(&lo, m);
+    select(&diff, &lo, Choice::from(borrow as u8))
+}
+
+// Same as `reduce_wide` for a single limb c = 2^256 - m below 2^64, which is the
+// case for the secp256k1 field prime (c = 2^32 + 977).
+pub fn reduce_wide_single(wide: &[u64; 8], c: u64, m: &Limbs) -> Limbs {
+    // lo + hi * c, the carry is below 2^65
+    let mut res = [0_u64; 4];
+    let mut carry = 0_u128;
+    for i in 0..4 {
+        let t = wide[i] as u128 + wide[i + 4] as u128 * c as u128 + carry;
+        res[i] = t as u64;
+        carry = t >> 64;
+    }
+    // fold the carry twice; the second fold can't overflow again since res is small then
+    for _ in 0..2 {
+        let mut t = res[0] as u128 + carry * c as u128;
+        res[0] = t as u64;
+        for limb in res.iter_mut().skip(1) {
+            t = *limb as u128 + (t >> 64);
+            *limb = t as u64;
+        }
+        carry = t >> 64;
+    }
+    let (diff, borrow) = sub(&res, m);
+    select(&diff, &res, Choice::from(borrow as u8))
+}
+
+// Fixed 4-bit window exponentiation. Only for public exponents: the sequence of
+// operations is fixed, but the table lookup indexes by exponent bits.
+pub fn pow_public<T: Copy>(base: &T, one: T, exp: &Limbs, mul: impl Fn(&T, &T) -> T) -> T {
+    let mut table = [one; 16];
+    for i in 1..16 {
+        table[i] = mul(&table[i - 1], base);
+    }
+    let mut res = one;
+    for limb in exp.iter().rev() {
+        for window in (0..16).rev() {
+            for _ in 0..4 {
+                res = mul(&res, &res);
+            }
+            let index = ((limb >> (4 * window)) & 0xf) as usize;
+            res = mul(&res, &table[index]);
+        }
+    }
+    res
+}
+
+// big-endian bytes, as used by SEC, DER and the hash functions
+pub fn from_bytes_be(bytes: &[u8; 32]) -> Limbs {
+    let mut res = [0_u64; 4];
+    for (i, limb) in res.iter_mut().enumerate() {
+        let start = 32 - 8 * (i + 1);
+        *limb = u64::from_be_bytes(bytes[start..start + 8].try_into().unwrap());
+    }
+    res
+}
+
+pub fn to_bytes_be(limbs: &Limbs) -> [u8; 32] {
+    let mut res = [0_u8; 32];
+    for (i, limb) in limbs.iter().enumerate() {
+        let start = 32 - 8 * (i + 1);
+        res[start..start + 8].copy_from_slice(&limb.to_be_bytes());
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{add, from_bytes_be, mul_wide, reduce_wide, sub, to_bytes_be};
+
+    #[test]
+    fn limbs_test() {
+        let max = [u64::MAX; 4];
+        assert_eq!(add(&max, &[1, 0, 0, 0]), ([0; 4], 1));
+        assert_eq!(sub(&[0; 4], &[1, 0, 0, 0]), (max, 1));
+        // (2^256 - 1)^2 = 2^512 - 2^257 + 1
+        assert_eq!(
+            mul_wide(&max, &max),
+            [1, 0, 0, 0, u64::MAX - 1, u64::MAX, u64::MAX, u64::MAX]
+        );
+
+        let mut bytes = [0_u8; 32];
+        bytes[0] = 0x01;
+        bytes[31] = 0x02;
+        let limbs = from_bytes_be(&bytes);
+        assert_eq!(limbs, [0x02, 0, 0, 0x01 << 56]);
+        assert_eq!(to_bytes_be(&limbs), bytes);
+    }
+
+    #[test]
+    fn reduce_wide_test() {
+        // modulo 2^256 - 1, 2^256 == 1 so the halves are just added
+        let m = [u64::MAX; 4];
+        let c = [1, 0, 0, 0];
+        assert_eq!(
+            reduce_wide(&[5, 0, 0, 0, 7, 0, 0, 0], &c, &m),
+            [12, 0, 0, 0]
+        );
+        assert_eq!(reduce_wide(&[u64::MAX; 8], &c, &m), [0; 4]);
+    }
+}
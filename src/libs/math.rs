@@ -1,8 +1,23 @@
+use num_bigint_dig::BigUint;
+
+// big-endian, left-padded with zeros; panics if `num` doesn't fit in 32 bytes
+pub fn biguint_to_32_bytes(num: &BigUint) -> [u8; 32] {
+    let bytes = num.to_bytes_be();
+    if bytes.len() > 32 {
+        panic!("[biguint_to_32_bytes] {} doesn't fit in 32 bytes", num);
+    }
+    let mut res = [0_u8; 32];
+    res[32 - bytes.len()..].copy_from_slice(&bytes);
+    res
+}
+
 #[cfg(test)]
 mod tests {
-    use num_bigint::BigInt;
+    use num_bigint::{BigInt, BigUint};
     use num_traits::One;
 
+    use super::biguint_to_32_bytes;
+
     #[test]
     fn modpow_test1() {
         let a = BigInt::parse_bytes(b"1233", 10).unwrap();
@@ -10,4 +25,13 @@ mod tests {
         let c = a.modpow(&BigInt::one(), &b);
         assert_eq!(&c, &BigInt::parse_bytes(b"207", 10).unwrap());
     }
+
+    #[test]
+    fn biguint_to_32_bytes_test() {
+        let mut expected = [0_u8; 32];
+        expected[30] = 0x01;
+        expected[31] = 0x02;
+        assert_eq!(biguint_to_32_bytes(&BigUint::from(0x0102_u32)), expected);
+        assert_eq!(biguint_to_32_bytes(&BigUint::from(0_u32)), [0_u8; 32]);
+    }
 }
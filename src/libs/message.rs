@@ -0,0 +1,130 @@
+use anyhow::bail;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use num_bigint_dig::BigUint;
+
+use super::{
+    address::Address,
+    ecc::{hash256, EccPoint},
+    key::Key,
+    signature::RecoverableSignature,
+    transaction::varint_representation,
+};
+
+pub const BITCOIN_SIGNED_MESSAGE_MAGIC: &str = "Bitcoin Signed Message:\n";
+
+// hash256(varint(len(magic)) || magic || varint(len(message)) || message)
+pub fn signed_message_hash(message: &str) -> Vec<u8> {
+    let mut data = Vec::new();
+    for part in [BITCOIN_SIGNED_MESSAGE_MAGIC, message] {
+        data.extend(varint_representation(part.len() as u64));
+        data.extend(part.as_bytes());
+    }
+    hash256(&data)
+}
+
+// `signmessage`: the base64 compact signature, whose header records whether the
+// address was made from the compressed SEC
+pub fn sign_message(key: &Key, message: &str, compressed: bool) -> String {
+    let z = BigUint::from_bytes_be(&signed_message_hash(message));
+    let sig = key.sign_recoverable(z);
+    STANDARD.encode(sig.to_compact(compressed))
+}
+
+// `verifymessage`: recovers the key from the signature and checks that it hashes
+// to the given P2PKH address
+pub fn verify_message(address: &str, signature: &str, message: &str) -> anyhow::Result<()> {
+    let address: Address = address.parse()?;
+    let network = match address {
+        Address::P2pkh { network, .. } => network,
+        _ => bail!("[verify_message] only P2PKH addresses can verify a signed message"),
+    };
+    let bytes = match STANDARD.decode(signature) {
+        Ok(bytes) => bytes,
+        Err(e) => bail!("[verify_message] signature is not valid base64: {}", e),
+    };
+    let (sig, compressed) = RecoverableSignature::from_compact(&bytes)?;
+
+    let z = BigUint::from_bytes_be(&signed_message_hash(message));
+    let point = EccPoint::recover(&z, &sig)?;
+    let recovered = Address::p2pkh(&point, compressed, network)?;
+    if recovered != address {
+        bail!(
+            "[verify_message] signature belongs to {}, not {}",
+            recovered,
+            address
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use base64::{engine::general_purpose::STANDARD, Engine};
+
+    use crate::libs::{key::Key, network::BitcoinNetwork};
+
+    use super::{sign_message, signed_message_hash, verify_message};
+
+    #[test]
+    fn signed_message_vector_test() {
+        // from bitcoinjs-message
+        let (key, network, compressed) =
+            Key::from_wif("L4rK1yDtCWekvXuE6oXD9jCYfFNV2cWRpVuPLBcCU2z8TrisoyY1").unwrap();
+        let address = "1F3sAm6ZtwLAUnj7d38pGFxtP3RVEvtsbV";
+        let message = "This is an example of a signed message.";
+        let expected = "H9L5yLFjti0QTHhPyFrZCT1V/MMnBtXKmoiKDZ78NDBjERki6ZTQZdSMCtkgoNmp17By9ItJr8o7ChX0XxY91nk=";
+
+        assert_eq!(network, BitcoinNetwork::MainNet);
+        assert!(compressed);
+        assert_eq!(sign_message(&key, message, compressed), expected);
+        verify_message(address, expected, message).unwrap();
+        assert!(verify_message(address, expected, "This is another message.").is_err());
+    }
+
+    #[test]
+    fn signed_message_roundtrip_test() {
+        let key = Key::new(0xdeadbeef_u32.into());
+        for compressed in [true, false] {
+            for network in [BitcoinNetwork::MainNet, BitcoinNetwork::TestNet] {
+                let address = match compressed {
+                    true => key.point.gernerate_address_from_sec_compressed(network),
+                    false => key.point.gernerate_address_from_sec(network),
+                }
+                .unwrap();
+                let signature = sign_message(&key, "ritcoin", compressed);
+                verify_message(&address, &signature, "ritcoin").unwrap();
+
+                // the same key under the other SEC form is a different address
+                let other = match compressed {
+                    true => key.point.gernerate_address_from_sec(network),
+                    false => key.point.gernerate_address_from_sec_compressed(network),
+                }
+                .unwrap();
+                assert!(verify_message(&other, &signature, "ritcoin").is_err());
+            }
+        }
+    }
+
+    #[test]
+    fn signed_message_error_test() {
+        let key = Key::new(2019_u32.into());
+        let address = key
+            .point
+            .gernerate_address_from_sec_compressed(BitcoinNetwork::MainNet)
+            .unwrap();
+        let signature = sign_message(&key, "hello", true);
+        let mut bytes = STANDARD.decode(&signature).unwrap();
+
+        assert!(verify_message(&address, "not base64!", "hello").is_err());
+        assert!(verify_message(&address, &STANDARD.encode(&bytes[..64]), "hello").is_err());
+        bytes[0] = 35;
+        assert!(verify_message(&address, &STANDARD.encode(&bytes), "hello").is_err());
+        assert!(verify_message(
+            "bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4",
+            &signature,
+            "hello"
+        )
+        .is_err());
+        assert_eq!(signed_message_hash("hello").len(), 32);
+    }
+}
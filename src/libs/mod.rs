@@ -5,6 +5,16 @@ pub mod key;
 pub mod signature;
 #[macro_use]
 pub mod macros;
+pub mod address;
+pub mod bech32;
+pub mod bip322;
+pub mod codec;
 pub mod ecc;
+pub mod field_element;
+pub mod interpreter;
+pub mod jacobian;
+pub mod limbs;
 pub mod finite_field;
 pub mod math;
+pub mod message;
+pub mod scalar;
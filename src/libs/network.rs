@@ -1,26 +1,99 @@
-use digest::Digest;
-use sha2::Sha256;
+use std::io::{Read, Write};
+
+use super::{
+    codec::{read_array, read_vec, write_all, CodecError, Decodable, Encodable},
+    ecc::hash256,
+};
 
 pub const BITCOIN_MAINNET_PREFIX: u8 = 0x00_u8;
 pub const BITCOIN_TESTNET_PREFIX: u8 = 0x6f_u8;
+pub const BITCOIN_MAINNET_P2SH_PREFIX: u8 = 0x05_u8;
+pub const BITCOIN_TESTNET_P2SH_PREFIX: u8 = 0xc4_u8;
+
+pub const BITCOIN_MAINNET_HRP: &str = "bc";
+pub const BITCOIN_TESTNET_HRP: &str = "tb";
+pub const BITCOIN_REGTEST_HRP: &str = "bcrt";
 
 pub const BITCOIN_NETWORK_MAGIC_FLAG_MAINNET: [u8; 4] = [0xf9, 0xbe, 0xb4, 0xd9];
 pub const BITCOIN_NETWORK_MAGIC_FLAG_TESTNET: [u8; 4] = [0x0b, 0x11, 0x09, 0x07];
+pub const BITCOIN_NETWORK_MAGIC_FLAG_REGTEST: [u8; 4] = [0xfa, 0xbf, 0xb5, 0xda];
 
+// regtest shares the testnet base58 prefixes but has its own bech32 hrp
 #[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum BitcoinNetwork {
     MainNet,
     TestNet,
+    RegTest,
 }
 
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct NetworkMagic {
     magic: [u8; 4],
 }
 
+impl NetworkMagic {
+    pub fn new(network: BitcoinNetwork) -> NetworkMagic {
+        let magic = match network {
+            BitcoinNetwork::MainNet => BITCOIN_NETWORK_MAGIC_FLAG_MAINNET,
+            BitcoinNetwork::TestNet => BITCOIN_NETWORK_MAGIC_FLAG_TESTNET,
+            BitcoinNetwork::RegTest => BITCOIN_NETWORK_MAGIC_FLAG_REGTEST,
+        };
+        NetworkMagic { magic }
+    }
+}
+
+impl Encodable for NetworkMagic {
+    fn encode<W: Write>(&self, writer: &mut W) -> Result<usize, CodecError> {
+        write_all(writer, &self.magic)
+    }
+}
+
+impl Decodable for NetworkMagic {
+    fn decode<R: Read>(reader: &mut R) -> Result<Self, CodecError> {
+        Ok(NetworkMagic {
+            magic: read_array(reader)?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct NetworkCommand {
     command: [u8; 12],
 }
 
+impl NetworkCommand {
+    // ascii command name, right-padded with null bytes
+    pub fn new(name: &str) -> Result<NetworkCommand, CodecError> {
+        let mut command = [0_u8; 12];
+        if name.len() > command.len() {
+            return Err(CodecError::CommandTooLong { length: name.len() });
+        }
+        command[..name.len()].copy_from_slice(name.as_bytes());
+        Ok(NetworkCommand { command })
+    }
+
+    pub fn name(&self) -> String {
+        let end = self.command.iter().position(|&b| b == 0).unwrap_or(12);
+        String::from_utf8_lossy(&self.command[..end]).to_string()
+    }
+}
+
+impl Encodable for NetworkCommand {
+    fn encode<W: Write>(&self, writer: &mut W) -> Result<usize, CodecError> {
+        write_all(writer, &self.command)
+    }
+}
+
+impl Decodable for NetworkCommand {
+    fn decode<R: Read>(reader: &mut R) -> Result<Self, CodecError> {
+        Ok(NetworkCommand {
+            command: read_array(reader)?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct NetworkPayload {
     payload_length: [u8; 4],
     payload_checksum: [u8; 4],
@@ -31,22 +104,129 @@ impl NetworkPayload {
     pub fn new(payload: Vec<u8>) -> NetworkPayload {
         let length: [u8; 4] = u32::to_le_bytes(payload.len() as u32);
 
-        let _checksum = Sha256::digest(&payload).to_vec();
-        let checksum: [u8; 4] = _checksum
-            .as_slice()
-            .try_into()
-            .expect("fail to cast to array");
-
         NetworkPayload {
             payload_length: length,
-            payload_checksum: checksum,
+            payload_checksum: payload_checksum(&payload),
             payload,
         }
     }
+
+    pub fn payload(&self) -> &[u8] {
+        &self.payload
+    }
+}
+
+// first 4 bytes of hash256(payload)
+fn payload_checksum(payload: &[u8]) -> [u8; 4] {
+    hash256(payload)[0..4]
+        .try_into()
+        .expect("checksum must be 4 bytes")
+}
+
+impl Encodable for NetworkPayload {
+    fn encode<W: Write>(&self, writer: &mut W) -> Result<usize, CodecError> {
+        let mut len = write_all(writer, &self.payload_length)?;
+        len += write_all(writer, &self.payload_checksum)?;
+        len += write_all(writer, &self.payload)?;
+        Ok(len)
+    }
+}
+
+impl Decodable for NetworkPayload {
+    fn decode<R: Read>(reader: &mut R) -> Result<Self, CodecError> {
+        let payload_length: [u8; 4] = read_array(reader)?;
+        let payload_checksum: [u8; 4] = read_array(reader)?;
+        let payload = read_vec(reader, u32::from_le_bytes(payload_length) as u64)?;
+
+        let actual = self::payload_checksum(&payload);
+        if actual != payload_checksum {
+            return Err(CodecError::ChecksumMismatch {
+                expected: payload_checksum,
+                actual,
+            });
+        }
+        Ok(NetworkPayload {
+            payload_length,
+            payload_checksum,
+            payload,
+        })
+    }
 }
 
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Network {
     pub network_magic: NetworkMagic,
     pub command: NetworkCommand,
     pub payload: NetworkPayload,
 }
+
+impl Encodable for Network {
+    fn encode<W: Write>(&self, writer: &mut W) -> Result<usize, CodecError> {
+        let mut len = self.network_magic.encode(writer)?;
+        len += self.command.encode(writer)?;
+        len += self.payload.encode(writer)?;
+        Ok(len)
+    }
+}
+
+impl Decodable for Network {
+    fn decode<R: Read>(reader: &mut R) -> Result<Self, CodecError> {
+        Ok(Network {
+            network_magic: NetworkMagic::decode(reader)?,
+            command: NetworkCommand::decode(reader)?,
+            payload: NetworkPayload::decode(reader)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::libs::codec::{CodecError, Decodable, Encodable};
+
+    use super::{BitcoinNetwork, Network, NetworkCommand, NetworkMagic, NetworkPayload};
+
+    const VERACK: &str = "f9beb4d976657261636b000000000000000000005df6e0e2";
+
+    #[test]
+    fn network_message_roundtrip() {
+        let raw = hex::decode(VERACK).unwrap();
+        let message = Network::decode(&mut raw.as_slice()).unwrap();
+        assert_eq!(
+            message.network_magic,
+            NetworkMagic::new(BitcoinNetwork::MainNet)
+        );
+        assert_eq!(message.command.name(), "verack");
+        assert!(message.payload.payload().is_empty());
+        assert_eq!(message.serialize(), raw);
+
+        let built = Network {
+            network_magic: NetworkMagic::new(BitcoinNetwork::MainNet),
+            command: NetworkCommand::new("verack").unwrap(),
+            payload: NetworkPayload::new(vec![]),
+        };
+        assert_eq!(built, message);
+    }
+
+    #[test]
+    fn network_command_too_long() {
+        assert_eq!(
+            NetworkCommand::new("sendaddrv2xx").unwrap().name(),
+            "sendaddrv2xx"
+        );
+        assert!(matches!(
+            NetworkCommand::new("sendaddrv2xxx"),
+            Err(CodecError::CommandTooLong { length: 13 })
+        ));
+    }
+
+    #[test]
+    fn network_payload_bad_checksum() {
+        let mut raw = hex::decode(VERACK).unwrap();
+        let last = raw.len() - 1;
+        raw[last] ^= 0xff;
+        assert!(matches!(
+            Network::decode(&mut raw.as_slice()),
+            Err(CodecError::ChecksumMismatch { .. })
+        ));
+    }
+}
@@ -0,0 +1,215 @@
+use num_bigint_dig::BigUint;
+use subtle::{Choice, ConditionallySelectable, ConstantTimeEq};
+
+use super::limbs::{self, Limbs};
+
+// n, the order of the secp256k1 generator
+const N: Limbs = [
+    0xBFD25E8CD0364141,
+    0xBAAEDCE6AF48A03B,
+    0xFFFFFFFFFFFFFFFE,
+    0xFFFFFFFFFFFFFFFF,
+];
+// 2^256 - n
+const N_COMPLEMENT: Limbs = [0x402DA1732FC9BEBF, 0x4551231950B75FC4, 0x1, 0x0];
+const N_MINUS_2: Limbs = [
+    0xBFD25E8CD036413F,
+    0xBAAEDCE6AF48A03B,
+    0xFFFFFFFFFFFFFFFE,
+    0xFFFFFFFFFFFFFFFF,
+];
+// floor(n / 2), the largest low-S value
+const N_HALF: Limbs = [
+    0xDFE92F46681B20A0,
+    0x5D576E7357A4501D,
+    0xFFFFFFFFFFFFFFFF,
+    0x7FFFFFFFFFFFFFFF,
+];
+
+// Integer modulo the group order n, used for secrets, nonces and signature values.
+// Arithmetic runs in constant time with respect to the values.
+#[derive(Debug, Clone, Copy)]
+pub struct Scalar(Limbs);
+
+impl Scalar {
+    pub const ZERO: Scalar = Scalar([0; 4]);
+    pub const ONE: Scalar = Scalar([1, 0, 0, 0]);
+
+    // None if `bytes` is not below n
+    pub fn from_bytes(bytes: &[u8; 32]) -> Option<Scalar> {
+        let limbs = limbs::from_bytes_be(bytes);
+        let (_, borrow) = limbs::sub(&limbs, &N);
+        if borrow == 1 {
+            Some(Scalar(limbs))
+        } else {
+            None
+        }
+    }
+
+    // reduces modulo n, e.g. for message hashes
+    pub fn from_bytes_reduced(bytes: &[u8; 32]) -> Scalar {
+        let limbs = limbs::from_bytes_be(bytes);
+        let (diff, borrow) = limbs::sub(&limbs, &N);
+        Scalar(limbs::select(&diff, &limbs, Choice::from(borrow as u8)))
+    }
+
+    pub fn to_bytes(&self) -> [u8; 32] {
+        limbs::to_bytes_be(&self.0)
+    }
+
+    // reduces modulo n
+    pub fn from_biguint(num: &BigUint) -> Scalar {
+        let reduced = num % BigUint::from_bytes_be(&limbs::to_bytes_be(&N));
+        let mut bytes = [0_u8; 32];
+        let raw = reduced.to_bytes_be();
+        bytes[32 - raw.len()..].copy_from_slice(&raw);
+        Scalar(limbs::from_bytes_be(&bytes))
+    }
+
+    pub fn to_biguint(&self) -> BigUint {
+        BigUint::from_bytes_be(&self.to_bytes())
+    }
+
+    // little-endian limbs, for bit-by-bit scalar multiplication
+    pub fn limbs(&self) -> &Limbs {
+        &self.0
+    }
+
+    pub fn is_zero(&self) -> Choice {
+        self.ct_eq(&Scalar::ZERO)
+    }
+
+    // BIP62: s is high if it is above n / 2
+    pub fn is_high(&self) -> Choice {
+        let (_, borrow) = limbs::sub(&N_HALF, &self.0);
+        Choice::from(borrow as u8)
+    }
+
+    pub fn negate(&self) -> Scalar {
+        Scalar(limbs::sub_mod(&[0; 4], &self.0, &N))
+    }
+
+    // Fermat inversion with the public exponent n - 2; zero maps to zero
+    pub fn invert(&self) -> Scalar {
+        limbs::pow_public(self, Scalar::ONE, &N_MINUS_2, |a, b| a * b)
+    }
+
+    // Montgomery's trick: one inversion and three multiplications per element.
+    // Zeros map to zeros, as with `invert`.
+    pub fn batch_invert(scalars: &[Scalar]) -> Vec<Scalar> {
+        let mut prefix = Vec::with_capacity(scalars.len());
+        let mut acc = Scalar::ONE;
+        for s in scalars {
+            prefix.push(acc);
+            acc = acc * Scalar::conditional_select(s, &Scalar::ONE, s.is_zero());
+        }
+        let mut acc_inv = acc.invert();
+        let mut res = vec![Scalar::ZERO; scalars.len()];
+        for (i, s) in scalars.iter().enumerate().rev() {
+            let is_zero = s.is_zero();
+            res[i] = Scalar::conditional_select(&(acc_inv * prefix[i]), &Scalar::ZERO, is_zero);
+            acc_inv = acc_inv * Scalar::conditional_select(s, &Scalar::ONE, is_zero);
+        }
+        res
+    }
+}
+
+impl ConstantTimeEq for Scalar {
+    fn ct_eq(&self, other: &Self) -> Choice {
+        self.0.ct_eq(&other.0)
+    }
+}
+
+impl ConditionallySelectable for Scalar {
+    fn conditional_select(a: &Self, b: &Self, choice: Choice) -> Self {
+        Scalar(limbs::select(&a.0, &b.0, choice))
+    }
+}
+
+impl PartialEq for Scalar {
+    fn eq(&self, other: &Self) -> bool {
+        self.ct_eq(other).into()
+    }
+}
+
+impl Eq for Scalar {}
+
+overloading!((lhs : Scalar) + (rhs : Scalar) => Scalar as {
+    Scalar(limbs::add_mod(&lhs.0, &rhs.0, &N))
+});
+overloading!((lhs : Scalar) - (rhs : Scalar) => Scalar as {
+    Scalar(limbs::sub_mod(&lhs.0, &rhs.0, &N))
+});
+overloading!((lhs : Scalar) * (rhs : Scalar) => Scalar as {
+    Scalar(limbs::reduce_wide(&limbs::mul_wide(&lhs.0, &rhs.0), &N_COMPLEMENT, &N))
+});
+
+#[cfg(test)]
+mod tests {
+    use num_bigint_dig::{BigUint, ModInverse, RandBigInt};
+    use num_traits::One;
+
+    use crate::libs::ecc::SECP256K1GENS_ORDER;
+
+    use super::Scalar;
+
+    #[test]
+    fn scalar_arithmetic_test() {
+        let n = &(*SECP256K1GENS_ORDER);
+        let mut rng = rand::thread_rng();
+        let mut samples = vec![BigUint::from(0_u32), BigUint::one(), n - 1_u32, n >> 1];
+        samples.extend((0..32).map(|_| rng.gen_biguint_below(n)));
+
+        for a in &samples {
+            for b in samples.iter().take(8) {
+                let sa = Scalar::from_biguint(a);
+                let sb = Scalar::from_biguint(b);
+                assert_eq!((sa + sb).to_biguint(), (a + b) % n);
+                assert_eq!((sa - sb).to_biguint(), (a + n - b) % n);
+                assert_eq!((sa * sb).to_biguint(), (a * b) % n);
+            }
+            let sa = Scalar::from_biguint(a);
+            assert_eq!(sa.negate().to_biguint(), (n - a) % n);
+            assert_eq!(bool::from(sa.is_high()), a > &(n >> 1));
+            if a != &BigUint::from(0_u32) {
+                let expected = a.mod_inverse(n).unwrap().to_biguint().unwrap();
+                assert_eq!(sa.invert().to_biguint(), expected);
+            }
+        }
+    }
+
+    #[test]
+    fn scalar_bytes_test() {
+        let n = &(*SECP256K1GENS_ORDER);
+        let mut bytes = [0_u8; 32];
+        bytes.copy_from_slice(&n.to_bytes_be());
+        assert!(Scalar::from_bytes(&bytes).is_none());
+        assert_eq!(Scalar::from_bytes_reduced(&bytes), Scalar::ZERO);
+        bytes[31] -= 1;
+        let max = Scalar::from_bytes(&bytes).unwrap();
+        assert_eq!(max.to_bytes(), bytes);
+        assert_eq!(max + Scalar::ONE, Scalar::ZERO);
+        assert_eq!(Scalar::from_bytes_reduced(&[0xff; 32]).to_biguint(), {
+            let all_ones = BigUint::from_bytes_be(&[0xff; 32]);
+            all_ones % n
+        });
+    }
+
+    #[test]
+    fn scalar_batch_invert_test() {
+        let n = &(*SECP256K1GENS_ORDER);
+        let mut rng = rand::thread_rng();
+        let mut samples: Vec<Scalar> = (0..8)
+            .map(|_| Scalar::from_biguint(&rng.gen_biguint_below(n)))
+            .collect();
+        samples.insert(3, Scalar::ZERO);
+        samples.push(Scalar::ONE);
+
+        let inverted = Scalar::batch_invert(&samples);
+        assert_eq!(inverted.len(), samples.len());
+        for (s, inv) in samples.iter().zip(&inverted) {
+            assert_eq!(*inv, s.invert());
+        }
+        assert!(Scalar::batch_invert(&[]).is_empty());
+    }
+}
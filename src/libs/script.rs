@@ -1,7 +1,501 @@
-pub struct BitcoinScript {}
+use std::{
+    fmt::Display,
+    io::{Read, Write},
+};
 
-impl BitcoinScript {
-    pub fn to_vector(&self) -> Vec<u8> {
-        vec![0]
+use super::{
+    codec::{read_array, read_vec, write_all, CodecError, Decodable, Encodable},
+    transaction::Varint,
+};
+
+macro_rules! opcodes {
+    ($($name:ident = $value:expr),+ $(,)?) => {
+        // 0x01..=0x4b are direct data pushes and never appear as an `Opcode`
+        #[allow(non_camel_case_types)]
+        #[non_exhaustive]
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+        pub enum Opcode {
+            $($name,)+
+            OP_UNKNOWN(u8),
+        }
+
+        impl Opcode {
+            pub fn from_u8(byte: u8) -> Opcode {
+                match byte {
+                    $($value => Opcode::$name,)+
+                    other => Opcode::OP_UNKNOWN(other),
+                }
+            }
+
+            pub fn to_u8(self) -> u8 {
+                match self {
+                    $(Opcode::$name => $value,)+
+                    Opcode::OP_UNKNOWN(other) => other,
+                }
+            }
+        }
+
+        impl Display for Opcode {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                match self {
+                    $(Opcode::$name => f.write_str(stringify!($name)),)+
+                    Opcode::OP_UNKNOWN(other) => write!(f, "OP_UNKNOWN<{:#04x}>", other),
+                }
+            }
+        }
+    };
+}
+
+opcodes! {
+    // constants
+    OP_0 = 0x00,
+    OP_PUSHDATA1 = 0x4c,
+    OP_PUSHDATA2 = 0x4d,
+    OP_PUSHDATA4 = 0x4e,
+    OP_1NEGATE = 0x4f,
+    OP_RESERVED = 0x50,
+    OP_1 = 0x51,
+    OP_2 = 0x52,
+    OP_3 = 0x53,
+    OP_4 = 0x54,
+    OP_5 = 0x55,
+    OP_6 = 0x56,
+    OP_7 = 0x57,
+    OP_8 = 0x58,
+    OP_9 = 0x59,
+    OP_10 = 0x5a,
+    OP_11 = 0x5b,
+    OP_12 = 0x5c,
+    OP_13 = 0x5d,
+    OP_14 = 0x5e,
+    OP_15 = 0x5f,
+    OP_16 = 0x60,
+    // flow control
+    OP_NOP = 0x61,
+    OP_VER = 0x62,
+    OP_IF = 0x63,
+    OP_NOTIF = 0x64,
+    OP_VERIF = 0x65,
+    OP_VERNOTIF = 0x66,
+    OP_ELSE = 0x67,
+    OP_ENDIF = 0x68,
+    OP_VERIFY = 0x69,
+    OP_RETURN = 0x6a,
+    // stack
+    OP_TOALTSTACK = 0x6b,
+    OP_FROMALTSTACK = 0x6c,
+    OP_2DROP = 0x6d,
+    OP_2DUP = 0x6e,
+    OP_3DUP = 0x6f,
+    OP_2OVER = 0x70,
+    OP_2ROT = 0x71,
+    OP_2SWAP = 0x72,
+    OP_IFDUP = 0x73,
+    OP_DEPTH = 0x74,
+    OP_DROP = 0x75,
+    OP_DUP = 0x76,
+    OP_NIP = 0x77,
+    OP_OVER = 0x78,
+    OP_PICK = 0x79,
+    OP_ROLL = 0x7a,
+    OP_ROT = 0x7b,
+    OP_SWAP = 0x7c,
+    OP_TUCK = 0x7d,
+    // splice
+    OP_CAT = 0x7e,
+    OP_SUBSTR = 0x7f,
+    OP_LEFT = 0x80,
+    OP_RIGHT = 0x81,
+    OP_SIZE = 0x82,
+    // bitwise logic
+    OP_INVERT = 0x83,
+    OP_AND = 0x84,
+    OP_OR = 0x85,
+    OP_XOR = 0x86,
+    OP_EQUAL = 0x87,
+    OP_EQUALVERIFY = 0x88,
+    OP_RESERVED1 = 0x89,
+    OP_RESERVED2 = 0x8a,
+    // arithmetic
+    OP_1ADD = 0x8b,
+    OP_1SUB = 0x8c,
+    OP_2MUL = 0x8d,
+    OP_2DIV = 0x8e,
+    OP_NEGATE = 0x8f,
+    OP_ABS = 0x90,
+    OP_NOT = 0x91,
+    OP_0NOTEQUAL = 0x92,
+    OP_ADD = 0x93,
+    OP_SUB = 0x94,
+    OP_MUL = 0x95,
+    OP_DIV = 0x96,
+    OP_MOD = 0x97,
+    OP_LSHIFT = 0x98,
+    OP_RSHIFT = 0x99,
+    OP_BOOLAND = 0x9a,
+    OP_BOOLOR = 0x9b,
+    OP_NUMEQUAL = 0x9c,
+    OP_NUMEQUALVERIFY = 0x9d,
+    OP_NUMNOTEQUAL = 0x9e,
+    OP_LESSTHAN = 0x9f,
+    OP_GREATERTHAN = 0xa0,
+    OP_LESSTHANOREQUAL = 0xa1,
+    OP_GREATERTHANOREQUAL = 0xa2,
+    OP_MIN = 0xa3,
+    OP_MAX = 0xa4,
+    OP_WITHIN = 0xa5,
+    // crypto
+    OP_RIPEMD160 = 0xa6,
+    OP_SHA1 = 0xa7,
+    OP_SHA256 = 0xa8,
+    OP_HASH160 = 0xa9,
+    OP_HASH256 = 0xaa,
+    OP_CODESEPARATOR = 0xab,
+    OP_CHECKSIG = 0xac,
+    OP_CHECKSIGVERIFY = 0xad,
+    OP_CHECKMULTISIG = 0xae,
+    OP_CHECKMULTISIGVERIFY = 0xaf,
+    // expansion
+    OP_NOP1 = 0xb0,
+    OP_CHECKLOCKTIMEVERIFY = 0xb1,
+    OP_CHECKSEQUENCEVERIFY = 0xb2,
+    OP_NOP4 = 0xb3,
+    OP_NOP5 = 0xb4,
+    OP_NOP6 = 0xb5,
+    OP_NOP7 = 0xb6,
+    OP_NOP8 = 0xb7,
+    OP_NOP9 = 0xb8,
+    OP_NOP10 = 0xb9,
+    OP_CHECKSIGADD = 0xba,
+    OP_INVALIDOPCODE = 0xff,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    Op(Opcode),
+    Data(Vec<u8>),
+}
+
+impl Display for Command {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Command::Op(op) => write!(f, "{}", op),
+            Command::Data(data) => f.write_str(&hex::encode(data)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Script {
+    commands: Vec<Command>,
+    // the encoding `commands` were parsed from; signature hashes commit to these exact
+    // bytes, non-minimal pushes included
+    raw: Vec<u8>,
+}
+
+impl Script {
+    // data pushes always use the smallest push opcode
+    pub fn new(commands: Vec<Command>) -> Self {
+        let raw = serialize_commands(&commands);
+        Script { commands, raw }
+    }
+
+    pub fn commands(&self) -> &[Command] {
+        &self.commands
+    }
+
+    // OP_DUP OP_HASH160 <20 bytes> OP_EQUALVERIFY OP_CHECKSIG
+    pub fn p2pkh(hash: &[u8]) -> Self {
+        Script::new(vec![
+            Command::Op(Opcode::OP_DUP),
+            Command::Op(Opcode::OP_HASH160),
+            Command::Data(hash.to_vec()),
+            Command::Op(Opcode::OP_EQUALVERIFY),
+            Command::Op(Opcode::OP_CHECKSIG),
+        ])
+    }
+
+    pub fn p2pkh_hash(&self) -> Option<&[u8]> {
+        match self.commands.as_slice() {
+            [Command::Op(Opcode::OP_DUP), Command::Op(Opcode::OP_HASH160), Command::Data(hash), Command::Op(Opcode::OP_EQUALVERIFY), Command::Op(Opcode::OP_CHECKSIG)]
+                if hash.len() == 20 =>
+            {
+                Some(hash)
+            }
+            _ => None,
+        }
+    }
+
+    // OP_HASH160 <20 bytes> OP_EQUAL
+    pub fn p2sh(hash: &[u8]) -> Self {
+        Script::new(vec![
+            Command::Op(Opcode::OP_HASH160),
+            Command::Data(hash.to_vec()),
+            Command::Op(Opcode::OP_EQUAL),
+        ])
+    }
+
+    // BIP16 matches the exact bytes a9 14 <20 bytes> 87, the same hash behind a longer push
+    // is an ordinary script
+    pub fn p2sh_hash(&self) -> Option<&[u8]> {
+        match self.raw.as_slice() {
+            [0xa9, 0x14, hash @ .., 0x87] if hash.len() == 20 => Some(hash),
+            _ => None,
+        }
+    }
+
+    pub fn is_p2sh(&self) -> bool {
+        self.p2sh_hash().is_some()
+    }
+
+    // OP_0..OP_16 <2 to 40 bytes>, BIP141; None for any other version or program length
+    pub fn witness_program(version: u8, program: &[u8]) -> Option<Self> {
+        if version > 16 || !(2..=40).contains(&program.len()) {
+            return None;
+        }
+        let version = match version {
+            0 => Opcode::OP_0,
+            v => Opcode::from_u8(Opcode::OP_1.to_u8() + v - 1),
+        };
+        Some(Script::new(vec![
+            Command::Op(version),
+            Command::Data(program.to_vec()),
+        ]))
+    }
+
+    // witness version and program if this is a segwit scriptPubKey; BIP141 requires the
+    // program to be a direct push, OP_PUSHDATA1/2/4 make it an ordinary script
+    pub fn witness_version_and_program(&self) -> Option<(u8, &[u8])> {
+        let raw = self.raw.as_slice();
+        if !(4..=42).contains(&raw.len()) || raw[1] as usize + 2 != raw.len() {
+            return None;
+        }
+        let version = match raw[0] {
+            0 => 0,
+            v if (Opcode::OP_1.to_u8()..=Opcode::OP_16.to_u8()).contains(&v) => {
+                v - Opcode::OP_1.to_u8() + 1
+            }
+            _ => return None,
+        };
+        Some((version, &raw[2..]))
+    }
+
+    // OP_1NEGATE..OP_16 count as pushes, like in Bitcoin Core
+    pub fn is_push_only(&self) -> bool {
+        self.commands.iter().all(|command| match command {
+            Command::Data(_) => true,
+            Command::Op(op) => op.to_u8() <= Opcode::OP_16.to_u8(),
+        })
+    }
+
+    // script body without the varint length prefix
+    pub fn parse_raw(bytes: &[u8]) -> Result<Script, CodecError> {
+        let mut raw = bytes;
+        let mut commands = Vec::new();
+        while let Ok([byte]) = read_array::<1, _>(&mut raw) {
+            let len = match byte {
+                0x01..=0x4b => byte as u64,
+                0x4c => u8::from_le_bytes(read_array(&mut raw)?) as u64,
+                0x4d => u16::from_le_bytes(read_array(&mut raw)?) as u64,
+                0x4e => u32::from_le_bytes(read_array(&mut raw)?) as u64,
+                op => {
+                    commands.push(Command::Op(Opcode::from_u8(op)));
+                    continue;
+                }
+            };
+            commands.push(Command::Data(read_vec(&mut raw, len)?));
+        }
+        Ok(Script {
+            commands,
+            raw: bytes.to_vec(),
+        })
+    }
+
+    pub fn raw_serialize(&self) -> Vec<u8> {
+        self.raw.clone()
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.raw
+    }
+}
+
+// Opcode byte and end offset of the operation starting at `pc`, None at the end of
+// `raw` or when a push runs past it
+pub fn next_op(raw: &[u8], pc: usize) -> Option<(u8, usize)> {
+    let op = *raw.get(pc)?;
+    let (prefix, len) = match op {
+        0x01..=0x4b => (1, op as usize),
+        0x4c => (2, *raw.get(pc + 1)? as usize),
+        0x4d => (
+            3,
+            u16::from_le_bytes(raw.get(pc + 1..pc + 3)?.try_into().ok()?) as usize,
+        ),
+        0x4e => (
+            5,
+            u32::from_le_bytes(raw.get(pc + 1..pc + 5)?.try_into().ok()?) as usize,
+        ),
+        _ => (1, 0),
+    };
+    let end = (pc + prefix).checked_add(len)?;
+    (end <= raw.len()).then_some((op, end))
+}
+
+fn serialize_commands(commands: &[Command]) -> Vec<u8> {
+    let mut res = Vec::new();
+    for command in commands {
+        match command {
+            Command::Op(op) => res.push(op.to_u8()),
+            Command::Data(data) => {
+                let len = data.len();
+                match len {
+                    0 => res.push(Opcode::OP_0.to_u8()),
+                    l if l <= 0x4b => res.push(l as u8),
+                    l if l <= 0xff => {
+                        res.push(Opcode::OP_PUSHDATA1.to_u8());
+                        res.push(l as u8);
+                    }
+                    l if l <= 0xffff => {
+                        res.push(Opcode::OP_PUSHDATA2.to_u8());
+                        res.extend((l as u16).to_le_bytes());
+                    }
+                    l => {
+                        res.push(Opcode::OP_PUSHDATA4.to_u8());
+                        res.extend((l as u32).to_le_bytes());
+                    }
+                }
+                res.extend(data);
+            }
+        }
+    }
+    res
+}
+
+impl Display for Script {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let asm: Vec<String> = self.commands.iter().map(|c| c.to_string()).collect();
+        f.write_str(&asm.join(" "))
+    }
+}
+
+impl Encodable for Script {
+    fn encode<W: Write>(&self, writer: &mut W) -> Result<usize, CodecError> {
+        let raw = self.raw_serialize();
+        let len = Varint::new(raw.len() as u64).encode(writer)?;
+        Ok(len + write_all(writer, &raw)?)
+    }
+}
+
+impl Decodable for Script {
+    fn decode<R: Read>(reader: &mut R) -> Result<Self, CodecError> {
+        let len = Varint::decode(reader)?.value();
+        Script::parse_raw(&read_vec(reader, len)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::libs::codec::{CodecError, Decodable, Encodable};
+
+    use super::{Command, Opcode, Script};
+
+    #[test]
+    fn opcode_roundtrip() {
+        for byte in 0..=0xff_u8 {
+            assert_eq!(Opcode::from_u8(byte).to_u8(), byte);
+        }
+        assert_eq!(Opcode::from_u8(0xac), Opcode::OP_CHECKSIG);
+        assert_eq!(Opcode::OP_CHECKSIGADD.to_string(), "OP_CHECKSIGADD");
+        assert_eq!(Opcode::from_u8(0xbb).to_string(), "OP_UNKNOWN<0xbb>");
+    }
+
+    #[test]
+    fn p2pkh_script() {
+        let raw = hex::decode("1976a914ab0c0b2e98b1ab6dbf67d4750b0a56244948a87988ac").unwrap();
+        let script = Script::decode(&mut raw.as_slice()).unwrap();
+        assert_eq!(script.commands().len(), 5);
+        assert_eq!(script.commands()[0], Command::Op(Opcode::OP_DUP));
+        assert_eq!(
+            script.to_string(),
+            "OP_DUP OP_HASH160 ab0c0b2e98b1ab6dbf67d4750b0a56244948a879 OP_EQUALVERIFY OP_CHECKSIG"
+        );
+        assert_eq!(script.serialize(), raw);
+    }
+
+    #[test]
+    fn pushdata_script() {
+        for len in [0_usize, 1, 75, 76, 255, 256, 520, 70000] {
+            let script = Script::new(vec![
+                Command::Data(vec![0xab; len]),
+                Command::Op(Opcode::OP_DROP),
+            ]);
+            let parsed = Script::parse_raw(&script.raw_serialize()).unwrap();
+            if len == 0 {
+                assert_eq!(parsed.commands()[0], Command::Op(Opcode::OP_0));
+            } else {
+                assert_eq!(parsed, script);
+            }
+        }
+
+        let raw = hex::decode("4c03aabbcc4d0100dd").unwrap();
+        let script = Script::parse_raw(&raw).unwrap();
+        assert_eq!(
+            script.commands(),
+            &[
+                Command::Data(vec![0xaa, 0xbb, 0xcc]),
+                Command::Data(vec![0xdd])
+            ]
+        );
+        // non-minimal pushes are kept as encoded
+        assert_eq!(script.raw_serialize(), raw);
+        assert_ne!(Script::new(script.commands().to_vec()), script);
+    }
+
+    #[test]
+    fn truncated_script() {
+        assert!(matches!(
+            Script::parse_raw(&[0x05, 0x01, 0x02]),
+            Err(CodecError::LengthMismatch {
+                expected: 5,
+                actual: 2
+            })
+        ));
+        assert!(matches!(
+            Script::parse_raw(&[0x4d, 0x01]),
+            Err(CodecError::Io(_))
+        ));
+    }
+
+    #[test]
+    fn witness_program_script() {
+        for (version, len, raw_prefix) in
+            [(0_u8, 20_usize, "0014"), (0, 32, "0020"), (1, 32, "5120")]
+        {
+            let script = Script::witness_program(version, &vec![0x42; len]).unwrap();
+            let raw = script.raw_serialize();
+            assert!(hex::encode(&raw).starts_with(raw_prefix));
+            let parsed = Script::parse_raw(&raw).unwrap();
+            assert_eq!(
+                parsed.witness_version_and_program(),
+                Some((version, vec![0x42; len].as_slice()))
+            );
+        }
+        assert_eq!(Script::witness_program(17, &[0x42; 32]), None);
+        assert_eq!(Script::witness_program(0, &[0x42; 41]), None);
+        assert_eq!(Script::witness_program(1, &[0x42]), None);
+
+        let p2sh = Script::p2sh(&[0x42; 20]);
+        assert!(p2sh.is_p2sh());
+        let pushdata1 = [&[0xa9, 0x4c, 0x14][..], &[0x42; 20], &[0x87]].concat();
+        assert!(!Script::parse_raw(&pushdata1).unwrap().is_p2sh());
+        let pushdata1 = [&[0x00, 0x4c, 0x14][..], &[0x42; 20]].concat();
+        assert_eq!(
+            Script::parse_raw(&pushdata1)
+                .unwrap()
+                .witness_version_and_program(),
+            None
+        );
+        assert_eq!(p2sh.witness_version_and_program(), None);
+        assert_eq!(Script::p2pkh(&[0x42; 20]).p2sh_hash(), None);
     }
 }
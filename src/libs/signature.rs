@@ -1,11 +1,16 @@
-use std::{string::FromUtf8Error, vec};
+use std::{fmt::Display, string::FromUtf8Error, vec};
 
+use anyhow::bail;
 use num_bigint_dig::{BigInt, BigUint, Sign};
-use num_traits::{FromPrimitive, ToPrimitive};
-use sha2::{Digest, Sha256};
+use num_traits::{FromPrimitive, ToPrimitive, Zero};
 
 use crate::unwrap_or_none;
 
+use super::{
+    ecc::{hash256, SECP256K1GENS_ORDER},
+    math::biguint_to_32_bytes,
+};
+
 const BASE58_ALPHABET: &[u8; 58] = b"123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
 
 #[non_exhaustive]
@@ -27,7 +32,6 @@ impl Signature {
             r.splice(0..0, [0x00_u8]);
         }
         r.splice(0..0, [0x02_u8, r.len().to_u8().unwrap()]);
-        eprintln!("{:?}", r);
 
         let mut s = self.s.to_bytes_be();
         if &s[0] & 0x80 > 0 {
@@ -36,19 +40,183 @@ impl Signature {
         s.splice(0..0, [0x02_u8, s.len().to_u8().unwrap()]);
 
         r.extend(&s);
-        eprintln!("{:?}", r);
         let tot_len = r.len().to_u8().unwrap();
         r.splice(0..0, [tot_len]);
         r.splice(0..0, der_prefix);
         r
     }
+
+    // Strict DER as enforced by BIP66, without the trailing sighash byte:
+    // 0x30 [total-len] 0x02 [R-len] [R] 0x02 [S-len] [S]
+    pub fn parse_der(der: &[u8]) -> anyhow::Result<Signature> {
+        let len = der.len();
+        if !(8..=72).contains(&len) {
+            bail!("[Signature::parse_der] invalid length {}", len);
+        }
+        if der[0] != 0x30 {
+            bail!("[Signature::parse_der] missing compound marker 0x30");
+        }
+        if der[1] as usize != len - 2 {
+            bail!(
+                "[Signature::parse_der] length byte {} doesn't match {} remaining bytes",
+                der[1],
+                len - 2
+            );
+        }
+
+        let r_len = der[3] as usize;
+        if 5 + r_len >= len {
+            bail!(
+                "[Signature::parse_der] R length {} overruns signature",
+                r_len
+            );
+        }
+        let s_len = der[5 + r_len] as usize;
+        if r_len + s_len + 6 != len {
+            bail!("[Signature::parse_der] R and S lengths don't add up to signature length");
+        }
+
+        let r = Self::parse_der_integer(&der[2..4 + r_len], "R")?;
+        let s = Self::parse_der_integer(&der[4 + r_len..], "S")?;
+        for (name, value) in [("R", &r), ("S", &s)] {
+            if value.is_zero() || value >= &(*SECP256K1GENS_ORDER) {
+                bail!("[Signature::parse_der] {} is out of range", name);
+            }
+        }
+        Ok(Signature::new(r, s))
+    }
+
+    // 0x02 [len] [big-endian two's complement integer], which must be positive and minimal
+    fn parse_der_integer(bytes: &[u8], name: &str) -> anyhow::Result<BigUint> {
+        if bytes[0] != 0x02 {
+            bail!("[Signature::parse_der] {} is not an integer", name);
+        }
+        let value = &bytes[2..];
+        if value.is_empty() {
+            bail!("[Signature::parse_der] {} is empty", name);
+        }
+        if value[0] & 0x80 != 0 {
+            bail!("[Signature::parse_der] {} is negative", name);
+        }
+        if value.len() > 1 && value[0] == 0x00 && value[1] & 0x80 == 0 {
+            bail!("[Signature::parse_der] {} has excess zero padding", name);
+        }
+        Ok(BigUint::from_bytes_be(value))
+    }
+
+    // BIP62: S must be at most n/2 so that (r, n - s) can't be used to malleate a transaction
+    pub fn is_low_s(&self) -> bool {
+        self.s <= &(*SECP256K1GENS_ORDER) >> 1
+    }
+
+    pub fn normalize_s(&self) -> Signature {
+        if self.is_low_s() {
+            self.clone()
+        } else {
+            Signature::new(self.r.clone(), &(*SECP256K1GENS_ORDER) - &self.s)
+        }
+    }
+}
+
+// Signature plus the recovery id needed to get the public key back from it:
+// bit 0 is the parity of R.y, bit 1 is set when R.x overflowed the order (R.x == r + n)
+#[non_exhaustive]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecoverableSignature {
+    pub signature: Signature,
+    pub recovery_id: u8,
+}
+
+impl RecoverableSignature {
+    pub fn new(signature: Signature, recovery_id: u8) -> anyhow::Result<Self> {
+        if recovery_id > 3 {
+            bail!(
+                "[RecoverableSignature::new] recovery id {} is not in 0..=3",
+                recovery_id
+            );
+        }
+        Ok(RecoverableSignature {
+            signature,
+            recovery_id,
+        })
+    }
+
+    // Bitcoin Core's 65-byte compact form: a header byte of 27 + recovery id
+    // (+ 4 when the key is compressed), then r and s as 32 bytes each
+    pub fn to_compact(&self, compressed: bool) -> [u8; 65] {
+        let mut res = [0_u8; 65];
+        res[0] = 27 + self.recovery_id + if compressed { 4 } else { 0 };
+        res[1..33].copy_from_slice(&biguint_to_32_bytes(&self.signature.r));
+        res[33..].copy_from_slice(&biguint_to_32_bytes(&self.signature.s));
+        res
+    }
+
+    // The inverse of `to_compact`; the flag tells whether the key was compressed
+    pub fn from_compact(bytes: &[u8]) -> anyhow::Result<(RecoverableSignature, bool)> {
+        if bytes.len() != 65 {
+            bail!(
+                "[RecoverableSignature::from_compact] invalid length {}",
+                bytes.len()
+            );
+        }
+        if !(27..=34).contains(&bytes[0]) {
+            bail!(
+                "[RecoverableSignature::from_compact] invalid header byte {}",
+                bytes[0]
+            );
+        }
+        let header = bytes[0] - 27;
+        let signature = Signature::new(
+            BigUint::from_bytes_be(&bytes[1..33]),
+            BigUint::from_bytes_be(&bytes[33..]),
+        );
+        Ok((
+            RecoverableSignature::new(signature, header & 3)?,
+            header & 4 != 0,
+        ))
+    }
+}
+
+// BIP340 signature: x(R) and s, 64 bytes on the wire
+#[non_exhaustive]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SchnorrSignature {
+    pub r: [u8; 32],
+    pub s: [u8; 32],
+}
+
+impl SchnorrSignature {
+    pub fn new(r: [u8; 32], s: [u8; 32]) -> Self {
+        SchnorrSignature { r, s }
+    }
+
+    pub fn to_bytes(&self) -> [u8; 64] {
+        let mut res = [0_u8; 64];
+        res[..32].copy_from_slice(&self.r);
+        res[32..].copy_from_slice(&self.s);
+        res
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> anyhow::Result<SchnorrSignature> {
+        if bytes.len() != 64 {
+            bail!(
+                "[SchnorrSignature::from_bytes] invalid length {}",
+                bytes.len()
+            );
+        }
+        let mut r = [0_u8; 32];
+        let mut s = [0_u8; 32];
+        r.copy_from_slice(&bytes[..32]);
+        s.copy_from_slice(&bytes[32..]);
+        Ok(SchnorrSignature { r, s })
+    }
 }
 
 pub fn trim_null_start(from: Vec<u8>) -> Option<Vec<u8>> {
     let len = from.len();
     let mut j: i64 = -1;
-    for i in 0..len {
-        if from[i] != 0x00 {
+    for (i, byte) in from.iter().enumerate() {
+        if *byte != 0x00 {
             j = unwrap_or_none!(i.to_i64());
             break;
         }
@@ -72,55 +240,111 @@ pub fn base58_encode(s: &[u8]) -> Result<String, FromUtf8Error> {
         }
     }
 
-    let mut number = BigInt::from_bytes_be(Sign::Plus, s.as_ref());
+    let mut number = BigInt::from_bytes_be(Sign::Plus, s);
     let mut result: Vec<u8> = Vec::new();
-    while &number > &BigInt::from_i32(0).unwrap() {
+    while number > BigInt::zero() {
         let divrem = num_integer::div_rem(number, BigInt::from_i32(58).unwrap());
         number = divrem.0;
         let idx = BASE58_ALPHABET[divrem.1.to_usize().unwrap()];
         result.push(idx);
     }
 
-    for _ in 0..null_count {
-        result.push(b'1');
-    }
+    result.extend(vec![b'1'; null_count]);
     result.reverse();
     String::from_utf8(result)
 }
 
-// TODO should shasum256 twice
-// https://en.bitcoin.it/wiki/Wallet_import_format
+// https://en.bitcoin.it/wiki/Base58Check_encoding
 pub fn base58_encode_with_checksum(s: &[u8]) -> Result<String, FromUtf8Error> {
-    eprintln!("[base58_checksum] given: {:?}", hex::encode(s));
-    let _checksum: [u8; 32] = Sha256::digest(s)
-        .as_slice()
-        .try_into()
-        .expect("wrong slice length");
-    eprintln!(
-        "[base58_checksum] checksum res: {:?}",
-        hex::encode(_checksum.to_vec())
-    );
-    let checksum: &[u8; 4] = &_checksum[0..4]
-        .try_into()
-        .expect("checksum must be 4 bytes");
-    let mut result: Vec<u8> = Vec::new();
+    let mut result: Vec<u8> = Vec::with_capacity(s.len() + 4);
     result.extend(s);
-    result.extend(checksum);
+    result.extend(&hash256(s)[..4]);
     base58_encode(&result)
 }
 
+#[non_exhaustive]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Base58Error {
+    InvalidCharacter { character: char, index: usize },
+    TooShort { length: usize },
+    ChecksumMismatch { expected: [u8; 4], actual: [u8; 4] },
+}
+
+impl Display for Base58Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Base58Error::InvalidCharacter { character, index } => write!(
+                f,
+                "[base58] invalid character {:?} at index {}",
+                character, index
+            ),
+            Base58Error::TooShort { length } => write!(
+                f,
+                "[base58] {} bytes are too short to contain a checksum",
+                length
+            ),
+            Base58Error::ChecksumMismatch { expected, actual } => write!(
+                f,
+                "[base58] checksum mismatch (expected: {}, actual: {})",
+                hex::encode(expected),
+                hex::encode(actual)
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Base58Error {}
+
+// each leading '1' decodes to a leading zero byte
+pub fn base58_decode(s: &str) -> Result<Vec<u8>, Base58Error> {
+    let null_count = s.bytes().take_while(|&c| c == b'1').count();
+    let mut number = BigUint::zero();
+    for (index, character) in s.char_indices() {
+        let digit = BASE58_ALPHABET
+            .iter()
+            .position(|&a| char::from(a) == character)
+            .ok_or(Base58Error::InvalidCharacter { character, index })?;
+        number = number * 58_u32 + digit;
+    }
+
+    let mut result = vec![0_u8; null_count];
+    if !number.is_zero() {
+        result.extend(number.to_bytes_be());
+    }
+    Ok(result)
+}
+
+// Inverse of `base58_encode_with_checksum`, returns the payload without the checksum
+pub fn base58_decode_with_checksum(s: &str) -> Result<Vec<u8>, Base58Error> {
+    let mut raw = base58_decode(s)?;
+    if raw.len() < 4 {
+        return Err(Base58Error::TooShort { length: raw.len() });
+    }
+    let checksum = raw.split_off(raw.len() - 4);
+    let mut expected = [0_u8; 4];
+    expected.copy_from_slice(&hash256(&raw)[..4]);
+    let mut actual = [0_u8; 4];
+    actual.copy_from_slice(&checksum);
+    if expected != actual {
+        return Err(Base58Error::ChecksumMismatch { expected, actual });
+    }
+    Ok(raw)
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::libs::signature::base58_encode_with_checksum;
-
-    use super::base58_encode;
     use super::Signature;
+    use super::{
+        base58_decode, base58_decode_with_checksum, base58_encode, base58_encode_with_checksum,
+        Base58Error,
+    };
     use digest::Digest;
     use num_bigint_dig::BigUint;
     use num_traits::{Num, ToPrimitive};
     use sha2::Sha256;
 
     #[test]
+    #[allow(clippy::needless_range_loop)]
     fn trim_test() {
         let test_vec: Vec<u8> = vec![0x00, 0x00, 0x32, 0x01, 0xA1];
         let len = test_vec.len();
@@ -140,6 +364,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::useless_format, clippy::needless_borrows_for_generic_args)]
     fn der_test() {
         let sig = Signature::new(
             BigUint::from_str_radix(
@@ -162,6 +387,58 @@ mod tests {
     }
 
     #[test]
+    fn parse_der_test() {
+        let der = hex::decode(
+            "3045022037206a0610995c58074999cb9767b87af4c4978db68c06e8e6e81d282047a7c6022100\
+            8ca63759c1157ebeaec0d03cecca119fc9a75bf8e6d0fa65c841c8e2738cdaec",
+        )
+        .unwrap();
+        let sig = Signature::parse_der(&der).unwrap();
+        assert_eq!(sig.der(), der);
+        assert!(!sig.is_low_s());
+        assert!(sig.normalize_s().is_low_s());
+        assert_eq!(
+            Signature::parse_der(&sig.normalize_s().der()).unwrap(),
+            sig.normalize_s()
+        );
+    }
+
+    #[test]
+    fn parse_der_bip66_test() {
+        let valid =
+            "3045022037206a0610995c58074999cb9767b87af4c4978db68c06e8e6e81d282047a7c6022100\
+            8ca63759c1157ebeaec0d03cecca119fc9a75bf8e6d0fa65c841c8e2738cdaec";
+        let invalid = [
+            // wrong compound marker
+            valid.replacen("30", "31", 1),
+            // total length off by one
+            valid.replacen("3045", "3046", 1),
+            // R length overruns
+            valid.replacen("30450220", "30450250", 1),
+            // S with excess zero padding
+            valid
+                .replacen("3045", "3046", 1)
+                .replacen("022100", "02220000", 1),
+            // negative R
+            valid.replacen("022037", "0220b7", 1),
+            // R not marked as integer
+            valid.replacen("30450220", "30450320", 1),
+            // zero-length R
+            "3006020002020101".to_string(),
+            // R is zero
+            "3006020100020101".to_string(),
+            // trailing garbage
+            format!("{}00", valid),
+        ];
+        for hex in invalid {
+            let der = hex::decode(&hex).unwrap();
+            assert!(Signature::parse_der(&der).is_err(), "{}", hex);
+        }
+        assert!(Signature::parse_der(&[]).is_err());
+    }
+
+    #[test]
+    #[allow(clippy::needless_borrows_for_generic_args)]
     fn base58_test() {
         let a = b"7c076ff316692a3d7eb3c3bb0f8b1488cf72e1afcd929e29307032997a838a3d";
         let b = b"eff69ef2b1bd93a66ed5219add4fb51e11a840f404876325a1e8ffe0529a2c";
@@ -197,9 +474,61 @@ mod tests {
 
     #[test]
     fn base58_checksum_test() {
-        let a = b"Test data";
-        eprintln!("{:?}", base58_encode(a).unwrap());
-        eprintln!("{:?}", base58_encode_with_checksum(a).unwrap());
-        panic!("s");
+        // version byte 0x00 + hash160 of the uncompressed generator point (secret 1)
+        let payload = hex::decode("0091b24bf9f5288532960ac687abb035127b1d28a5").unwrap();
+        let encoded = base58_encode_with_checksum(&payload).unwrap();
+        assert_eq!(encoded, "1EHNa6Q4Jz2uvNExL497mE43ikXhwF6kZm");
+        assert_eq!(base58_decode_with_checksum(&encoded).unwrap(), payload);
+
+        let mut tampered = encoded.into_bytes();
+        tampered[5] = b'z';
+        let tampered = String::from_utf8(tampered).unwrap();
+        assert!(base58_decode_with_checksum(&tampered).is_err());
+    }
+
+    #[test]
+    fn base58_decode_test() {
+        let hw = b"Hello World!";
+        assert_eq!(base58_decode("2NEpo7TZRRrLZSi2U").unwrap(), hw.to_vec());
+        assert_eq!(
+            base58_decode("9MA8fRQrT4u8Zj8ZRd6MAiiyaxb2Y1CMpvVkHQu5hVM6").unwrap(),
+            hex::decode("7c076ff316692a3d7eb3c3bb0f8b1488cf72e1afcd929e29307032997a838a3d")
+                .unwrap()
+        );
+
+        // leading zero bytes survive the roundtrip
+        let zeros = vec![0x00, 0x00, 0x00, 0x01, 0x02];
+        let encoded = base58_encode(&zeros).unwrap();
+        assert!(encoded.starts_with("111"));
+        assert_eq!(base58_decode(&encoded).unwrap(), zeros);
+        assert_eq!(base58_decode("111").unwrap(), vec![0, 0, 0]);
+        assert_eq!(base58_decode("").unwrap(), Vec::<u8>::new());
+    }
+
+    #[test]
+    fn base58_decode_error_test() {
+        // '0', 'O', 'I' and 'l' are not in the alphabet
+        for (s, character, index) in [("0abc", '0', 0), ("abO", 'O', 2), ("1Il", 'I', 1)] {
+            assert_eq!(
+                base58_decode(s),
+                Err(Base58Error::InvalidCharacter { character, index })
+            );
+        }
+        assert_eq!(
+            base58_decode("1€"),
+            Err(Base58Error::InvalidCharacter {
+                character: '€',
+                index: 1
+            })
+        );
+
+        assert_eq!(
+            base58_decode_with_checksum("2g"),
+            Err(Base58Error::TooShort { length: 1 })
+        );
+        assert!(matches!(
+            base58_decode_with_checksum("1EHNa6Q4Jz2uvNExL497mE43ikXhwF6kZn"),
+            Err(Base58Error::ChecksumMismatch { .. })
+        ));
     }
 }
@@ -1,11 +1,44 @@
 use std::{
+    collections::HashMap,
     fmt::Display,
-    io::{BufReader, Read},
+    io::{Read, Write},
 };
 
-use super::script::BitcoinScript;
+use anyhow::{anyhow, bail};
+use digest::Digest;
+use num_bigint_dig::BigUint;
+use num_traits::One;
+use rand::Rng;
+use sha2::Sha256;
 
-#[derive(Debug)]
+use super::{
+    codec::{read_array, read_vec, write_all, CodecError, Decodable, Encodable},
+    ecc::{hash160, hash256, tagged_hash},
+    interpreter::{verify_script_with_witness, TransactionSignatureChecker},
+    key::Key,
+    script::{next_op, Command, Opcode, Script},
+};
+
+// BIP341: taproot signatures without a hash type byte sign like SIGHASH_ALL
+pub const SIGHASH_DEFAULT: u32 = 0x00;
+pub const SIGHASH_ALL: u32 = 0x01;
+pub const SIGHASH_NONE: u32 = 0x02;
+pub const SIGHASH_SINGLE: u32 = 0x03;
+pub const SIGHASH_ANYONECANPAY: u32 = 0x80;
+
+// Source of the outputs spent by a transaction's inputs
+pub trait UtxoLookup {
+    // `previous_tx_id` is in wire (little-endian) byte order, as stored in `TransactionInput`
+    fn lookup(&self, previous_tx_id: &[u8; 32], previous_tx_idx: u32) -> Option<TransactionOutput>;
+}
+
+impl UtxoLookup for HashMap<([u8; 32], u32), TransactionOutput> {
+    fn lookup(&self, previous_tx_id: &[u8; 32], previous_tx_idx: u32) -> Option<TransactionOutput> {
+        self.get(&(*previous_tx_id, previous_tx_idx)).cloned()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct TransactionVersion {
     version: [u8; 4],
 }
@@ -30,6 +63,18 @@ impl TransactionVersion {
     }
 }
 
+impl Encodable for TransactionVersion {
+    fn encode<W: Write>(&self, writer: &mut W) -> Result<usize, CodecError> {
+        write_all(writer, &self.version)
+    }
+}
+
+impl Decodable for TransactionVersion {
+    fn decode<R: Read>(reader: &mut R) -> Result<Self, CodecError> {
+        Ok(TransactionVersion::from_bytes(read_array(reader)?))
+    }
+}
+
 impl Display for TransactionVersion {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let hex = hex::encode(self.version);
@@ -37,14 +82,14 @@ impl Display for TransactionVersion {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum TransactionLocktimeType {
     Ignore,
     Block,
     UnixTime,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct TransactionLocktime {
     locktime: [u8; 4],
     locktime_type: TransactionLocktimeType,
@@ -53,8 +98,8 @@ pub struct TransactionLocktime {
 impl TransactionLocktime {
     pub fn new(locktime: u32) -> Self {
         let locktime_type = match locktime {
+            u32::MAX => TransactionLocktimeType::Ignore,
             time if time >= 500_000_000 => TransactionLocktimeType::UnixTime,
-            time if time == u32::MAX => TransactionLocktimeType::Ignore,
             _ => TransactionLocktimeType::Block,
         };
 
@@ -63,6 +108,30 @@ impl TransactionLocktime {
             locktime_type,
         }
     }
+
+    pub fn from_bytes(bytes: [u8; 4]) -> Self {
+        TransactionLocktime::new(u32::from_le_bytes(bytes))
+    }
+
+    pub fn to_u32(&self) -> u32 {
+        u32::from_le_bytes(self.locktime)
+    }
+
+    pub fn locktime_type(&self) -> &TransactionLocktimeType {
+        &self.locktime_type
+    }
+}
+
+impl Encodable for TransactionLocktime {
+    fn encode<W: Write>(&self, writer: &mut W) -> Result<usize, CodecError> {
+        write_all(writer, &self.locktime)
+    }
+}
+
+impl Decodable for TransactionLocktime {
+    fn decode<R: Read>(reader: &mut R) -> Result<Self, CodecError> {
+        Ok(TransactionLocktime::from_bytes(read_array(reader)?))
+    }
 }
 
 impl Display for TransactionLocktime {
@@ -71,39 +140,178 @@ impl Display for TransactionLocktime {
     }
 }
 
-// TODO script_signature -> independent struct
-// TODO verification of signature
-#[derive(Debug)]
+// scripts are kept as raw bytes so that transactions with non-minimal pushes
+// (or arbitrary coinbase data) still round-trip byte-for-byte
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct TransactionInput {
     previous_tx_id: [u8; 32],
     previous_tx_idx: [u8; 4],
     script_signature: Vec<u8>,
     sequence: [u8; 4],
+    // serialized after all outputs, see `Transaction`'s `Encodable`
+    witness: Witness,
 }
 
 impl TransactionInput {
     pub fn new(
         previous_tx_id: [u8; 32],
         previous_tx_idx: [u8; 4],
-        script_signature: BitcoinScript,
+        script_signature: Script,
         sequence: [u8; 4],
     ) -> Self {
         TransactionInput {
             previous_tx_id,
             previous_tx_idx,
-            script_signature: script_signature.to_vector(),
+            script_signature: script_signature.raw_serialize(),
+            sequence,
+            witness: Witness::default(),
+        }
+    }
+
+    pub fn script_signature(&self) -> Result<Script, CodecError> {
+        Script::parse_raw(&self.script_signature)
+    }
+
+    pub fn previous_tx_id(&self) -> &[u8; 32] {
+        &self.previous_tx_id
+    }
+
+    pub fn previous_tx_idx(&self) -> u32 {
+        u32::from_le_bytes(self.previous_tx_idx)
+    }
+
+    pub fn sequence(&self) -> u32 {
+        u32::from_le_bytes(self.sequence)
+    }
+
+    pub fn witness(&self) -> &Witness {
+        &self.witness
+    }
+
+    pub fn set_witness(&mut self, witness: Witness) {
+        self.witness = witness;
+    }
+
+    // previous_tx_id || previous_tx_idx, as committed to by the segwit signature hashes
+    fn outpoint(&self) -> Vec<u8> {
+        [&self.previous_tx_id[..], &self.previous_tx_idx].concat()
+    }
+}
+
+impl Encodable for TransactionInput {
+    fn encode<W: Write>(&self, writer: &mut W) -> Result<usize, CodecError> {
+        let mut len = write_all(writer, &self.previous_tx_id)?;
+        len += write_all(writer, &self.previous_tx_idx)?;
+        len += Varint::new(self.script_signature.len() as u64).encode(writer)?;
+        len += write_all(writer, &self.script_signature)?;
+        len += write_all(writer, &self.sequence)?;
+        Ok(len)
+    }
+}
+
+impl Decodable for TransactionInput {
+    fn decode<R: Read>(reader: &mut R) -> Result<Self, CodecError> {
+        let previous_tx_id = read_array(reader)?;
+        let previous_tx_idx = read_array(reader)?;
+        let script_len = Varint::decode(reader)?.value();
+        let script_signature = read_vec(reader, script_len)?;
+        let sequence = read_array(reader)?;
+        Ok(TransactionInput {
+            previous_tx_id,
+            previous_tx_idx,
+            script_signature,
             sequence,
+            witness: Witness::default(),
+        })
+    }
+}
+
+// BIP141 witness stack of one input: a varint item count, then varint-prefixed items
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Witness(Vec<Vec<u8>>);
+
+impl Witness {
+    pub fn new(items: Vec<Vec<u8>>) -> Self {
+        Witness(items)
+    }
+
+    pub fn items(&self) -> &[Vec<u8>] {
+        &self.0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl Encodable for Witness {
+    fn encode<W: Write>(&self, writer: &mut W) -> Result<usize, CodecError> {
+        let mut len = Varint::new(self.0.len() as u64).encode(writer)?;
+        for item in &self.0 {
+            len += Varint::new(item.len() as u64).encode(writer)?;
+            len += write_all(writer, item)?;
+        }
+        Ok(len)
+    }
+}
+
+impl Decodable for Witness {
+    fn decode<R: Read>(reader: &mut R) -> Result<Self, CodecError> {
+        let count = Varint::decode(reader)?.value();
+        let mut items = Vec::new();
+        for _ in 0..count {
+            let len = Varint::decode(reader)?.value();
+            items.push(read_vec(reader, len)?);
         }
+        Ok(Witness(items))
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct TransactionOutput {
-    amount: Vec<u8>,
+    amount: [u8; 8],
     script_pubkey: Vec<u8>,
 }
 
-#[derive(Debug)]
+impl TransactionOutput {
+    pub fn new(amount: u64, script_pubkey: Script) -> Self {
+        TransactionOutput {
+            amount: amount.to_le_bytes(),
+            script_pubkey: script_pubkey.raw_serialize(),
+        }
+    }
+
+    pub fn script_pubkey(&self) -> Result<Script, CodecError> {
+        Script::parse_raw(&self.script_pubkey)
+    }
+
+    pub fn amount(&self) -> u64 {
+        u64::from_le_bytes(self.amount)
+    }
+}
+
+impl Encodable for TransactionOutput {
+    fn encode<W: Write>(&self, writer: &mut W) -> Result<usize, CodecError> {
+        let mut len = write_all(writer, &self.amount)?;
+        len += Varint::new(self.script_pubkey.len() as u64).encode(writer)?;
+        len += write_all(writer, &self.script_pubkey)?;
+        Ok(len)
+    }
+}
+
+impl Decodable for TransactionOutput {
+    fn decode<R: Read>(reader: &mut R) -> Result<Self, CodecError> {
+        let amount = read_array(reader)?;
+        let script_len = Varint::decode(reader)?.value();
+        let script_pubkey = read_vec(reader, script_len)?;
+        Ok(TransactionOutput {
+            amount,
+            script_pubkey,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Transaction {
     version: TransactionVersion,
     transaction_inputs_varint: Varint,
@@ -115,26 +323,509 @@ pub struct Transaction {
 
 // TODO parse_from_str
 impl Transaction {
-    pub fn parse(text: &[u8]) {
-        let mut stream = BufReader::new(text);
-        let mut n: i64 = -1;
+    pub fn new(
+        version: TransactionVersion,
+        transaction_inputs: Vec<TransactionInput>,
+        transaction_outputs: Vec<TransactionOutput>,
+        locktime: TransactionLocktime,
+    ) -> Self {
+        Transaction {
+            version,
+            transaction_inputs_varint: Varint::new(transaction_inputs.len() as u64),
+            transaction_inputs,
+            transaction_outputs_varint: Varint::new(transaction_outputs.len() as u64),
+            transaction_outputs,
+            locktime,
+        }
+    }
 
-        // version (4)
-        let array = Self::parse_nbytes::<4>(&mut stream);
+    pub fn parse(mut text: &[u8]) -> anyhow::Result<Transaction> {
+        Ok(Transaction::decode(&mut text)?)
     }
 
-    fn parse_nbytes<const N: usize>(stream: &mut BufReader<&[u8]>) -> [u8; N] {
-        let mut buffer = [0_u8; N];
-        let res = stream.read(&mut buffer).unwrap();
-        if res != N {
-            panic!("[parse_nbytes] panic while reading nbytes {}", res);
-        }
+    // hash256 of the serialization without witnesses, reversed to little-endian
+    // (block explorer order)
+    pub fn hash(&self) -> [u8; 32] {
+        let mut hash: [u8; 32] = hash256(&self.serialize_without_witness())
+            .try_into()
+            .expect("hash256 must be 32 bytes");
+        hash.reverse();
+        hash
+    }
+
+    pub fn txid(&self) -> String {
+        hex::encode(self.hash())
+    }
+
+    pub fn has_witness(&self) -> bool {
+        self.transaction_inputs
+            .iter()
+            .any(|input| !input.witness.is_empty())
+    }
+
+    // pre-segwit serialization, which txids and legacy signature hashes are computed over
+    pub fn serialize_without_witness(&self) -> Vec<u8> {
+        let mut buffer = Vec::new();
+        self.encode_with_witness(&mut buffer, false)
+            .expect("writing into Vec<u8> never fails");
         buffer
     }
 
-    pub fn parse_version(stream: &mut BufReader<&[u8]>) -> TransactionVersion {
-        let array = Self::parse_nbytes::<4>(stream);
-        TransactionVersion::from_bytes(array)
+    // BIP144: with any witness present, a 0x00 marker and 0x01 flag follow the version
+    // and every input's witness follows the outputs
+    fn encode_with_witness<W: Write>(
+        &self,
+        writer: &mut W,
+        include_witness: bool,
+    ) -> Result<usize, CodecError> {
+        let segwit = include_witness && self.has_witness();
+        let mut len = self.version.encode(writer)?;
+        if segwit {
+            len += write_all(writer, &[0x00, 0x01])?;
+        }
+        len += self.transaction_inputs_varint.encode(writer)?;
+        for input in &self.transaction_inputs {
+            len += input.encode(writer)?;
+        }
+        len += self.transaction_outputs_varint.encode(writer)?;
+        for output in &self.transaction_outputs {
+            len += output.encode(writer)?;
+        }
+        if segwit {
+            for input in &self.transaction_inputs {
+                len += input.witness.encode(writer)?;
+            }
+        }
+        len += self.locktime.encode(writer)?;
+        Ok(len)
+    }
+
+    // Legacy (pre-segwit) signature hash of input `input_index` spending `script_code`
+    pub fn sig_hash(&self, input_index: usize, script_code: &[u8], sighash_type: u32) -> BigUint {
+        // Bitcoin Core signs the number one instead of failing for out-of-range inputs,
+        // and for SIGHASH_SINGLE without a matching output
+        if input_index >= self.transaction_inputs.len() {
+            return BigUint::one();
+        }
+        let base_type = sighash_type & 0x1f;
+        if base_type == SIGHASH_SINGLE && input_index >= self.transaction_outputs.len() {
+            return BigUint::one();
+        }
+
+        // OP_CODESEPARATORs are cut out of the encoded script, every other byte is signed
+        // as is; a truncated push ends the walk and is kept verbatim
+        let mut stripped = Vec::with_capacity(script_code.len());
+        let mut pc = 0;
+        while let Some((op, end)) = next_op(script_code, pc) {
+            if op != Opcode::OP_CODESEPARATOR.to_u8() {
+                stripped.extend(&script_code[pc..end]);
+            }
+            pc = end;
+        }
+        stripped.extend(&script_code[pc..]);
+
+        let mut inputs: Vec<TransactionInput> = Vec::new();
+        for (idx, input) in self.transaction_inputs.iter().enumerate() {
+            let signing = idx == input_index;
+            if sighash_type & SIGHASH_ANYONECANPAY != 0 && !signing {
+                continue;
+            }
+            let mut input = input.clone();
+            input.script_signature = if signing {
+                stripped.clone()
+            } else {
+                Vec::new()
+            };
+            // other inputs may be updated freely when not all outputs are signed
+            if !signing && (base_type == SIGHASH_NONE || base_type == SIGHASH_SINGLE) {
+                input.sequence = [0; 4];
+            }
+            inputs.push(input);
+        }
+
+        let outputs = match base_type {
+            SIGHASH_NONE => Vec::new(),
+            SIGHASH_SINGLE => {
+                let mut outputs: Vec<TransactionOutput> = (0..input_index)
+                    .map(|_| TransactionOutput::new(u64::MAX, Script::default()))
+                    .collect();
+                outputs.push(self.transaction_outputs[input_index].clone());
+                outputs
+            }
+            _ => self.transaction_outputs.clone(),
+        };
+
+        let modified =
+            Transaction::new(self.version.clone(), inputs, outputs, self.locktime.clone());
+        let mut serialized = modified.serialize_without_witness();
+        serialized.extend(sighash_type.to_le_bytes());
+        BigUint::from_bytes_be(&hash256(&serialized))
+    }
+
+    // BIP143 signature hash of input `input_index` spending a segwit v0 output worth `amount`.
+    // `script_code` is signed byte-for-byte, OP_CODESEPARATORs and non-minimal pushes included
+    pub fn sig_hash_segwit_v0(
+        &self,
+        input_index: usize,
+        script_code: &[u8],
+        amount: u64,
+        sighash_type: u32,
+    ) -> anyhow::Result<BigUint> {
+        let input = self.transaction_inputs.get(input_index).ok_or_else(|| {
+            anyhow!(
+                "[Transaction::sig_hash_segwit_v0] no input at index {}",
+                input_index
+            )
+        })?;
+        let base_type = sighash_type & 0x1f;
+        let anyone_can_pay = sighash_type & SIGHASH_ANYONECANPAY != 0;
+        let signs_all_outputs = base_type != SIGHASH_NONE && base_type != SIGHASH_SINGLE;
+
+        let hash_prevouts = match anyone_can_pay {
+            false => hash256(&self.concat_inputs(|input| input.outpoint())),
+            true => vec![0; 32],
+        };
+        let hash_sequence = match !anyone_can_pay && signs_all_outputs {
+            true => hash256(&self.concat_inputs(|input| input.sequence.to_vec())),
+            false => vec![0; 32],
+        };
+        let hash_outputs = match self.transaction_outputs.get(input_index) {
+            _ if signs_all_outputs => hash256(&self.concat_outputs()),
+            Some(output) if base_type == SIGHASH_SINGLE => hash256(&output.serialize()),
+            _ => vec![0; 32],
+        };
+
+        let mut preimage = self.version.serialize();
+        preimage.extend(hash_prevouts);
+        preimage.extend(hash_sequence);
+        preimage.extend(input.outpoint());
+        preimage.extend(Varint::new(script_code.len() as u64).serialize());
+        preimage.extend(script_code);
+        preimage.extend(amount.to_le_bytes());
+        preimage.extend(input.sequence);
+        preimage.extend(hash_outputs);
+        preimage.extend(self.locktime.serialize());
+        preimage.extend(sighash_type.to_le_bytes());
+        Ok(BigUint::from_bytes_be(&hash256(&preimage)))
+    }
+
+    // BIP341 signature hash of a key path spend without annex; `prevouts` are the
+    // outputs spent by every input, in input order
+    pub fn sig_hash_taproot(
+        &self,
+        input_index: usize,
+        prevouts: &[TransactionOutput],
+        sighash_type: u32,
+    ) -> anyhow::Result<[u8; 32]> {
+        if !matches!(sighash_type, 0x00..=0x03 | 0x81..=0x83) {
+            bail!(
+                "[Transaction::sig_hash_taproot] invalid hash type {:#04x}",
+                sighash_type
+            );
+        }
+        if prevouts.len() != self.transaction_inputs.len() {
+            bail!(
+                "[Transaction::sig_hash_taproot] {} spent outputs given for {} inputs",
+                prevouts.len(),
+                self.transaction_inputs.len()
+            );
+        }
+        let input = self.transaction_inputs.get(input_index).ok_or_else(|| {
+            anyhow!(
+                "[Transaction::sig_hash_taproot] no input at index {}",
+                input_index
+            )
+        })?;
+        let base_type = sighash_type & 0x03;
+        let anyone_can_pay = sighash_type & SIGHASH_ANYONECANPAY != 0;
+        let sha256 = |data: &[u8]| Sha256::digest(data).to_vec();
+
+        // epoch 0
+        let mut msg = vec![0x00, sighash_type as u8];
+        msg.extend(self.version.serialize());
+        msg.extend(self.locktime.serialize());
+        if !anyone_can_pay {
+            msg.extend(sha256(&self.concat_inputs(|input| input.outpoint())));
+            let amounts: Vec<u8> = prevouts.iter().flat_map(|out| out.amount).collect();
+            msg.extend(sha256(&amounts));
+            let script_pubkeys: Vec<u8> = prevouts
+                .iter()
+                .flat_map(|out| out.serialize().split_off(8))
+                .collect();
+            msg.extend(sha256(&script_pubkeys));
+            msg.extend(sha256(&self.concat_inputs(|input| input.sequence.to_vec())));
+        }
+        if base_type != SIGHASH_NONE && base_type != SIGHASH_SINGLE {
+            msg.extend(sha256(&self.concat_outputs()));
+        }
+        // spend type: key path, no annex
+        msg.push(0x00);
+        if anyone_can_pay {
+            msg.extend(input.outpoint());
+            msg.extend(prevouts[input_index].serialize());
+            msg.extend(input.sequence);
+        } else {
+            msg.extend((input_index as u32).to_le_bytes());
+        }
+        if base_type == SIGHASH_SINGLE {
+            let output = self.transaction_outputs.get(input_index).ok_or_else(|| {
+                anyhow!("[Transaction::sig_hash_taproot] SIGHASH_SINGLE without a matching output")
+            })?;
+            msg.extend(sha256(&output.serialize()));
+        }
+        Ok(tagged_hash("TapSighash", &msg))
+    }
+
+    fn concat_inputs(&self, field: impl Fn(&TransactionInput) -> Vec<u8>) -> Vec<u8> {
+        self.transaction_inputs.iter().flat_map(field).collect()
+    }
+
+    fn concat_outputs(&self) -> Vec<u8> {
+        self.transaction_outputs
+            .iter()
+            .flat_map(|output| output.serialize())
+            .collect()
+    }
+
+    // Signs input `input_index` spending a P2PKH `prev_script_pubkey`, replacing its scriptSig
+    // with <DER signature + hash type> <SEC pubkey>
+    pub fn sign_input(
+        &mut self,
+        input_index: usize,
+        key: &Key,
+        prev_script_pubkey: &Script,
+        sighash_type: u32,
+    ) -> anyhow::Result<()> {
+        if input_index >= self.transaction_inputs.len() {
+            bail!(
+                "[Transaction::sign_input] no input at index {}",
+                input_index
+            );
+        }
+        let hash = prev_script_pubkey
+            .p2pkh_hash()
+            .ok_or_else(|| anyhow!("[Transaction::sign_input] scriptPubKey is not P2PKH"))?;
+        let sec = [
+            key.point.serialize_sec_compressed()?,
+            key.point.serialize_sec()?,
+        ]
+        .into_iter()
+        .find(|sec| hash160(sec) == hash)
+        .ok_or_else(|| anyhow!("[Transaction::sign_input] key doesn't match scriptPubKey"))?;
+
+        let z = self.sig_hash(input_index, prev_script_pubkey.as_bytes(), sighash_type);
+        let mut signature = key.sign(z).der();
+        signature.push(sighash_type as u8);
+
+        let script_signature = Script::new(vec![Command::Data(signature), Command::Data(sec)]);
+        self.transaction_inputs[input_index].script_signature = script_signature.raw_serialize();
+        Ok(())
+    }
+
+    // Signs input `input_index` spending the P2WPKH output `prev_output`, replacing its witness
+    // with <DER signature + hash type> <compressed SEC pubkey>
+    pub fn sign_input_p2wpkh(
+        &mut self,
+        input_index: usize,
+        key: &Key,
+        prev_output: &TransactionOutput,
+        sighash_type: u32,
+    ) -> anyhow::Result<()> {
+        if input_index >= self.transaction_inputs.len() {
+            bail!(
+                "[Transaction::sign_input_p2wpkh] no input at index {}",
+                input_index
+            );
+        }
+        let sec = key.point.serialize_sec_compressed()?;
+        let hash = hash160(&sec);
+        if prev_output.script_pubkey()?.witness_version_and_program() != Some((0, &hash[..])) {
+            bail!("[Transaction::sign_input_p2wpkh] key doesn't match scriptPubKey");
+        }
+
+        // BIP143: the script code of P2WPKH is the matching P2PKH script
+        let script_code = Script::p2pkh(&hash);
+        let z = self.sig_hash_segwit_v0(
+            input_index,
+            script_code.as_bytes(),
+            prev_output.amount(),
+            sighash_type,
+        )?;
+        let mut signature = key.sign(z).der();
+        signature.push(sighash_type as u8);
+        self.transaction_inputs[input_index].witness = Witness::new(vec![signature, sec]);
+        Ok(())
+    }
+
+    // Key path spend of a P2TR output without a script tree (BIP86) for input `input_index`,
+    // with `prevouts` the outputs spent by every input. SIGHASH_DEFAULT gives a 64-byte
+    // signature, other hash types are appended as a 65th byte.
+    pub fn sign_input_p2tr(
+        &mut self,
+        input_index: usize,
+        key: &Key,
+        prevouts: &[TransactionOutput],
+        sighash_type: u32,
+    ) -> anyhow::Result<()> {
+        let prev_output = prevouts.get(input_index).ok_or_else(|| {
+            anyhow!(
+                "[Transaction::sign_input_p2tr] no spent output for input {}",
+                input_index
+            )
+        })?;
+        let tweaked = key.taproot_tweak()?;
+        let output_key = tweaked.point.x_only()?;
+        if prev_output.script_pubkey()?.witness_version_and_program() != Some((1, &output_key[..]))
+        {
+            bail!("[Transaction::sign_input_p2tr] key doesn't match scriptPubKey");
+        }
+
+        let sighash = self.sig_hash_taproot(input_index, prevouts, sighash_type)?;
+        let mut aux_rand = [0_u8; 32];
+        rand::thread_rng().fill(&mut aux_rand);
+        let mut signature = tweaked
+            .sign_schnorr(&sighash, &aux_rand)?
+            .to_bytes()
+            .to_vec();
+        if sighash_type != SIGHASH_DEFAULT {
+            signature.push(sighash_type as u8);
+        }
+        self.transaction_inputs[input_index].witness = Witness::new(vec![signature]);
+        Ok(())
+    }
+
+    // Runs every input's scriptSig and witness against the output it spends and checks
+    // no value is created
+    pub fn verify<U: UtxoLookup>(&self, utxo_lookup: &U) -> anyhow::Result<()> {
+        let mut input_sum: u64 = 0;
+        let mut prevouts = Vec::with_capacity(self.transaction_inputs.len());
+        for (idx, input) in self.transaction_inputs.iter().enumerate() {
+            let prev_output = utxo_lookup
+                .lookup(&input.previous_tx_id, input.previous_tx_idx())
+                .ok_or_else(|| {
+                    anyhow!(
+                        "[Transaction::verify] input {}: spent output not found",
+                        idx
+                    )
+                })?;
+            input_sum = input_sum
+                .checked_add(prev_output.amount())
+                .ok_or_else(|| anyhow!("[Transaction::verify] input amount overflow"))?;
+            prevouts.push(prev_output);
+        }
+
+        // taproot signatures commit to every spent output, so all of them are looked up first
+        for (idx, (input, prev_output)) in self.transaction_inputs.iter().zip(&prevouts).enumerate()
+        {
+            let script_sig = input.script_signature()?;
+            let script_pubkey = prev_output.script_pubkey()?;
+            let checker = TransactionSignatureChecker::with_prevouts(self, idx, &prevouts);
+            if let Err(e) = verify_script_with_witness(
+                &script_sig,
+                &script_pubkey,
+                input.witness.items(),
+                &checker,
+            ) {
+                bail!("[Transaction::verify] input {}: {}", idx, e);
+            }
+        }
+
+        let mut output_sum: u64 = 0;
+        for output in &self.transaction_outputs {
+            output_sum = output_sum
+                .checked_add(output.amount())
+                .ok_or_else(|| anyhow!("[Transaction::verify] output amount overflow"))?;
+        }
+        if input_sum < output_sum {
+            bail!(
+                "[Transaction::verify] outputs ({}) exceed inputs ({})",
+                output_sum,
+                input_sum
+            );
+        }
+        Ok(())
+    }
+
+    pub fn version(&self) -> &TransactionVersion {
+        &self.version
+    }
+
+    pub fn inputs(&self) -> &[TransactionInput] {
+        &self.transaction_inputs
+    }
+
+    // a slice, so the input count stays in sync with its varint
+    pub fn inputs_mut(&mut self) -> &mut [TransactionInput] {
+        &mut self.transaction_inputs
+    }
+
+    pub fn outputs(&self) -> &[TransactionOutput] {
+        &self.transaction_outputs
+    }
+
+    pub fn locktime(&self) -> &TransactionLocktime {
+        &self.locktime
+    }
+}
+
+impl Encodable for Transaction {
+    fn encode<W: Write>(&self, writer: &mut W) -> Result<usize, CodecError> {
+        self.encode_with_witness(writer, true)
+    }
+}
+
+impl Decodable for Transaction {
+    fn decode<R: Read>(reader: &mut R) -> Result<Self, CodecError> {
+        // version (4)
+        let version = TransactionVersion::decode(reader)?;
+
+        // inputs (varint + n * TransactionInput), or the segwit marker and flag first
+        let mut transaction_inputs_varint = Varint::decode(reader)?;
+        let segwit = transaction_inputs_varint.value() == 0;
+        if segwit {
+            let [flag] = read_array(reader)?;
+            if flag != 0x01 {
+                return Err(CodecError::InvalidSegwitFlag { flag });
+            }
+            transaction_inputs_varint = Varint::decode(reader)?;
+        }
+        let mut transaction_inputs = Vec::new();
+        for _ in 0..transaction_inputs_varint.value() {
+            transaction_inputs.push(TransactionInput::decode(reader)?);
+        }
+
+        // outputs (varint + n * TransactionOutput)
+        let transaction_outputs_varint = Varint::decode(reader)?;
+        let mut transaction_outputs = Vec::new();
+        for _ in 0..transaction_outputs_varint.value() {
+            transaction_outputs.push(TransactionOutput::decode(reader)?);
+        }
+
+        // witnesses (one per input); the marker must not be used without any, Bitcoin Core
+        // rejects that as a superfluous witness record
+        if segwit {
+            for input in &mut transaction_inputs {
+                input.witness = Witness::decode(reader)?;
+            }
+            if transaction_inputs
+                .iter()
+                .all(|input| input.witness.is_empty())
+            {
+                return Err(CodecError::SuperfluousWitness);
+            }
+        }
+
+        // locktime (4)
+        let locktime = TransactionLocktime::decode(reader)?;
+
+        Ok(Transaction {
+            version,
+            transaction_inputs_varint,
+            transaction_inputs,
+            transaction_outputs_varint,
+            transaction_outputs,
+            locktime,
+        })
     }
 }
 
@@ -142,13 +833,46 @@ pub fn u32_to_le_array(value: u32) -> [u8; 4] {
     value.to_le_bytes()
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Varint(Vec<u8>);
 
 impl Varint {
     pub fn new(value: u64) -> Self {
         Varint(varint_representation(value))
     }
+
+    pub fn value(&self) -> u64 {
+        let mut buffer = [0_u8; 8];
+        match self.0[0] {
+            0xfd..=0xff => {
+                buffer[..self.0.len() - 1].copy_from_slice(&self.0[1..]);
+                u64::from_le_bytes(buffer)
+            }
+            v => v as u64,
+        }
+    }
+}
+
+impl Encodable for Varint {
+    fn encode<W: Write>(&self, writer: &mut W) -> Result<usize, CodecError> {
+        write_all(writer, &self.0)
+    }
+}
+
+impl Decodable for Varint {
+    fn decode<R: Read>(reader: &mut R) -> Result<Self, CodecError> {
+        let [prefix] = read_array(reader)?;
+        let (value, min) = match prefix {
+            0xfd => (u16::from_le_bytes(read_array(reader)?) as u64, 0xfd),
+            0xfe => (u32::from_le_bytes(read_array(reader)?) as u64, 1 << 16),
+            0xff => (u64::from_le_bytes(read_array(reader)?), 1 << 32),
+            v => return Ok(Varint(vec![v])),
+        };
+        if value < min {
+            return Err(CodecError::NonCanonicalVarint { prefix, value });
+        }
+        Ok(Varint::new(value))
+    }
 }
 
 pub fn varint_representation(value: u64) -> Vec<u8> {
@@ -173,9 +897,26 @@ pub fn varint_representation(value: u64) -> Vec<u8> {
 
 #[cfg(test)]
 mod tests {
-    use std::io::{BufReader, Read};
+    use std::{
+        collections::HashMap,
+        io::{BufReader, Read},
+    };
+
+    use num_bigint_dig::BigUint;
+    use num_traits::{Num, One};
 
-    use super::{varint_representation, Transaction, TransactionVersion};
+    use crate::libs::{
+        codec::{CodecError, Decodable, Encodable},
+        ecc::hash160,
+        key::Key,
+        script::Script,
+    };
+
+    use super::{
+        varint_representation, Transaction, TransactionInput, TransactionLocktime,
+        TransactionOutput, TransactionVersion, Varint, Witness, SIGHASH_ALL, SIGHASH_ANYONECANPAY,
+        SIGHASH_DEFAULT, SIGHASH_NONE, SIGHASH_SINGLE,
+    };
 
     const TX1: &str = "010000000456919960ac691763688d3d3bcea9ad6ecaf875df5339e\
     148a1fc61c6ed7a069e010000006a47304402204585bcdef85e6b1c6af5c2669d4830ff86e42dd\
@@ -196,6 +937,16 @@ mod tests {
     b6dbf67d4750b0a56244948a87988ac005a6202000000001976a9143c82d7df364eb6c75be8c80\
     df2b3eda8db57397088ac46430600";
 
+    const TX2: &str = "0100000001813f79011acb80925dfe69b3def355fe914bd1d96a3f5f71bf8303c6a\
+    989c7d1000000006b483045022100ed81ff192e75a3fd2304004dcadb746fa5e24c5031ccfcf21320b0277457\
+    c98f02207a986d955c6e0cb35d446a89d3f56100f4d7f67801c31967743a9c8e10615bed01210349fc4e631e36\
+    24a545de3f89f5d8684c7b8138bd94bdd531d2e213bf016b278afeffffff02a135ef01000000001976a914bc3b\
+    654dca7e56b04dca18f2566cdaf02e8d9ada88ac99c39800000000001976a9141c4bc762dd5423e332166702cb\
+    75f40df79fea1288ac19430600";
+
+    // scriptPubKey of the output spent by TX2's only input
+    const TX2_PREV_SCRIPT_PUBKEY: &str = "76a914a802fc56c704ce87c42d7c92eb75e7896bdc41ae88ac";
+
     #[test]
     fn tx_version() {
         let v1 = TransactionVersion::new(1);
@@ -218,6 +969,35 @@ mod tests {
         );
     }
 
+    #[test]
+    fn varint_decode_test() {
+        for value in [0, 100, 252, 253, 255, 555, 70015, 18005558675309, u64::MAX] {
+            let encoded = varint_representation(value);
+            let decoded = Varint::decode(&mut encoded.as_slice()).unwrap();
+            assert_eq!(decoded.value(), value);
+            assert_eq!(decoded.serialize(), encoded);
+        }
+    }
+
+    #[test]
+    fn varint_non_canonical_test() {
+        let non_canonical: [&[u8]; 3] = [
+            &[0xfd, 0x64, 0x00],
+            &[0xfe, 0xff, 0xff, 0x00, 0x00],
+            &[0xff, 0x7f, 0x11, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00],
+        ];
+        for mut bytes in non_canonical {
+            assert!(matches!(
+                Varint::decode(&mut bytes),
+                Err(CodecError::NonCanonicalVarint { .. })
+            ));
+        }
+        assert!(matches!(
+            Varint::decode(&mut [0xfd_u8, 0x64].as_slice()),
+            Err(CodecError::Io(_))
+        ));
+    }
+
     #[test]
     fn bufreader_test() {
         let text = vec![0x21, 0x20, 0x19, 0x18, 0x17_u8];
@@ -241,8 +1021,433 @@ mod tests {
         let hex = hex::decode(TX1).unwrap();
         let hex = hex.as_slice();
         let mut stream = BufReader::new(hex);
-        let v = Transaction::parse_version(&mut stream);
-        eprintln!("{:?}", v.version);
+        let v = TransactionVersion::decode(&mut stream).unwrap();
         assert_eq!(v.to_u32(), 1_u32);
     }
+
+    #[test]
+    fn parse_transaction() {
+        let hex = hex::decode(TX1).unwrap();
+        let tx = Transaction::parse(&hex).unwrap();
+        assert_eq!(tx.version().to_u32(), 1);
+
+        assert_eq!(tx.inputs().len(), 4);
+        let first = &tx.inputs()[0];
+        assert_eq!(
+            hex::encode(first.previous_tx_id),
+            "56919960ac691763688d3d3bcea9ad6ecaf875df5339e148a1fc61c6ed7a069e"
+        );
+        assert_eq!(first.previous_tx_idx(), 1);
+        assert_eq!(first.script_signature.len(), 0x6a);
+        assert_eq!(first.sequence(), 0xfffffffe);
+
+        assert_eq!(tx.outputs().len(), 2);
+        assert_eq!(tx.outputs()[0].amount(), 1000273);
+        assert_eq!(
+            hex::encode(&tx.outputs()[0].script_pubkey),
+            "76a914ab0c0b2e98b1ab6dbf67d4750b0a56244948a87988ac"
+        );
+        assert_eq!(tx.outputs()[1].amount(), 40000000);
+
+        assert_eq!(tx.locktime().to_u32(), 410438);
+
+        assert_eq!(
+            first.script_signature().unwrap().to_string(),
+            "304402204585bcdef85e6b1c6af5c2669d4830ff86e42dd205c0e089bc2a821657e951c002201024a10366077f87d6bce1f7100ad8cfa8a064b39d4e8fe4ea13a7b71aa8180f01 \
+            02f0da57e85eec2934a82a585ea337ce2f4998b50ae699dd79f5880e253dafafb7"
+        );
+        assert_eq!(
+            tx.outputs()[1].script_pubkey().unwrap().to_string(),
+            "OP_DUP OP_HASH160 3c82d7df364eb6c75be8c80df2b3eda8db573970 OP_EQUALVERIFY OP_CHECKSIG"
+        );
+    }
+
+    #[test]
+    fn serialize_roundtrip() {
+        let hex = hex::decode(TX1).unwrap();
+        let tx = Transaction::parse(&hex).unwrap();
+        assert_eq!(tx.serialize(), hex);
+        assert_eq!(
+            tx.txid(),
+            "ee51510d7bbabe28052038d1deb10c03ec74f06a79e21913c6fcf48d56217c87"
+        );
+    }
+
+    #[test]
+    fn serialize_roundtrip_mainnet() {
+        // mainnet block 170, first bitcoin transaction between two people
+        let raw = "0100000001c997a5e56e104102fa209c6a852dd90660a20b2d9c352423edce25857fcd37040\
+        00000004847304402204e45e16932b8af514961a1d3a1a25fdf3f4f7732e9d624c6c61548ab5fb8cd41022\
+        0181522ec8eca07de4860a4acdd12909d831cc56cbbac4622082221a8768d1d0901ffffffff0200ca9a3b\
+        00000000434104ae1a62fe09c5f51b13905f07f06b99a2f7159b2225f374cd378d71302fa28414e7aab37\
+        397f554a7df5f142c21c1b7303b8a0626f1baded5c72a704f7e6cd84cac00286bee0000000043410411db\
+        93e1dcdb8a016b49840f8c53bc1eb68a382e97b1482ecad7b148a6909a5cb2e0eaddfb84ccf9744464f82\
+        e160bfa9b8b64f9d4c03f999b8643f656b412a3ac00000000";
+        let hex = hex::decode(raw).unwrap();
+        let tx = Transaction::parse(&hex).unwrap();
+        assert_eq!(tx.serialize(), hex);
+        assert_eq!(
+            tx.txid(),
+            "f4184fc596403b9d638783cf57adfe4c75c605f6356fbc91338530e9831e9e16"
+        );
+    }
+
+    #[test]
+    fn parse_truncated_transaction() {
+        let hex = hex::decode(TX1).unwrap();
+        assert!(Transaction::parse(&hex[..hex.len() - 1]).is_err());
+        assert!(Transaction::parse(&hex[..100]).is_err());
+    }
+
+    #[test]
+    fn sig_hash_all() {
+        let tx = Transaction::parse(&hex::decode(TX2).unwrap()).unwrap();
+        let script_code = hex::decode(TX2_PREV_SCRIPT_PUBKEY).unwrap();
+        assert_eq!(
+            tx.sig_hash(0, &script_code, SIGHASH_ALL),
+            BigUint::from_str_radix(
+                "27e0c5994dec7824e56dec6b2fcb342eb7cdb0d0957c2fce9882f715e85d81a6",
+                16
+            )
+            .unwrap()
+        );
+    }
+
+    #[test]
+    fn sig_hash_raw_script_code() {
+        let tx = Transaction::parse(&hex::decode(TX2).unwrap()).unwrap();
+        // the pushed hash is all 0xab, the OP_CODESEPARATOR byte
+        let hash = "ab".repeat(20);
+        let minimal = hex::decode(format!("76a914{hash}88ac")).unwrap();
+        let pushdata1 = hex::decode(format!("76a94c14{hash}88ac")).unwrap();
+        let separated = hex::decode(format!("ab76a94c14{hash}ab88acab")).unwrap();
+
+        // a non-minimal push is signed as encoded
+        let z = tx.sig_hash(0, &pushdata1, SIGHASH_ALL);
+        assert_ne!(z, tx.sig_hash(0, &minimal, SIGHASH_ALL));
+        // separators are dropped, the same byte inside a push is not
+        assert_eq!(z, tx.sig_hash(0, &separated, SIGHASH_ALL));
+    }
+
+    #[test]
+    fn sig_hash_flags() {
+        let tx = Transaction::parse(&hex::decode(TX1).unwrap()).unwrap();
+        let script_code = hex::decode(TX2_PREV_SCRIPT_PUBKEY).unwrap();
+
+        let mut other_outputs = tx.clone();
+        other_outputs.transaction_outputs[1] = TransactionOutput::new(1, Script::default());
+        let mut other_inputs = tx.clone();
+        other_inputs.transaction_inputs[3].sequence = [0; 4];
+        other_inputs.transaction_inputs[3].previous_tx_idx = [9; 4];
+
+        for flag in [SIGHASH_ALL, SIGHASH_NONE, SIGHASH_SINGLE] {
+            let z = tx.sig_hash(0, &script_code, flag);
+            assert_ne!(z, tx.sig_hash(1, &script_code, flag));
+            assert_ne!(z, tx.sig_hash(0, &script_code, flag | SIGHASH_ANYONECANPAY));
+            // outputs not covered by the flag can change freely
+            assert_eq!(
+                z == other_outputs.sig_hash(0, &script_code, flag),
+                flag != SIGHASH_ALL
+            );
+            // other inputs' outpoints are committed unless ANYONECANPAY is set
+            assert_ne!(z, other_inputs.sig_hash(0, &script_code, flag));
+            assert_eq!(
+                tx.sig_hash(0, &script_code, flag | SIGHASH_ANYONECANPAY),
+                other_inputs.sig_hash(0, &script_code, flag | SIGHASH_ANYONECANPAY)
+            );
+        }
+    }
+
+    #[test]
+    fn sig_hash_single_bug() {
+        // TX1 has 4 inputs but only 2 outputs
+        let tx = Transaction::parse(&hex::decode(TX1).unwrap()).unwrap();
+        let script_code = hex::decode(TX2_PREV_SCRIPT_PUBKEY).unwrap();
+        assert_eq!(tx.sig_hash(2, &script_code, SIGHASH_SINGLE), BigUint::one());
+        assert_eq!(
+            tx.sig_hash(3, &script_code, SIGHASH_SINGLE | SIGHASH_ANYONECANPAY),
+            BigUint::one()
+        );
+        assert_ne!(tx.sig_hash(1, &script_code, SIGHASH_SINGLE), BigUint::one());
+        assert_eq!(tx.sig_hash(4, &script_code, SIGHASH_ALL), BigUint::one());
+    }
+
+    #[test]
+    fn sign_and_verify() {
+        let key = Key::new(BigUint::from(8675309_u32));
+        let prev_script_pubkey = Script::p2pkh(&hash160(&key.point.serialize_sec().unwrap()));
+        let prev_tx_id = [0x11_u8; 32];
+
+        let mut utxos = HashMap::new();
+        utxos.insert(
+            (prev_tx_id, 0_u32),
+            TransactionOutput::new(50_000, prev_script_pubkey.clone()),
+        );
+
+        let build = |amount: u64| {
+            Transaction::new(
+                TransactionVersion::new(1),
+                vec![TransactionInput::new(
+                    prev_tx_id,
+                    0_u32.to_le_bytes(),
+                    Script::default(),
+                    [0xff; 4],
+                )],
+                vec![TransactionOutput::new(amount, Script::p2pkh(&[0x22; 20]))],
+                TransactionLocktime::new(0),
+            )
+        };
+
+        let mut tx = build(40_000);
+        assert!(tx.verify(&utxos).is_err());
+        tx.sign_input(0, &key, &prev_script_pubkey, SIGHASH_ALL)
+            .unwrap();
+        tx.verify(&utxos).unwrap();
+
+        // signature no longer matches once an output changes
+        let mut tampered = tx.clone();
+        tampered.transaction_outputs[0] =
+            TransactionOutput::new(45_000, Script::p2pkh(&[0x22; 20]));
+        assert!(tampered.verify(&utxos).is_err());
+
+        // validly signed but spends more than its inputs
+        let mut overspend = build(60_000);
+        overspend
+            .sign_input(0, &key, &prev_script_pubkey, SIGHASH_ALL)
+            .unwrap();
+        let err = overspend.verify(&utxos).unwrap_err();
+        assert!(err.to_string().contains("exceed inputs"));
+
+        assert!(tx.verify(&HashMap::new()).is_err());
+        assert!(tx
+            .sign_input(
+                0,
+                &Key::new(BigUint::from(1_u32)),
+                &prev_script_pubkey,
+                SIGHASH_ALL
+            )
+            .is_err());
+    }
+
+    #[test]
+    fn verify_mainnet_p2pkh() {
+        // TX2 spends a compressed-key P2PKH output worth 0.42505594 BTC
+        let tx = Transaction::parse(&hex::decode(TX2).unwrap()).unwrap();
+        let prev_script_pubkey =
+            Script::parse_raw(&hex::decode(TX2_PREV_SCRIPT_PUBKEY).unwrap()).unwrap();
+        let input = &tx.inputs()[0];
+        let mut utxos = HashMap::new();
+        utxos.insert(
+            (*input.previous_tx_id(), input.previous_tx_idx()),
+            TransactionOutput::new(42505594, prev_script_pubkey),
+        );
+        tx.verify(&utxos).unwrap();
+    }
+
+    #[test]
+    fn sig_hash_segwit_v0() {
+        // BIP143 native P2WPKH example, second input
+        let unsigned = "0100000002fff7f7881a8099afa6940d42d1e7f6362bec38171ea3edf433541db4e4\
+        ad969f0000000000eeffffffef51e1b804cc89d182d279655c3aa89e815b1b309fe287d9b2b55d57b90ec68\
+        a0100000000ffffffff02202cb206000000001976a9148280b37df378db99f66f85c95a783a76ac7a6d598\
+        8ac9093510d000000001976a9143bde42dbee7e4dbe6a21b2d50ce2f0167faa815988ac11000000";
+        let tx = Transaction::parse(&hex::decode(unsigned).unwrap()).unwrap();
+        let script_code =
+            Script::p2pkh(&hex::decode("1d0f172a0ecb48aee1be1f2687d2963ae33f71a1").unwrap())
+                .raw_serialize();
+        assert_eq!(
+            tx.sig_hash_segwit_v0(1, &script_code, 600000000, SIGHASH_ALL)
+                .unwrap(),
+            BigUint::from_str_radix(
+                "c37af31116d1b27caf68aae9e3ac82f1477929014d5b917657d0eb49478cb670",
+                16
+            )
+            .unwrap()
+        );
+        assert!(tx
+            .sig_hash_segwit_v0(2, &script_code, 600000000, SIGHASH_ALL)
+            .is_err());
+    }
+
+    #[test]
+    fn sig_hash_segwit_v0_code_separator() {
+        // BIP143 native P2WSH example: <pubkey1> OP_CHECKSIGVERIFY OP_CODESEPARATOR
+        // <pubkey2> OP_CHECKSIG, the separator stays in the first signature's script code
+        let unsigned = "0100000002fe3dc9208094f3ffd12645477b3dc56f60ec4fa8e6f5d67c565d1c6b92\
+        16b36e0000000000ffffffff0815cf020f013ed6cf91d29f4202e8a58726b1ac6c79da47c23d1bee0a6925\
+        f80000000000ffffffff0100f2052a010000001976a914a30741f8145e5acadf23f751864167f32e0963f7\
+        88ac00000000";
+        let tx = Transaction::parse(&hex::decode(unsigned).unwrap()).unwrap();
+        let witness_script = hex::decode(
+            "21026dccc749adc2a9d0d89497ac511f760f45c47dc5ed9cf352a58ac706453880aeadab210255a9\
+            626aebf5e29c0e6538428ba0d1dcf6ca98ffdf086aa8ced5e0d0215ea465ac",
+        )
+        .unwrap();
+        let cases = [
+            (
+                &witness_script[..],
+                "82dde6e4f1e94d02c2b7ad03d2115d691f48d064e9d52f58194a6637e4194391",
+            ),
+            (
+                &witness_script[36..],
+                "fef7bd749cce710c5c052bd796df1af0d935e59cea63736268bcbe2d2134fc47",
+            ),
+        ];
+        for (script_code, expected) in cases {
+            assert_eq!(
+                tx.sig_hash_segwit_v0(1, script_code, 4900000000, SIGHASH_SINGLE)
+                    .unwrap(),
+                BigUint::from_str_radix(expected, 16).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn segwit_serialize_roundtrip() {
+        let mut tx = Transaction::parse(&hex::decode(TX2).unwrap()).unwrap();
+        let txid = tx.txid();
+        tx.transaction_inputs[0].set_witness(Witness::new(vec![vec![0x01; 71], vec![0x02; 33]]));
+        assert!(tx.has_witness());
+
+        let raw = tx.serialize();
+        assert_eq!(&raw[4..6], &[0x00, 0x01]);
+        assert_eq!(
+            raw.len(),
+            hex::decode(TX2).unwrap().len() + 2 + 1 + 1 + 71 + 1 + 33
+        );
+        let parsed = Transaction::parse(&raw).unwrap();
+        assert_eq!(parsed, tx);
+        // witnesses don't change the txid
+        assert_eq!(parsed.txid(), txid);
+        assert_eq!(
+            parsed.serialize_without_witness(),
+            hex::decode(TX2).unwrap()
+        );
+
+        let mut bad_flag = raw.clone();
+        bad_flag[5] = 0x02;
+        assert!(matches!(
+            Transaction::decode(&mut bad_flag.as_slice()),
+            Err(CodecError::InvalidSegwitFlag { flag: 0x02 })
+        ));
+
+        // marker and flag followed by an empty witness for every input
+        tx.transaction_inputs[0].set_witness(Witness::default());
+        let mut superfluous = tx.serialize_without_witness();
+        superfluous.splice(4..4, [0x00, 0x01]);
+        let locktime_at = superfluous.len() - 4;
+        superfluous.splice(locktime_at..locktime_at, vec![0x00; tx.inputs().len()]);
+        assert!(matches!(
+            Transaction::decode(&mut superfluous.as_slice()),
+            Err(CodecError::SuperfluousWitness)
+        ));
+    }
+
+    fn spend(prev_tx_id: [u8; 32], amount: u64) -> Transaction {
+        Transaction::new(
+            TransactionVersion::new(2),
+            vec![TransactionInput::new(
+                prev_tx_id,
+                0_u32.to_le_bytes(),
+                Script::default(),
+                [0xff; 4],
+            )],
+            vec![TransactionOutput::new(amount, Script::p2pkh(&[0x22; 20]))],
+            TransactionLocktime::new(0),
+        )
+    }
+
+    #[test]
+    fn sign_and_verify_p2wpkh() {
+        let key = Key::new(BigUint::from(8675309_u32));
+        let sec = key.point.serialize_sec_compressed().unwrap();
+        let prev_output =
+            TransactionOutput::new(50_000, Script::witness_program(0, &hash160(&sec)).unwrap());
+        let prev_tx_id = [0x33_u8; 32];
+        let mut utxos = HashMap::new();
+        utxos.insert((prev_tx_id, 0_u32), prev_output.clone());
+
+        let mut tx = spend(prev_tx_id, 40_000);
+        assert!(tx.verify(&utxos).is_err());
+        tx.sign_input_p2wpkh(0, &key, &prev_output, SIGHASH_ALL)
+            .unwrap();
+        assert!(tx.inputs()[0].script_signature.is_empty());
+        tx.verify(&utxos).unwrap();
+        let parsed = Transaction::parse(&tx.serialize()).unwrap();
+        parsed.verify(&utxos).unwrap();
+
+        // BIP143 commits to the spent amount
+        let mut other_amount = HashMap::new();
+        other_amount.insert(
+            (prev_tx_id, 0_u32),
+            TransactionOutput::new(50_001, prev_output.script_pubkey().unwrap()),
+        );
+        assert!(tx.verify(&other_amount).is_err());
+
+        let mut tampered = tx.clone();
+        tampered.transaction_outputs[0] =
+            TransactionOutput::new(45_000, Script::p2pkh(&[0x22; 20]));
+        assert!(tampered.verify(&utxos).is_err());
+
+        assert!(tx
+            .sign_input_p2wpkh(
+                0,
+                &Key::new(BigUint::from(1_u32)),
+                &prev_output,
+                SIGHASH_ALL
+            )
+            .is_err());
+    }
+
+    #[test]
+    fn sign_and_verify_p2tr() {
+        let key = Key::new(BigUint::from(8675309_u32));
+        let output_key = key.point.taproot_output_key().unwrap().x_only().unwrap();
+        let prev_output =
+            TransactionOutput::new(50_000, Script::witness_program(1, &output_key).unwrap());
+        let prev_tx_id = [0x44_u8; 32];
+        let mut utxos = HashMap::new();
+        utxos.insert((prev_tx_id, 0_u32), prev_output.clone());
+        let prevouts = vec![prev_output];
+
+        for sighash_type in [
+            SIGHASH_DEFAULT,
+            SIGHASH_ALL,
+            SIGHASH_NONE | SIGHASH_ANYONECANPAY,
+        ] {
+            let mut tx = spend(prev_tx_id, 40_000);
+            tx.sign_input_p2tr(0, &key, &prevouts, sighash_type)
+                .unwrap();
+            let signature = &tx.inputs()[0].witness().items()[0];
+            assert_eq!(
+                signature.len(),
+                if sighash_type == SIGHASH_DEFAULT {
+                    64
+                } else {
+                    65
+                }
+            );
+            tx.verify(&utxos).unwrap();
+
+            let mut tampered = tx.clone();
+            tampered.transaction_outputs[0] =
+                TransactionOutput::new(45_000, Script::p2pkh(&[0x22; 20]));
+            assert_eq!(
+                tampered.verify(&utxos).is_err(),
+                sighash_type & 0x03 != SIGHASH_NONE
+            );
+        }
+
+        let mut tx = spend(prev_tx_id, 40_000);
+        assert!(tx.sign_input_p2tr(0, &key, &prevouts, 0x04).is_err());
+        assert!(tx.sign_input_p2tr(0, &key, &[], SIGHASH_DEFAULT).is_err());
+        assert!(tx
+            .sign_input_p2tr(
+                0,
+                &Key::new(BigUint::from(1_u32)),
+                &prevouts,
+                SIGHASH_DEFAULT
+            )
+            .is_err());
+    }
 }